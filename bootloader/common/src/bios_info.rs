@@ -0,0 +1,246 @@
+//! The boot info handed to the kernel.
+//!
+//! Stage 1 fills it in at [`crate::BIOS_INFO`] and the kernel gets a pointer to it in `rdi`.
+//! It is written by 16 bit code and read by 64 bit code, so every field has a fixed size and
+//! there is no implicit padding. Addresses are `u64`s instead of pointers for the same reason.
+
+use core::mem::size_of;
+
+/// Value of [`BiosInfo::magic`]
+pub const BIOS_INFO_MAGIC: u64 = u64::from_le_bytes(*b"SPOSINFO");
+/// Version of the [`BiosInfo`] layout. New fields only get added at the end, along with a bump
+/// of this.
+pub const BIOS_INFO_VERSION: u32 = 2;
+
+/// Signature of the kernel entry point. The kernel is called with the System V calling
+/// convention, so the info pointer is in `rdi`.
+pub type KernelEntry = extern "C" fn(&'static BiosInfo) -> !;
+
+/// Info passed to the kernel
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BiosInfo {
+    /// Always [`BIOS_INFO_MAGIC`]
+    pub magic: u64,
+    /// [`BIOS_INFO_VERSION`] of the bootloader that wrote this
+    pub version: u32,
+    /// Size of this struct in bytes
+    pub size: u32,
+    pub memory_map: MemoryMapInfo,
+    pub framebuffer: FrameBufferInfo,
+    pub font: FontInfo,
+    /// BIOS drive number the bootloader was loaded from, 0x80 for the first hard disk
+    pub boot_drive: u8,
+    _reserved: [u8; 7],
+    /// Added in version 2
+    pub cmdline: CmdlineInfo,
+}
+
+/// Where the kernel command line from the boot configuration is
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdlineInfo {
+    /// Address of the first byte of the UTF-8 command line, which isn't nul terminated
+    pub address: u64,
+    /// Length in bytes
+    pub len: u64,
+}
+
+/// Where the E820 memory map was stored
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryMapInfo {
+    /// Address of the first [`MemoryMapEntry`]
+    pub address: u64,
+    /// Number of entries
+    pub count: u32,
+    /// Bytes between the start of two entries
+    pub entry_size: u32,
+}
+
+/// An entry of the E820 memory map, as returned by the BIOS
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryMapEntry {
+    pub base: u64,
+    pub length: u64,
+    pub region_type: u32,
+    /// ACPI 3.0 extended attributes
+    pub attributes: u32,
+}
+
+/// Information about the framebuffer to write to the screen
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FrameBufferInfo {
+    /// Physical address of the first pixel, 0 if there is no framebuffer. Identity mapped when
+    /// a 64 bit kernel starts.
+    pub address: u64,
+    /// Pixels per line
+    pub width: u32,
+    /// Number of lines
+    pub height: u32,
+    /// Bytes between the start of two lines
+    pub pitch: u32,
+    pub bits_per_pixel: u32,
+    pub format: PixelFormat,
+}
+
+/// Where each color is in a pixel, as a number of bits and the position of the lowest one
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PixelFormat {
+    pub red_size: u8,
+    pub red_shift: u8,
+    pub green_size: u8,
+    pub green_shift: u8,
+    pub blue_size: u8,
+    pub blue_shift: u8,
+    pub reserved_size: u8,
+    pub reserved_shift: u8,
+}
+
+impl PixelFormat {
+    /// The usual layout of direct color pixels of the given depth, for modes that don't report
+    /// their masks
+    pub fn rgb(bits_per_pixel: u8) -> Option<Self> {
+        let (red, green, blue) = match bits_per_pixel {
+            15 => (5, 5, 5),
+            16 => (5, 6, 5),
+            24 | 32 => (8, 8, 8),
+            _ => return None,
+        };
+        let used = red + green + blue;
+        Some(PixelFormat {
+            red_size: red,
+            red_shift: green + blue,
+            green_size: green,
+            green_shift: blue,
+            blue_size: blue,
+            blue_shift: 0,
+            reserved_size: bits_per_pixel - used,
+            reserved_shift: used,
+        })
+    }
+
+    /// Packs a color with 8 bits per channel into a pixel, dropping the low bits of narrower
+    /// channels
+    pub fn encode(&self, r: u8, g: u8, b: u8) -> u32 {
+        let channel =
+            |value: u8, size: u8, shift: u8| ((value as u32) >> 8u8.saturating_sub(size)) << shift;
+        channel(r, self.red_size, self.red_shift)
+            | channel(g, self.green_size, self.green_shift)
+            | channel(b, self.blue_size, self.blue_shift)
+    }
+}
+
+/// A bitmap font, one bit per pixel with each row padded to a whole byte
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FontInfo {
+    /// Address of the glyph for character 0, the rest follow
+    pub address: u64,
+    pub glyph_count: u32,
+    /// Width of a glyph in pixels
+    pub width: u16,
+    /// Height of a glyph in pixels
+    pub height: u16,
+}
+
+// Same size everywhere means the same layout everywhere, since nothing needs padding
+const _: () = assert!(size_of::<MemoryMapEntry>() == 24);
+const _: () = assert!(size_of::<MemoryMapInfo>() == 16);
+const _: () = assert!(size_of::<PixelFormat>() == 8);
+const _: () = assert!(size_of::<FrameBufferInfo>() == 32);
+const _: () = assert!(size_of::<FontInfo>() == 16);
+const _: () = assert!(size_of::<CmdlineInfo>() == 16);
+const _: () = assert!(size_of::<BiosInfo>() == 104);
+
+impl BiosInfo {
+    pub const fn new(
+        boot_drive: u8,
+        memory_map: MemoryMapInfo,
+        framebuffer: FrameBufferInfo,
+        font: FontInfo,
+        cmdline: CmdlineInfo,
+    ) -> Self {
+        BiosInfo {
+            magic: BIOS_INFO_MAGIC,
+            version: BIOS_INFO_VERSION,
+            size: size_of::<BiosInfo>() as u32,
+            memory_map,
+            framebuffer,
+            font,
+            boot_drive,
+            _reserved: [0; 7],
+            cmdline,
+        }
+    }
+
+    /// Checks this was written by a bootloader with the same layout
+    pub fn is_valid(&self) -> bool {
+        self.magic == BIOS_INFO_MAGIC
+            && self.version == BIOS_INFO_VERSION
+            && self.size as usize == size_of::<BiosInfo>()
+    }
+
+    /// The E820 memory map
+    ///
+    /// # Safety
+    ///
+    /// The memory map has to still be there, identity mapped
+    pub unsafe fn memory_map(&self) -> &'static [MemoryMapEntry] {
+        let address = self.memory_map.address as usize as *const MemoryMapEntry;
+        unsafe { core::slice::from_raw_parts(address, self.memory_map.count as usize) }
+    }
+
+    /// The font bitmap, `glyph_count` glyphs of `height` rows each
+    ///
+    /// # Safety
+    ///
+    /// The font has to still be there, identity mapped. The built-in and BIOS fonts live below
+    /// 1 MiB, but a font file is loaded to the first page after the initrd, or after the kernel
+    /// file if there is no initrd, so that memory has to be kept too.
+    pub unsafe fn font(&self) -> &'static [u8] {
+        let row_bytes = (self.font.width as usize).div_ceil(8);
+        let len = self.font.glyph_count as usize * self.font.height as usize * row_bytes;
+        let address = self.font.address as usize as *const u8;
+        unsafe { core::slice::from_raw_parts(address, len) }
+    }
+
+    /// The kernel command line, empty if it isn't valid UTF-8
+    ///
+    /// # Safety
+    ///
+    /// The command line has to still be there, identity mapped. It lives in bootloader memory
+    /// below 1 MiB.
+    pub unsafe fn cmdline(&self) -> &'static str {
+        let address = self.cmdline.address as usize as *const u8;
+        let bytes = unsafe { core::slice::from_raw_parts(address, self.cmdline.len as usize) };
+        core::str::from_utf8(bytes).unwrap_or("")
+    }
+}
+
+#[test]
+fn test_bios_info_layout() {
+    use core::mem::offset_of;
+    assert_eq!(offset_of!(BiosInfo, memory_map), 16);
+    assert_eq!(offset_of!(BiosInfo, framebuffer), 32);
+    assert_eq!(offset_of!(BiosInfo, font), 64);
+    assert_eq!(offset_of!(BiosInfo, boot_drive), 80);
+    assert_eq!(offset_of!(BiosInfo, cmdline), 88);
+    assert_eq!(offset_of!(FrameBufferInfo, format), 24);
+}
+
+#[test]
+fn test_pixel_format_encode() {
+    let format = PixelFormat::rgb(32).unwrap();
+    assert_eq!(format.encode(0x12, 0x34, 0x56), 0x123456);
+    assert_eq!(format.reserved_size, 8);
+    let format = PixelFormat::rgb(16).unwrap();
+    assert_eq!(format.encode(0xff, 0, 0), 0xf800);
+    assert_eq!(format.encode(0, 0xff, 0), 0x07e0);
+    let format = PixelFormat::rgb(15).unwrap();
+    assert_eq!(format.encode(0xff, 0xff, 0xff), 0x7fff);
+    assert_eq!(PixelFormat::rgb(8), None);
+}
@@ -0,0 +1,251 @@
+//! The boot configuration file read from the boot partition.
+//!
+//! The file is made of `key=value` lines. Blank lines and lines starting with `#` are ignored,
+//! as are keys the bootloader doesn't know about.
+//!
+//! `kernel`, `cmdline` and `initrd` before the first `entry` line make up the default boot entry.
+//! Every `entry` line starts another one, titled with its value, for the boot menu to offer.
+//! `timeout` and `font` apply to all of them wherever they are.
+//!
+//! ```text
+//! # spenceros boot configuration
+//! timeout=5
+//! font=TER16X32.PSF
+//! kernel=KERNEL.ELF
+//! cmdline=
+//! initrd=INITRD.IMG
+//!
+//! entry=Debug kernel
+//! kernel=DEBUG.ELF
+//! cmdline=debug
+//! ```
+
+/// Name of the configuration file in the root directory of the boot partition
+pub const CONFIG_FILE: &str = "BOOT.CFG";
+/// Kernel loaded when the configuration doesn't name one
+pub const DEFAULT_KERNEL: &str = "KERNEL.ELF";
+/// Name the image builder stores an initrd as
+pub const DEFAULT_INITRD: &str = "INITRD.IMG";
+/// Largest configuration file the bootloader reads, anything after this is ignored
+pub const MAX_CONFIG_SIZE: usize = 0x400;
+/// `font` value for the font in the VGA BIOS
+pub const BIOS_FONT: &str = "bios";
+/// Title of the entry made of the keys before the first `entry` line
+pub const DEFAULT_TITLE: &str = "spenceros";
+/// Most boot entries the bootloader keeps, later ones are ignored
+pub const MAX_ENTRIES: usize = 8;
+
+/// Which font the console is drawn with in graphics modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontChoice<'a> {
+    /// The 8x16 font built into stage 1
+    Builtin,
+    /// The 8x16 font of the VGA BIOS
+    Bios,
+    /// 8.3 name of a PSF1 or PSF2 file in the root directory
+    File(&'a str),
+}
+
+/// A kernel that can be booted, with what to pass to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootEntry<'a> {
+    /// Name shown in the boot menu
+    pub title: &'a str,
+    /// 8.3 name of the kernel file in the root directory
+    pub kernel: &'a str,
+    /// Command line passed to the kernel
+    pub cmdline: &'a str,
+    /// 8.3 name of the initrd file for Linux kernels, if there is one
+    pub initrd: Option<&'a str>,
+}
+
+impl Default for BootEntry<'_> {
+    fn default() -> Self {
+        BootEntry {
+            title: DEFAULT_TITLE,
+            kernel: DEFAULT_KERNEL,
+            cmdline: "",
+            initrd: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootConfig<'a> {
+    /// Seconds the boot menu waits before booting the default entry, 0 to boot it straight
+    /// away unless a key is pressed
+    pub timeout: u32,
+    pub font: FontChoice<'a>,
+    /// The first `entry_count` are in use, the first of them is the default
+    entries: [BootEntry<'a>; MAX_ENTRIES],
+    entry_count: usize,
+}
+
+impl Default for BootConfig<'_> {
+    fn default() -> Self {
+        BootConfig::new(BootEntry::default())
+    }
+}
+
+impl<'a> BootConfig<'a> {
+    /// A configuration with only the given entry, no timeout and the built-in font
+    pub fn new(entry: BootEntry<'a>) -> Self {
+        let mut entries = [BootEntry::default(); MAX_ENTRIES];
+        entries[0] = entry;
+        BootConfig {
+            timeout: 0,
+            font: FontChoice::Builtin,
+            entries,
+            entry_count: 1,
+        }
+    }
+
+    /// The boot entries, the default one first
+    pub fn entries(&self) -> &[BootEntry<'a>] {
+        &self.entries[..self.entry_count]
+    }
+
+    /// Adds an entry after the others, false if there are `MAX_ENTRIES` already
+    pub fn add_entry(&mut self, entry: BootEntry<'a>) -> bool {
+        let Some(slot) = self.entries.get_mut(self.entry_count) else {
+            return false;
+        };
+        *slot = entry;
+        self.entry_count += 1;
+        true
+    }
+
+    /// Parses a configuration file, using defaults for anything missing
+    pub fn parse(text: &'a str) -> Self {
+        let mut config = BootConfig::default();
+        // Entry the keys go to, `None` while skipping entries that don't fit
+        let mut current = Some(0);
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            let entry = current.map(|index| &mut config.entries[index]);
+            match (key.trim(), entry) {
+                ("entry", _) => {
+                    let entry = BootEntry {
+                        title: value,
+                        ..BootEntry::default()
+                    };
+                    current = config.add_entry(entry).then_some(config.entry_count - 1);
+                }
+                ("kernel", Some(entry)) => entry.kernel = value,
+                ("cmdline", Some(entry)) => entry.cmdline = value,
+                ("initrd", Some(entry)) => {
+                    entry.initrd = Some(value).filter(|name| !name.is_empty())
+                }
+                ("timeout", _) => config.timeout = value.parse().unwrap_or(config.timeout),
+                ("font", _) => {
+                    config.font = match value {
+                        "" => FontChoice::Builtin,
+                        BIOS_FONT => FontChoice::Bios,
+                        name => FontChoice::File(name),
+                    }
+                }
+                _ => {}
+            }
+        }
+        config
+    }
+}
+
+impl core::fmt::Display for BootConfig<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "# spenceros boot configuration")?;
+        if self.timeout != 0 {
+            writeln!(f, "timeout={}", self.timeout)?;
+        }
+        match self.font {
+            FontChoice::Builtin => {}
+            FontChoice::Bios => writeln!(f, "font={BIOS_FONT}")?,
+            FontChoice::File(name) => writeln!(f, "font={name}")?,
+        }
+        for (index, entry) in self.entries().iter().enumerate() {
+            // The default entry can't have a title of its own
+            if index > 0 {
+                writeln!(f, "\nentry={}", entry.title)?;
+            }
+            writeln!(f, "kernel={}", entry.kernel)?;
+            writeln!(f, "cmdline={}", entry.cmdline)?;
+            if let Some(initrd) = entry.initrd {
+                writeln!(f, "initrd={initrd}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_config() {
+    let text = "# comment\n\nkernel = OTHER.ELF\r\ncmdline=quiet debug=1\nunknown=1\n";
+    let config = BootConfig::parse(text);
+    let [entry] = config.entries() else {
+        panic!("Expected one entry, got {:?}", config.entries());
+    };
+    assert_eq!(entry.title, DEFAULT_TITLE);
+    assert_eq!(entry.kernel, "OTHER.ELF");
+    assert_eq!(entry.cmdline, "quiet debug=1");
+    assert_eq!(entry.initrd, None);
+    assert_eq!(config.timeout, 0);
+    assert_eq!(config.font, FontChoice::Builtin);
+    assert_eq!(BootConfig::parse("font=bios").font, FontChoice::Bios);
+    assert_eq!(
+        BootConfig::parse("font=BIG.PSF").font,
+        FontChoice::File("BIG.PSF")
+    );
+    assert_eq!(
+        BootConfig::parse("initrd=INITRD.IMG").entries()[0].initrd,
+        Some("INITRD.IMG")
+    );
+    assert_eq!(BootConfig::parse("timeout=x").timeout, 0);
+    assert_eq!(BootConfig::parse(""), BootConfig::default());
+}
+
+#[test]
+fn test_parse_entries() {
+    let text =
+        "cmdline=quiet\nentry=Debug\nkernel=DEBUG.ELF\ntimeout=5\ncmdline=debug\nentry=Other\n";
+    let config = BootConfig::parse(text);
+    assert_eq!(config.timeout, 5);
+    let [default, debug, other] = config.entries() else {
+        panic!("Expected three entries, got {:?}", config.entries());
+    };
+    assert_eq!(default.cmdline, "quiet");
+    assert_eq!(debug.title, "Debug");
+    assert_eq!((debug.kernel, debug.cmdline), ("DEBUG.ELF", "debug"));
+    assert_eq!(other.title, "Other");
+    assert_eq!((other.kernel, other.cmdline), (DEFAULT_KERNEL, ""));
+
+    // Keys of the entries that don't fit go nowhere
+    let text = "entry=Extra\ncmdline=extra\n".repeat(MAX_ENTRIES);
+    let config = BootConfig::parse(&text);
+    assert_eq!(config.entries().len(), MAX_ENTRIES);
+    assert_eq!(config.entries()[0].cmdline, "");
+}
+
+#[test]
+fn test_config_round_trip() {
+    let mut config = BootConfig::new(BootEntry {
+        cmdline: "console=ttyS0",
+        initrd: Some(DEFAULT_INITRD),
+        ..BootEntry::default()
+    });
+    config.timeout = 3;
+    config.font = FontChoice::File("BIG.PSF");
+    assert!(config.add_entry(BootEntry {
+        title: "Debug kernel",
+        kernel: "DEBUG.ELF",
+        cmdline: "debug",
+        initrd: None,
+    }));
+    assert_eq!(BootConfig::parse(&config.to_string()), config);
+}
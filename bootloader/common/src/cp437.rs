@@ -0,0 +1,68 @@
+//! Code page 437, the character set of the VGA text mode and BIOS fonts, and of the built-in font
+//! of stage 1
+
+/// What the bytes from 0x80 up show, a row of 16 per line
+#[rustfmt::skip]
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// What the control characters from 0x01 to 0x1f show when they are drawn rather than obeyed
+#[rustfmt::skip]
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', '►',
+    '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// The code page 437 byte that shows `c`, ASCII as is. `None` for characters it doesn't have.
+pub fn from_char(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        return Some(c as u8);
+    }
+    if let Some(index) = HIGH.iter().position(|&high| high == c) {
+        return Some(0x80 + index as u8);
+    }
+    if let Some(index) = LOW.iter().position(|&low| low == c) {
+        return Some(0x01 + index as u8);
+    }
+    // Look alikes of characters that are there
+    match c {
+        'β' => Some(0xe1),
+        'μ' => Some(0xe6),
+        '∑' => Some(0xe4),
+        'Ω' => Some(0xea),
+        '⌂' => Some(0x7f),
+        _ => None,
+    }
+}
+
+/// Like [`from_char`], but `?` for characters code page 437 doesn't have
+pub fn encode(c: char) -> u8 {
+    from_char(c).unwrap_or(b'?')
+}
+
+#[test]
+fn test_cp437() {
+    assert_eq!(encode('A'), b'A');
+    assert_eq!(encode('\n'), b'\n');
+    assert_eq!(encode('é'), 0x82);
+    assert_eq!(encode('Ñ'), 0xa5);
+    assert_eq!(encode('─'), 0xc4);
+    assert_eq!(encode('╔'), 0xc9);
+    assert_eq!(encode('█'), 0xdb);
+    assert_eq!(encode('►'), 0x10);
+    assert_eq!(encode('μ'), encode('µ'));
+    assert_eq!(from_char('€'), None);
+    assert_eq!(encode('€'), b'?');
+    // Every character in the table round trips
+    for (index, c) in HIGH.iter().enumerate() {
+        assert_eq!(encode(*c), 0x80 + index as u8);
+    }
+}
@@ -0,0 +1,151 @@
+//! Reading sectors from whatever the bootloader was loaded from
+
+/// Size of a disk sector in bytes
+pub const SECTOR_SIZE: usize = 512;
+
+/// Error returned by a sector read, holds the BIOS int 0x13 status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskError(pub u8);
+
+impl DiskError {
+    /// What the BIOS status code means, for the common ones
+    pub fn reason(&self) -> &'static str {
+        match self.0 {
+            0x01 => "bad command",
+            0x02 => "no address mark",
+            0x03 => "write protected",
+            0x04 => "sector not found",
+            0x05 => "reset failed",
+            0x06 => "media changed",
+            0x08 => "DMA overrun",
+            0x09 => "DMA boundary",
+            0x0a => "bad sector",
+            0x0d => "bad sector count",
+            0x10 => "CRC error",
+            0x20 => "controller failure",
+            0x31 => "no media",
+            0x40 => "seek failed",
+            0x80 => "timeout",
+            0xaa => "drive not ready",
+            _ => "unknown error",
+        }
+    }
+}
+
+/// Something sectors can be read from
+pub trait SectorReader {
+    /// Reads `buffer.len() / SECTOR_SIZE` sectors starting at `lba` into `buffer`
+    fn read_sectors(&mut self, lba: u64, buffer: &mut [u8]) -> Result<(), DiskError>;
+}
+
+/// Where stage 0 loads the following stages from and to. Lives at `STAGE_LOCATION_OFFSET` in
+/// stage 0, where the image builder fills it in once it knows the layout, so the stages can move
+/// without rebuilding stage 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C, packed)]
+pub struct StageLocation {
+    /// First 512 byte sector of the stages
+    pub lba: u32,
+    /// Number of 512 byte sectors to load
+    pub sectors: u16,
+    /// Real mode segment the stages are loaded to, stage 0 jumps to offset 0 of it
+    pub segment: u16,
+}
+
+impl StageLocation {
+    /// Size of the table in stage 0
+    pub const SIZE: usize = 8;
+
+    /// Encodes the table as it is stored in stage 0
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&{ self.lba }.to_le_bytes());
+        bytes[4..6].copy_from_slice(&{ self.sectors }.to_le_bytes());
+        bytes[6..8].copy_from_slice(&{ self.segment }.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        StageLocation {
+            lba: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            sectors: u16::from_le_bytes([bytes[4], bytes[5]]),
+            segment: u16::from_le_bytes([bytes[6], bytes[7]]),
+        }
+    }
+}
+
+const _: () = assert!(size_of::<StageLocation>() == StageLocation::SIZE);
+const _: () = assert!(crate::STAGE_LOCATION_OFFSET + StageLocation::SIZE <= 446);
+
+/// Disk geometry for CHS addressing, as reported by int 0x13 AH=0x08
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub heads: u32,
+    pub sectors_per_track: u32,
+}
+
+/// A cylinder, head, sector address. Sectors count from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chs {
+    pub cylinder: u16,
+    pub head: u8,
+    pub sector: u8,
+}
+
+/// Highest cylinder int 0x13 can address, it only has 10 bits for it
+const MAX_CYLINDER: u32 = 1023;
+
+impl Geometry {
+    /// Converts an LBA to a CHS address, `None` if it is past the cylinders CHS can address
+    pub fn chs(&self, lba: u32) -> Option<Chs> {
+        let track = lba / self.sectors_per_track;
+        let cylinder = track / self.heads;
+        if cylinder > MAX_CYLINDER {
+            return None;
+        }
+        Some(Chs {
+            cylinder: cylinder as u16,
+            head: (track % self.heads) as u8,
+            sector: (lba % self.sectors_per_track + 1) as u8,
+        })
+    }
+}
+
+#[test]
+fn test_disk_error_reason() {
+    assert_eq!(DiskError(0x80).reason(), "timeout");
+    assert_eq!(DiskError(0x09).reason(), "DMA boundary");
+    assert_eq!(DiskError(0x42).reason(), "unknown error");
+}
+
+#[test]
+fn test_lba_to_chs() {
+    let geometry = Geometry {
+        heads: 16,
+        sectors_per_track: 63,
+    };
+    let chs = |cylinder, head, sector| {
+        Some(Chs {
+            cylinder,
+            head,
+            sector,
+        })
+    };
+    assert_eq!(geometry.chs(0), chs(0, 0, 1));
+    assert_eq!(geometry.chs(34), chs(0, 0, 35));
+    assert_eq!(geometry.chs(63), chs(0, 1, 1));
+    assert_eq!(geometry.chs(16 * 63 + 5), chs(1, 0, 6));
+    assert_eq!(geometry.chs(1024 * 16 * 63 - 1), chs(1023, 15, 63));
+    assert_eq!(geometry.chs(1024 * 16 * 63), None);
+}
+
+#[test]
+fn test_stage_location_bytes() {
+    let location = StageLocation {
+        lba: 0x801,
+        sectors: 0x40,
+        segment: 0x7e0,
+    };
+    assert_eq!(location.to_bytes(), [0x01, 0x08, 0, 0, 0x40, 0, 0xe0, 0x07]);
+    assert_eq!(StageLocation::from_bytes(&location.to_bytes()), location);
+}
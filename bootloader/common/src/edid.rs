@@ -0,0 +1,126 @@
+//! Just enough of EDID, the block a display describes itself with, to find its native resolution
+
+/// Size of the base EDID block
+pub const EDID_SIZE: usize = 128;
+/// Every EDID block starts with this
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+/// Offset of the established timings bitmap
+const ESTABLISHED_TIMINGS: usize = 35;
+/// Offset of the first detailed timing descriptor, the preferred timing on EDID 1.3 and later
+const DETAILED_TIMING: usize = 54;
+
+/// Resolutions in the established timings bitmap, from the top bit of its first byte down
+const ESTABLISHED: [(u16, u16); 17] = [
+    (720, 400),
+    (720, 400),
+    (640, 480),
+    (640, 480),
+    (640, 480),
+    (640, 480),
+    (800, 600),
+    (800, 600),
+    (800, 600),
+    (800, 600),
+    (832, 624),
+    (1024, 768),
+    (1024, 768),
+    (1024, 768),
+    (1024, 768),
+    (1280, 1024),
+    (1152, 870),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdidError {
+    /// The block doesn't start with the EDID header
+    BadHeader,
+    /// The bytes of the block don't add up to 0
+    BadChecksum,
+}
+
+/// A base EDID block
+pub struct Edid<'a>(&'a [u8; EDID_SIZE]);
+
+impl<'a> Edid<'a> {
+    /// Checks the header and checksum of `block`
+    pub fn parse(block: &'a [u8; EDID_SIZE]) -> Result<Self, EdidError> {
+        if block[..HEADER.len()] != HEADER {
+            return Err(EdidError::BadHeader);
+        }
+        if block.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(EdidError::BadChecksum);
+        }
+        Ok(Edid(block))
+    }
+
+    /// Width and height of the first detailed timing, which is the preferred one, if it is a
+    /// timing and not some other display descriptor
+    pub fn preferred_timing(&self) -> Option<(u16, u16)> {
+        let timing = &self.0[DETAILED_TIMING..DETAILED_TIMING + 18];
+        // Other descriptors have a zero pixel clock
+        if timing[0] == 0 && timing[1] == 0 {
+            return None;
+        }
+        // The upper 4 bits of the sizes are in the high nibble of the byte after the blanking
+        let width = timing[2] as u16 | ((timing[4] as u16 & 0xf0) << 4);
+        let height = timing[5] as u16 | ((timing[7] as u16 & 0xf0) << 4);
+        (width != 0 && height != 0).then_some((width, height))
+    }
+
+    /// The largest resolution in the established timings
+    pub fn largest_established_timing(&self) -> Option<(u16, u16)> {
+        let bits = u32::from_be_bytes([
+            self.0[ESTABLISHED_TIMINGS],
+            self.0[ESTABLISHED_TIMINGS + 1],
+            self.0[ESTABLISHED_TIMINGS + 2],
+            0,
+        ]);
+        ESTABLISHED
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| bits & (1 << (31 - bit)) != 0)
+            .map(|(_, resolution)| *resolution)
+            .max_by_key(|(width, height)| *width as u32 * *height as u32)
+    }
+
+    /// The resolution the display looks best at, the preferred timing or else the largest
+    /// established timing
+    pub fn native_resolution(&self) -> Option<(u16, u16)> {
+        self.preferred_timing()
+            .or_else(|| self.largest_established_timing())
+    }
+}
+
+/// An EDID block with the given established timings and first descriptor, checksum included
+#[cfg(test)]
+fn test_block(established: [u8; 3], descriptor: [u8; 18]) -> [u8; EDID_SIZE] {
+    let mut block = [0; EDID_SIZE];
+    block[..HEADER.len()].copy_from_slice(&HEADER);
+    block[ESTABLISHED_TIMINGS..ESTABLISHED_TIMINGS + 3].copy_from_slice(&established);
+    block[DETAILED_TIMING..DETAILED_TIMING + 18].copy_from_slice(&descriptor);
+    let sum = block.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    block[EDID_SIZE - 1] = sum.wrapping_neg();
+    block
+}
+
+#[test]
+fn test_native_resolution() {
+    // 1920x1080 at 148.5 MHz
+    let mut timing = [0; 18];
+    timing[..8].copy_from_slice(&[0x02, 0x3a, 0x80, 0x18, 0x71, 0x38, 0x2d, 0x40]);
+    let block = test_block([0x21, 0x08, 0x00], timing);
+    let edid = Edid::parse(&block).unwrap();
+    assert_eq!(edid.native_resolution(), Some((1920, 1080)));
+
+    // 640x480 and 1024x768 established, with a display name instead of a timing
+    let mut name = [0; 18];
+    name[3] = 0xfc;
+    let block = test_block([0x20, 0x08, 0x00], name);
+    let edid = Edid::parse(&block).unwrap();
+    assert_eq!(edid.preferred_timing(), None);
+    assert_eq!(edid.native_resolution(), Some((1024, 768)));
+
+    let mut block = block;
+    block[20] ^= 1;
+    assert_eq!(Edid::parse(&block).err(), Some(EdidError::BadChecksum));
+}
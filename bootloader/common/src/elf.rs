@@ -0,0 +1,277 @@
+//! Minimal ELF parser, enough to find the loadable segments and entry point of a kernel. Handles
+//! both 64 bit kernels and the 32 bit ones Multiboot2 kernels tend to be.
+
+/// Program header type of segments that are loaded into memory
+pub const PT_LOAD: u32 = 1;
+/// Object file type of executables
+const ET_EXEC: u16 = 2;
+/// Machine type of 32 bit x86
+const EM_386: u16 = 3;
+/// Machine type of x86_64
+const EM_X86_64: u16 = 62;
+
+/// Size of the ELF64 file header
+const HEADER_SIZE: usize = 64;
+/// Size of an ELF64 program header
+const PROGRAM_HEADER_SIZE: usize = 56;
+/// Size of the ELF32 file header
+const HEADER_SIZE_32: usize = 52;
+/// Size of an ELF32 program header
+const PROGRAM_HEADER_SIZE_32: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file is smaller than the headers say it is
+    TooShort,
+    /// The file doesn't start with `\x7fELF`
+    BadMagic,
+    /// Not a little endian x86 or x86_64 file
+    WrongArchitecture,
+    /// Not an executable, e.g. a relocatable object or shared library
+    NotExecutable,
+    /// Program headers aren't the size of a program header of the file's class
+    BadProgramHeaderSize,
+    /// A segment has more bytes in the file than in memory
+    BadSegmentSize,
+}
+
+/// A program header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub kind: u32,
+    pub flags: u32,
+    /// Offset of the segment in the file
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub physical_address: u64,
+    /// Bytes of the segment stored in the file
+    pub file_size: u64,
+    /// Bytes of the segment in memory, anything past `file_size` is zeroed (`.bss`)
+    pub memory_size: u64,
+}
+
+/// A parsed ELF executable
+pub struct Elf<'a> {
+    bytes: &'a [u8],
+    is_64_bit: bool,
+    entry: u64,
+    program_header_offset: usize,
+    program_header_count: usize,
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+impl<'a> Elf<'a> {
+    /// Checks the headers of an x86 or x86_64 executable. Every segment is checked to be inside
+    /// of `bytes`, so [`Elf::data`] can't fail later.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ElfError> {
+        if bytes.len() < HEADER_SIZE_32 {
+            return Err(ElfError::TooShort);
+        }
+        if bytes[0..4] != *b"\x7fELF" {
+            return Err(ElfError::BadMagic);
+        }
+        // Class has to match the machine, and everything is little endian
+        let is_64_bit = match (bytes[4], u16_at(bytes, 18)) {
+            (1, EM_386) => false,
+            (2, EM_X86_64) => true,
+            _ => return Err(ElfError::WrongArchitecture),
+        };
+        if bytes[5] != 1 {
+            return Err(ElfError::WrongArchitecture);
+        }
+        if is_64_bit && bytes.len() < HEADER_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if u16_at(bytes, 16) != ET_EXEC {
+            return Err(ElfError::NotExecutable);
+        }
+
+        // Same fields, but the 32 bit header has 4 byte addresses
+        let (entry, program_header_offset, header_size_at, expected_size) = if is_64_bit {
+            (
+                u64_at(bytes, 24),
+                u64_at(bytes, 32),
+                54,
+                PROGRAM_HEADER_SIZE,
+            )
+        } else {
+            let entry = u32_at(bytes, 24) as u64;
+            (entry, u32_at(bytes, 28) as u64, 42, PROGRAM_HEADER_SIZE_32)
+        };
+        let program_header_count = u16_at(bytes, header_size_at + 2) as usize;
+        if program_header_count != 0 && u16_at(bytes, header_size_at) as usize != expected_size {
+            return Err(ElfError::BadProgramHeaderSize);
+        }
+        let table_end = program_header_offset
+            .checked_add((program_header_count * expected_size) as u64)
+            .ok_or(ElfError::TooShort)?;
+        if table_end > bytes.len() as u64 {
+            return Err(ElfError::TooShort);
+        }
+
+        let elf = Elf {
+            bytes,
+            is_64_bit,
+            entry,
+            program_header_offset: program_header_offset as usize,
+            program_header_count,
+        };
+        for segment in elf.load_segments() {
+            if segment.file_size > segment.memory_size {
+                return Err(ElfError::BadSegmentSize);
+            }
+            match segment.offset.checked_add(segment.file_size) {
+                Some(end) if end <= bytes.len() as u64 => {}
+                _ => return Err(ElfError::TooShort),
+            }
+        }
+        Ok(elf)
+    }
+
+    /// Whether this is an x86_64 executable, as opposed to 32 bit x86
+    pub fn is_64_bit(&self) -> bool {
+        self.is_64_bit
+    }
+
+    /// Address execution starts at
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Every program header
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + '_ {
+        (0..self.program_header_count).map(|ii| {
+            if self.is_64_bit {
+                let at = self.program_header_offset + ii * PROGRAM_HEADER_SIZE;
+                let header = &self.bytes[at..];
+                Segment {
+                    kind: u32_at(header, 0),
+                    flags: u32_at(header, 4),
+                    offset: u64_at(header, 8),
+                    virtual_address: u64_at(header, 16),
+                    physical_address: u64_at(header, 24),
+                    file_size: u64_at(header, 32),
+                    memory_size: u64_at(header, 40),
+                }
+            } else {
+                let at = self.program_header_offset + ii * PROGRAM_HEADER_SIZE_32;
+                let header = &self.bytes[at..];
+                Segment {
+                    kind: u32_at(header, 0),
+                    offset: u32_at(header, 4) as u64,
+                    virtual_address: u32_at(header, 8) as u64,
+                    physical_address: u32_at(header, 12) as u64,
+                    file_size: u32_at(header, 16) as u64,
+                    memory_size: u32_at(header, 20) as u64,
+                    flags: u32_at(header, 24),
+                }
+            }
+        })
+    }
+
+    /// The segments that have to be copied into memory
+    pub fn load_segments(&self) -> impl Iterator<Item = Segment> + '_ {
+        self.segments().filter(|segment| segment.kind == PT_LOAD)
+    }
+
+    /// Bytes of a loadable segment stored in the file
+    pub fn data(&self, segment: &Segment) -> &'a [u8] {
+        let start = segment.offset as usize;
+        &self.bytes[start..start + segment.file_size as usize]
+    }
+}
+
+/// Builds an executable with a single segment of `data` followed by `bss` zeroed bytes
+#[cfg(test)]
+fn test_elf(data: &[u8], bss: u64) -> Vec<u8> {
+    let mut elf = vec![0; HEADER_SIZE + PROGRAM_HEADER_SIZE];
+    elf[0..4].copy_from_slice(b"\x7fELF");
+    elf[4] = 2;
+    elf[5] = 1;
+    elf[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+    elf[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+    elf[24..32].copy_from_slice(&0x100010u64.to_le_bytes());
+    elf[32..40].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+    elf[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    elf[56..58].copy_from_slice(&1u16.to_le_bytes());
+
+    let header = &mut elf[HEADER_SIZE..];
+    header[0..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+    header[8..16].copy_from_slice(&((HEADER_SIZE + PROGRAM_HEADER_SIZE) as u64).to_le_bytes());
+    header[16..24].copy_from_slice(&0x100000u64.to_le_bytes());
+    header[24..32].copy_from_slice(&0x100000u64.to_le_bytes());
+    header[32..40].copy_from_slice(&(data.len() as u64).to_le_bytes());
+    header[40..48].copy_from_slice(&(data.len() as u64 + bss).to_le_bytes());
+    elf.extend_from_slice(data);
+    elf
+}
+
+#[test]
+fn test_parse_elf() {
+    let bytes = test_elf(b"kernel code", 0x20);
+    let elf = Elf::parse(&bytes).unwrap();
+    assert!(elf.is_64_bit());
+    assert_eq!(elf.entry(), 0x100010);
+
+    let segments: Vec<_> = elf.load_segments().collect();
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].physical_address, 0x100000);
+    assert_eq!(segments[0].memory_size, 11 + 0x20);
+    assert_eq!(elf.data(&segments[0]), b"kernel code");
+}
+
+#[test]
+fn test_bad_elf() {
+    let bytes = test_elf(b"kernel code", 0);
+    assert_eq!(
+        Elf::parse(&bytes[..bytes.len() - 1]).err(),
+        Some(ElfError::TooShort)
+    );
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] = 0;
+    assert_eq!(Elf::parse(&bad_magic).err(), Some(ElfError::BadMagic));
+
+    // 32 bit class with a 64 bit machine
+    let mut elf32 = bytes.clone();
+    elf32[4] = 1;
+    assert_eq!(Elf::parse(&elf32).err(), Some(ElfError::WrongArchitecture));
+}
+
+#[test]
+fn test_parse_elf32() {
+    let mut elf = vec![0; HEADER_SIZE_32 + PROGRAM_HEADER_SIZE_32];
+    elf[0..4].copy_from_slice(b"\x7fELF");
+    elf[4] = 1;
+    elf[5] = 1;
+    elf[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+    elf[18..20].copy_from_slice(&EM_386.to_le_bytes());
+    elf[24..28].copy_from_slice(&0x100000u32.to_le_bytes());
+    elf[28..32].copy_from_slice(&(HEADER_SIZE_32 as u32).to_le_bytes());
+    elf[42..44].copy_from_slice(&(PROGRAM_HEADER_SIZE_32 as u16).to_le_bytes());
+    elf[44..46].copy_from_slice(&1u16.to_le_bytes());
+    let header = &mut elf[HEADER_SIZE_32..];
+    header[0..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+    header[4..8].copy_from_slice(&0u32.to_le_bytes());
+    header[12..16].copy_from_slice(&0x100000u32.to_le_bytes());
+    header[16..20].copy_from_slice(&0x10u32.to_le_bytes());
+    header[20..24].copy_from_slice(&0x1000u32.to_le_bytes());
+
+    let elf = Elf::parse(&elf).unwrap();
+    assert!(!elf.is_64_bit());
+    assert_eq!(elf.entry(), 0x100000);
+    let segment = elf.load_segments().next().unwrap();
+    assert_eq!(segment.physical_address, 0x100000);
+    assert_eq!((segment.file_size, segment.memory_size), (0x10, 0x1000));
+}
@@ -0,0 +1,344 @@
+//! Minimal read only FAT32 driver, enough to find and read files in the root directory
+
+use crate::disk::{DiskError, SectorReader, SECTOR_SIZE};
+
+/// Size of a directory entry in bytes
+pub const DIR_ENTRY_SIZE: usize = 32;
+/// Cluster numbers at or above this mark the end of a chain
+pub const END_OF_CHAIN: u32 = 0x0fff_fff8;
+/// Only the low 28 bits of a FAT32 entry are used
+const CLUSTER_MASK: u32 = 0x0fff_ffff;
+
+/// Directory entry attribute of the volume label
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+/// Directory entry attribute of subdirectories
+pub const ATTR_DIRECTORY: u8 = 0x10;
+/// Directory entry attribute of regular files
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// Attribute combination marking a long file name entry
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatError {
+    Disk(DiskError),
+    /// The boot sector doesn't describe a FAT32 volume
+    NotFat32,
+    /// There is no file with the given name
+    NotFound,
+    /// A cluster chain points outside the volume
+    BadCluster(u32),
+    /// A cluster chain loops or ends before the file does
+    BadChain,
+}
+
+impl From<DiskError> for FatError {
+    fn from(e: DiskError) -> Self {
+        FatError::Disk(e)
+    }
+}
+
+/// Converts a file name like `KERNEL.ELF` to the space padded 8.3 form stored in directory
+/// entries. Returns `None` if the name doesn't fit in 8.3 or has lowercase letters.
+pub const fn short_name(name: &str) -> Option<[u8; 11]> {
+    let bytes = name.as_bytes();
+    let mut short = [b' '; 11];
+    let mut ii = 0;
+    let mut out = 0;
+    let mut in_extension = false;
+    while ii < bytes.len() {
+        let c = bytes[ii];
+        ii += 1;
+        if c == b'.' && !in_extension && ii > 1 {
+            in_extension = true;
+            out = 8;
+            continue;
+        }
+        let valid = c.is_ascii_uppercase()
+            || c.is_ascii_digit()
+            || matches!(c, b'_' | b'-' | b'~' | b'!' | b'#' | b'$' | b'%' | b'&');
+        let limit = if in_extension { 11 } else { 8 };
+        if !valid || out >= limit {
+            return None;
+        }
+        short[out] = c;
+        out += 1;
+    }
+
+    if bytes.is_empty() {
+        None
+    } else {
+        Some(short)
+    }
+}
+
+/// A file found in a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileEntry {
+    pub first_cluster: u32,
+    /// Size in bytes
+    pub size: u32,
+}
+
+/// An opened FAT32 volume
+pub struct Fat32<'a, R: SectorReader> {
+    reader: &'a mut R,
+    /// Scratch space for directory and file sectors
+    sector: &'a mut [u8; SECTOR_SIZE],
+    /// The most recently read sector of the FAT, so following a chain doesn't reread it
+    fat_sector: &'a mut [u8; SECTOR_SIZE],
+    fat_sector_lba: Option<u64>,
+    fat_start: u64,
+    data_start: u64,
+    sectors_per_cluster: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+}
+
+impl<'a, R: SectorReader> Fat32<'a, R> {
+    /// Reads the boot sector of the volume starting at `partition_lba`
+    pub fn open(
+        reader: &'a mut R,
+        sector: &'a mut [u8; SECTOR_SIZE],
+        fat_sector: &'a mut [u8; SECTOR_SIZE],
+        partition_lba: u64,
+    ) -> Result<Self, FatError> {
+        reader.read_sectors(partition_lba, sector)?;
+
+        let u16_at = |at: usize| u16::from_le_bytes([sector[at], sector[at + 1]]) as u64;
+        let u32_at = |at: usize| u32::from_le_bytes(sector[at..at + 4].try_into().unwrap());
+
+        let bytes_per_sector = u16_at(11);
+        let sectors_per_cluster = sector[13] as u64;
+        let reserved_sectors = u16_at(14);
+        let fat_count = sector[16] as u64;
+        let root_entries = u16_at(17);
+        let total_sectors = u32_at(32) as u64;
+        let fat_sectors = u32_at(36) as u64;
+        let root_cluster = u32_at(44);
+
+        // FAT12/16 have root entries and a 16 bit FAT size
+        let is_fat32 = sector[510..512] == [0x55, 0xaa]
+            && bytes_per_sector == SECTOR_SIZE as u64
+            && sectors_per_cluster != 0
+            && root_entries == 0
+            && fat_sectors != 0;
+        if !is_fat32 {
+            return Err(FatError::NotFat32);
+        }
+
+        let fat_start = partition_lba + reserved_sectors;
+        let data_start = fat_start + fat_count * fat_sectors;
+        // A bad boot sector can put the data before the FATs end
+        let Some(data_sectors) = total_sectors.checked_sub(data_start - partition_lba) else {
+            return Err(FatError::NotFat32);
+        };
+        let cluster_count = (data_sectors / sectors_per_cluster) as u32;
+
+        Ok(Fat32 {
+            reader,
+            sector,
+            fat_sector,
+            fat_sector_lba: None,
+            fat_start,
+            data_start,
+            sectors_per_cluster,
+            cluster_count,
+            root_cluster,
+        })
+    }
+
+    /// First sector of a cluster
+    fn cluster_lba(&self, cluster: u32) -> Result<u64, FatError> {
+        if cluster < 2 || cluster - 2 >= self.cluster_count {
+            return Err(FatError::BadCluster(cluster));
+        }
+        Ok(self.data_start + (cluster as u64 - 2) * self.sectors_per_cluster)
+    }
+
+    /// Looks up the cluster following `cluster` in the FAT
+    fn next_cluster(&mut self, cluster: u32) -> Result<u32, FatError> {
+        let offset = cluster as u64 * 4;
+        let lba = self.fat_start + offset / SECTOR_SIZE as u64;
+        if self.fat_sector_lba != Some(lba) {
+            self.reader.read_sectors(lba, self.fat_sector)?;
+            self.fat_sector_lba = Some(lba);
+        }
+        let at = (offset % SECTOR_SIZE as u64) as usize;
+        let entry = u32::from_le_bytes(self.fat_sector[at..at + 4].try_into().unwrap());
+        Ok(entry & CLUSTER_MASK)
+    }
+
+    /// Calls `f` with every sector of a cluster chain until it returns false
+    fn walk_chain(
+        &mut self,
+        first_cluster: u32,
+        mut f: impl FnMut(&[u8; SECTOR_SIZE]) -> bool,
+    ) -> Result<(), FatError> {
+        let mut cluster = first_cluster;
+        // A chain can't be longer than the volume, so any more clusters means it loops
+        let mut clusters_left = self.cluster_count;
+        while cluster < END_OF_CHAIN {
+            if clusters_left == 0 {
+                return Err(FatError::BadChain);
+            }
+            clusters_left -= 1;
+            let lba = self.cluster_lba(cluster)?;
+            for ii in 0..self.sectors_per_cluster {
+                self.reader.read_sectors(lba + ii, self.sector)?;
+                if !f(self.sector) {
+                    return Ok(());
+                }
+            }
+            cluster = self.next_cluster(cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Finds a file in the root directory by its 8.3 name, see [`short_name`]
+    pub fn find(&mut self, name: &[u8; 11]) -> Result<FileEntry, FatError> {
+        let mut found = None;
+        self.walk_chain(self.root_cluster, |sector| {
+            for entry in sector.chunks_exact(DIR_ENTRY_SIZE) {
+                match entry[0] {
+                    // End of directory
+                    0x00 => return false,
+                    // Deleted
+                    0xe5 => continue,
+                    _ => {}
+                }
+                let attributes = entry[11];
+                if attributes == ATTR_LONG_NAME
+                    || attributes & (ATTR_VOLUME_ID | ATTR_DIRECTORY) != 0
+                {
+                    continue;
+                }
+                if &entry[0..11] == name {
+                    let high = u16::from_le_bytes([entry[20], entry[21]]) as u32;
+                    let low = u16::from_le_bytes([entry[26], entry[27]]) as u32;
+                    found = Some(FileEntry {
+                        first_cluster: high << 16 | low,
+                        size: u32::from_le_bytes(entry[28..32].try_into().unwrap()),
+                    });
+                    return false;
+                }
+            }
+            true
+        })?;
+
+        found.ok_or(FatError::NotFound)
+    }
+
+    /// Reads a file, calling `f` with consecutive pieces of it. Every piece is a whole sector
+    /// except the last one.
+    pub fn read(&mut self, file: &FileEntry, mut f: impl FnMut(&[u8])) -> Result<(), FatError> {
+        let mut remaining = file.size as usize;
+        if remaining == 0 {
+            return Ok(());
+        }
+        self.walk_chain(file.first_cluster, |sector| {
+            let len = remaining.min(SECTOR_SIZE);
+            f(&sector[..len]);
+            remaining -= len;
+            remaining > 0
+        })?;
+
+        if remaining > 0 {
+            return Err(FatError::BadChain);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+struct MemoryDisk(Vec<u8>);
+
+#[cfg(test)]
+impl SectorReader for MemoryDisk {
+    fn read_sectors(&mut self, lba: u64, buffer: &mut [u8]) -> Result<(), DiskError> {
+        let start = lba as usize * SECTOR_SIZE;
+        let bytes = self
+            .0
+            .get(start..start + buffer.len())
+            .ok_or(DiskError(0x04))?;
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_short_name() {
+    assert_eq!(short_name("KERNEL.ELF"), Some(*b"KERNEL  ELF"));
+    assert_eq!(short_name("BOOT.CFG"), Some(*b"BOOT    CFG"));
+    assert_eq!(short_name("README"), Some(*b"README     "));
+    assert_eq!(short_name("kernel.elf"), None);
+    assert_eq!(short_name("TOOLONGNAME.ELF"), None);
+    assert_eq!(short_name("A.B.C"), None);
+    assert_eq!(short_name(""), None);
+}
+
+#[test]
+fn test_read_file() {
+    // One reserved sector, a one sector FAT and 8 one sector clusters
+    let mut disk = vec![0; 10 * SECTOR_SIZE];
+    let boot = &mut disk[..SECTOR_SIZE];
+    boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+    boot[13] = 1;
+    boot[14..16].copy_from_slice(&1u16.to_le_bytes());
+    boot[16] = 1;
+    boot[32..36].copy_from_slice(&10u32.to_le_bytes());
+    boot[36..40].copy_from_slice(&1u32.to_le_bytes());
+    boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+    boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+    // Root directory in cluster 2, file spans cluster 3 and 5
+    let fat = &mut disk[SECTOR_SIZE..2 * SECTOR_SIZE];
+    for (cluster, next) in [(2, 0x0fff_ffff), (3, 5), (5, 0x0fff_ffff)] {
+        fat[cluster * 4..cluster * 4 + 4].copy_from_slice(&(next as u32).to_le_bytes());
+    }
+
+    let root = &mut disk[2 * SECTOR_SIZE..3 * SECTOR_SIZE];
+    root[0..11].copy_from_slice(b"SPENCEROS  ");
+    root[11] = ATTR_VOLUME_ID;
+    let entry = &mut root[DIR_ENTRY_SIZE..2 * DIR_ENTRY_SIZE];
+    entry[0..11].copy_from_slice(b"KERNEL  ELF");
+    entry[11] = ATTR_ARCHIVE;
+    entry[26..28].copy_from_slice(&3u16.to_le_bytes());
+    entry[28..32].copy_from_slice(&600u32.to_le_bytes());
+
+    disk[3 * SECTOR_SIZE..4 * SECTOR_SIZE].fill(b'a');
+    disk[5 * SECTOR_SIZE..6 * SECTOR_SIZE].fill(b'b');
+
+    let mut disk = MemoryDisk(disk);
+    let (mut sector, mut fat_sector) = ([0; SECTOR_SIZE], [0; SECTOR_SIZE]);
+    let mut volume = Fat32::open(&mut disk, &mut sector, &mut fat_sector, 0).unwrap();
+
+    assert_eq!(volume.find(b"MISSING    "), Err(FatError::NotFound));
+    let file = volume.find(b"KERNEL  ELF").unwrap();
+    assert_eq!(file.size, 600);
+
+    let mut contents = Vec::new();
+    volume
+        .read(&file, |piece| contents.extend_from_slice(piece))
+        .unwrap();
+    assert_eq!(contents.len(), 600);
+    assert!(contents[..512].iter().all(|c| *c == b'a'));
+    assert!(contents[512..].iter().all(|c| *c == b'b'));
+
+    // A chain ending before the file does
+    let long = FileEntry { size: 2000, ..file };
+    assert_eq!(volume.read(&long, |_| {}), Err(FatError::BadChain));
+
+    // A chain looping back on itself, with a size that would never be covered
+    disk.0[SECTOR_SIZE + 5 * 4..SECTOR_SIZE + 6 * 4].copy_from_slice(&3u32.to_le_bytes());
+    let mut volume = Fat32::open(&mut disk, &mut sector, &mut fat_sector, 0).unwrap();
+    let huge = FileEntry {
+        size: u32::MAX,
+        ..file
+    };
+    assert_eq!(volume.read(&huge, |_| {}), Err(FatError::BadChain));
+
+    // Fewer sectors in total than the reserved sector and the FAT take up
+    disk.0[32..36].copy_from_slice(&1u32.to_le_bytes());
+    let volume = Fat32::open(&mut disk, &mut sector, &mut fat_sector, 0);
+    assert_eq!(volume.err(), Some(FatError::NotFat32));
+}
@@ -0,0 +1,301 @@
+//! Bitmap fonts, from PSF1 and PSF2 files like the Linux console uses or from a bare bitmap like
+//! the VGA BIOS font
+
+use crate::cp437;
+use crate::FontInfo;
+
+/// Every PSF1 file starts with this
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+/// Size of the PSF1 header
+const PSF1_HEADER_SIZE: usize = 4;
+/// PSF1 mode bit set for fonts with 512 glyphs instead of 256
+const PSF1_MODE_512: u8 = 0x01;
+/// PSF1 mode bits set for fonts with a unicode table after the glyphs
+const PSF1_MODE_HAS_TABLE: u8 = 0x02 | 0x04;
+/// Ends the characters of a glyph in a PSF1 unicode table
+const PSF1_SEPARATOR: u16 = 0xffff;
+/// Starts the sequences of characters of a glyph in a PSF1 unicode table
+const PSF1_SEQUENCE: u16 = 0xfffe;
+/// Every PSF2 file starts with this
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+/// Size of the PSF2 header, later versions could make it bigger
+const PSF2_HEADER_SIZE: usize = 32;
+/// PSF2 flag set for fonts with a unicode table after the glyphs
+const PSF2_HAS_TABLE: u32 = 0x01;
+/// Ends the characters of a glyph in a PSF2 unicode table
+const PSF2_SEPARATOR: u8 = 0xff;
+/// Starts the sequences of characters of a glyph in a PSF2 unicode table
+const PSF2_SEQUENCE: u8 = 0xfe;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsfError {
+    /// Neither a PSF1 nor a PSF2 file
+    BadMagic,
+    /// The file ends before the last glyph
+    Truncated,
+    /// Glyphs without pixels, too big or bigger than the bytes the header gives them
+    BadGlyphSize,
+}
+
+/// Which characters each glyph shows
+#[derive(Debug, Clone, Copy)]
+enum UnicodeTable<'a> {
+    /// The glyphs are in code page 437 order, like the VGA BIOS font
+    Cp437,
+    /// For each glyph UTF-16 characters, then sequences of them, then a separator
+    Psf1(&'a [u8]),
+    /// For each glyph UTF-8 characters, then sequences of them, then a separator
+    Psf2(&'a [u8]),
+}
+
+/// A bitmap font, one bit per pixel with the leftmost pixel in the top bit and each row padded to
+/// a whole byte
+#[derive(Debug, Clone, Copy)]
+pub struct Font<'a> {
+    glyphs: &'a [u8],
+    glyph_count: u32,
+    width: u16,
+    height: u16,
+    /// Bytes between the starts of consecutive glyphs, at least `height` rows
+    bytes_per_glyph: usize,
+    unicode: UnicodeTable<'a>,
+}
+
+impl<'a> Font<'a> {
+    /// A font from glyphs of the given size right after each other, like the VGA BIOS font
+    pub fn new(glyphs: &'a [u8], width: u16, height: u16) -> Self {
+        let bytes_per_glyph = (width as usize).div_ceil(8) * height as usize;
+        Font {
+            glyphs,
+            glyph_count: (glyphs.len() / bytes_per_glyph) as u32,
+            width,
+            height,
+            bytes_per_glyph,
+            unicode: UnicodeTable::Cp437,
+        }
+    }
+
+    /// Reads a PSF1 or PSF2 file. Fonts without a unicode table are taken to be in code page 437
+    /// order.
+    pub fn parse_psf(file: &'a [u8]) -> Result<Self, PsfError> {
+        let u32_at = |offset: usize| {
+            let bytes = file.get(offset..offset + 4).ok_or(PsfError::Truncated)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let psf2 = file.starts_with(&PSF2_MAGIC);
+        let (start, count, glyph_size, width, height) = if psf2 {
+            // Magic, version, header size, flags, glyph count, bytes per glyph, height, width
+            let start = u32_at(8)? as usize;
+            if start < PSF2_HEADER_SIZE {
+                return Err(PsfError::Truncated);
+            }
+            let count = u32_at(16)?;
+            let glyph_size = u32_at(20)? as usize;
+            (start, count, glyph_size, u32_at(28)?, u32_at(24)?)
+        } else if file.starts_with(&PSF1_MAGIC) {
+            // Magic, mode and bytes per glyph, which is the height since they are 8 wide
+            let height = *file.get(3).ok_or(PsfError::Truncated)?;
+            let count = if file[2] & PSF1_MODE_512 != 0 {
+                512
+            } else {
+                256
+            };
+            (PSF1_HEADER_SIZE, count, height as usize, 8, height as u32)
+        } else {
+            return Err(PsfError::BadMagic);
+        };
+
+        let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+            return Err(PsfError::BadGlyphSize);
+        };
+        let bytes_per_row = (width as usize).div_ceil(8);
+        if width == 0 || height == 0 || glyph_size < bytes_per_row * height as usize {
+            return Err(PsfError::BadGlyphSize);
+        }
+        let len = glyph_size
+            .checked_mul(count as usize)
+            .ok_or(PsfError::Truncated)?;
+        let glyphs = start
+            .checked_add(len)
+            .and_then(|end| file.get(start..end))
+            .ok_or(PsfError::Truncated)?;
+        // The unicode table is whatever comes after the glyphs
+        let table = &file[start + len..];
+        let unicode = if psf2 && u32_at(12)? & PSF2_HAS_TABLE != 0 {
+            UnicodeTable::Psf2(table)
+        } else if !psf2 && file[2] & PSF1_MODE_HAS_TABLE != 0 {
+            UnicodeTable::Psf1(table)
+        } else {
+            UnicodeTable::Cp437
+        };
+        Ok(Font {
+            glyphs,
+            glyph_count: count,
+            width,
+            height,
+            bytes_per_glyph: glyph_size,
+            unicode,
+        })
+    }
+
+    /// Width of a glyph in pixels
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    /// Height of a glyph in pixels
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn glyph_count(&self) -> u32 {
+        self.glyph_count
+    }
+
+    /// Bytes in a row of a glyph
+    pub fn bytes_per_row(&self) -> usize {
+        (self.width as usize).div_ceil(8)
+    }
+
+    /// The rows of glyph `index`, `None` if the font doesn't have that many
+    pub fn glyph(&self, index: u32) -> Option<&'a [u8]> {
+        if index >= self.glyph_count {
+            return None;
+        }
+        let start = index as usize * self.bytes_per_glyph;
+        Some(&self.glyphs[start..start + self.bytes_per_row() * self.height as usize])
+    }
+
+    /// The glyph that shows `c`, from the unicode table of the font if it has one. Characters
+    /// that are only there as part of a sequence, like a letter and a combining accent, don't
+    /// count.
+    pub fn glyph_index(&self, c: char) -> Option<u32> {
+        let index = match self.unicode {
+            UnicodeTable::Cp437 => cp437::from_char(c).map(u32::from),
+            UnicodeTable::Psf1(table) => {
+                let mut index = 0;
+                let mut in_sequences = false;
+                let mut found = None;
+                for pair in table.chunks_exact(2) {
+                    match u16::from_le_bytes([pair[0], pair[1]]) {
+                        PSF1_SEPARATOR => {
+                            index += 1;
+                            in_sequences = false;
+                        }
+                        PSF1_SEQUENCE => in_sequences = true,
+                        value if !in_sequences && value as u32 == c as u32 => {
+                            found = Some(index);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                found
+            }
+            UnicodeTable::Psf2(table) => table
+                .split(|&byte| byte == PSF2_SEPARATOR)
+                .position(|entry| {
+                    let characters = entry.split(|&byte| byte == PSF2_SEQUENCE).next();
+                    characters
+                        .and_then(|characters| core::str::from_utf8(characters).ok())
+                        .is_some_and(|characters| characters.contains(c))
+                })
+                .map(|index| index as u32),
+        }?;
+        (index < self.glyph_count).then_some(index)
+    }
+
+    /// Whether pixel `x` of a row of a glyph is set
+    pub fn is_set(row: &[u8], x: u16) -> bool {
+        row[x as usize / 8] & (0x80 >> (x % 8)) != 0
+    }
+
+    /// The font as handed to the kernel
+    pub fn info(&self) -> FontInfo {
+        FontInfo {
+            address: self.glyphs.as_ptr() as u64,
+            glyph_count: self.glyph_count,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+#[test]
+fn test_parse_psf() {
+    // PSF1 with 256 8x2 glyphs, glyph n is [n, !n]
+    let mut file = vec![0x36, 0x04, 0x00, 0x02];
+    file.extend((0..=255u8).flat_map(|n| [n, !n]));
+    let font = Font::parse_psf(&file).unwrap();
+    assert_eq!(
+        (font.width(), font.height(), font.glyph_count()),
+        (8, 2, 256)
+    );
+    assert_eq!(font.glyph(0x41), Some(&[0x41, 0xbe][..]));
+    assert_eq!(font.glyph(256), None);
+    file.pop();
+    assert_eq!(Font::parse_psf(&file).err(), Some(PsfError::Truncated));
+
+    // PSF2 with 2 glyphs 12 wide, 3 high, each padded to 8 bytes
+    let mut file = Vec::new();
+    for value in [0x864ab572u32, 0, 32, 0, 2, 8, 3, 12] {
+        file.extend(value.to_le_bytes());
+    }
+    file.extend([0xff, 0xf0, 0x80, 0x10, 0xff, 0xf0, 0, 0]);
+    file.extend([0; 8]);
+    let font = Font::parse_psf(&file).unwrap();
+    assert_eq!(
+        (font.width(), font.height(), font.bytes_per_row()),
+        (12, 3, 2)
+    );
+    let row = &font.glyph(0).unwrap()[2..4];
+    assert!(Font::is_set(row, 0) && !Font::is_set(row, 1) && Font::is_set(row, 11));
+    assert_eq!(font.glyph(1), Some(&[0; 6][..]));
+
+    // The header says glyphs take fewer bytes than their rows
+    file[20] = 4;
+    assert_eq!(Font::parse_psf(&file).err(), Some(PsfError::BadGlyphSize));
+    assert_eq!(Font::parse_psf(b"BM").err(), Some(PsfError::BadMagic));
+
+    let bitmap = [0u8; 0x1000];
+    let font = Font::new(&bitmap, 8, 16);
+    assert_eq!(font.glyph_count(), 256);
+    assert_eq!(font.glyph_index('╔'), Some(0xc9));
+}
+
+#[test]
+fn test_unicode_table() {
+    // PSF1 with 256 glyphs of 1 row, glyph 1 shows 'é' and 'e' with a combining acute accent,
+    // glyph 2 shows 'Ω' and 'Ω'
+    let mut file = vec![0x36, 0x04, PSF1_MODE_HAS_TABLE, 0x01];
+    file.extend([0; 256]);
+    let table: [u16; 8] = [0x41, 0xffff, 0xe9, 0xfffe, 0x65, 0x301, 0xffff, 0x3a9];
+    file.extend(table.iter().flat_map(|value| value.to_le_bytes()));
+    file.extend(
+        [0x2126u16, 0xffff]
+            .iter()
+            .flat_map(|value| value.to_le_bytes()),
+    );
+    let font = Font::parse_psf(&file).unwrap();
+    assert_eq!(font.glyph_index('A'), Some(0));
+    assert_eq!(font.glyph_index('é'), Some(1));
+    assert_eq!(font.glyph_index('\u{301}'), None);
+    assert_eq!(font.glyph_index('Ω'), Some(2));
+    assert_eq!(font.glyph_index('B'), None);
+
+    // The same for PSF2, with 3 glyphs
+    let mut file = Vec::new();
+    for value in [0x864ab572u32, 0, 32, PSF2_HAS_TABLE, 3, 1, 1, 8] {
+        file.extend(value.to_le_bytes());
+    }
+    file.extend([0; 3]);
+    file.extend(b"A\xff");
+    file.extend("é".bytes().chain([PSF2_SEQUENCE]).chain("e\u{301}".bytes()));
+    file.push(PSF2_SEPARATOR);
+    file.extend("ΩΩ".bytes().chain([PSF2_SEPARATOR]));
+    let font = Font::parse_psf(&file).unwrap();
+    assert_eq!(font.glyph_index('A'), Some(0));
+    assert_eq!(font.glyph_index('é'), Some(1));
+    assert_eq!(font.glyph_index('e'), None);
+    assert_eq!(font.glyph_index('Ω'), Some(2));
+}
@@ -25,9 +25,10 @@ impl GdtEntry {
         GdtEntry::new(0, 0xFFFFF, kernel_data_flags(), extra_flags_long())
     }
 
+    /// Needs the long mode flag, without it a far jump lands in 32 bit compatibility mode
     #[inline]
     pub const fn code_64() -> GdtEntry {
-        GdtEntry::code_32()
+        GdtEntry::new(0, 0xFFFFF, kernel_code_flags(), extra_flags_long())
     }
 
     #[inline]
@@ -147,7 +148,7 @@ pub struct Gdt {
 #[repr(C, packed(2))]
 pub struct GdtPointer {
     pub limit: u16,
-    pub base: *const Gdt,
+    pub base: *const GdtEntry,
     // We conditionally pad the struct so that it will always be a valid width for 64 bit mode
     #[cfg(target_pointer_width = "32")]
     _pad: [u8; 4],
@@ -164,18 +165,23 @@ fn test_size() {
     }
 }
 
+/// Loads the table of `size` bytes at `base`
+fn load_table(base: *const GdtEntry, size: usize) {
+    let pointer = GdtPointer {
+        limit: size as u16,
+        base,
+        #[cfg(target_pointer_width = "32")]
+        _pad: [0; 4],
+    };
+
+    unsafe {
+        asm!("lgdt [{}]", in(reg) &pointer, options(readonly, nostack, preserves_flags));
+    }
+}
+
 impl Gdt {
     pub fn load(&'static self) {
-        let pointer = GdtPointer {
-            limit: size_of::<Gdt>() as u16,
-            base: self,
-            #[cfg(target_pointer_width = "32")]
-            _pad: [0; 4],
-        };
-
-        unsafe {
-            asm!("lgdt [{}]", in(reg) &pointer, options(readonly, nostack, preserves_flags));
-        }
+        load_table(self as *const Gdt as *const GdtEntry, size_of::<Gdt>());
     }
 
     pub const fn protected_mode() -> Gdt {
@@ -194,3 +200,44 @@ impl Gdt {
         }
     }
 }
+
+/// The GDT the Linux boot protocol asks for, with the code segment at
+/// [`LinuxGdt::CODE_SELECTOR`] and the data segment at [`LinuxGdt::DATA_SELECTOR`]
+#[derive(Debug)]
+// Gets written directly to memory
+#[allow(dead_code)]
+pub struct LinuxGdt {
+    null: [GdtEntry; 2],
+    code: GdtEntry,
+    data: GdtEntry,
+}
+
+impl LinuxGdt {
+    /// `__BOOT_CS`
+    pub const CODE_SELECTOR: u16 = 0x10;
+    /// `__BOOT_DS`
+    pub const DATA_SELECTOR: u16 = 0x18;
+
+    pub fn load(&'static self) {
+        load_table(
+            self as *const LinuxGdt as *const GdtEntry,
+            size_of::<LinuxGdt>(),
+        );
+    }
+
+    pub const fn protected_mode() -> LinuxGdt {
+        LinuxGdt {
+            null: [GdtEntry::null(), GdtEntry::null()],
+            code: GdtEntry::code_32(),
+            data: GdtEntry::data_32(),
+        }
+    }
+
+    pub const fn long_mode() -> LinuxGdt {
+        LinuxGdt {
+            null: [GdtEntry::null(), GdtEntry::null()],
+            code: GdtEntry::code_64(),
+            data: GdtEntry::data_64(),
+        }
+    }
+}
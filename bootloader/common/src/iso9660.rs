@@ -0,0 +1,154 @@
+//! Minimal read only ISO 9660 driver, enough to find and read files in the root directory of a
+//! CD we were booted from
+
+use crate::disk::{DiskError, SectorReader, SECTOR_SIZE};
+use crate::CD_SECTOR_BYTES;
+
+/// CD sector of the first volume descriptor, everything before it is the system area
+pub const VOLUME_DESCRIPTORS_LBA: u32 = 16;
+/// Identifier every volume descriptor has after its type
+pub const STANDARD_ID: &[u8; 5] = b"CD001";
+/// Volume descriptor type of the El Torito boot record
+pub const TYPE_BOOT_RECORD: u8 = 0;
+/// Volume descriptor type of the primary volume descriptor
+pub const TYPE_PRIMARY: u8 = 1;
+/// Volume descriptor type ending the set
+pub const TYPE_TERMINATOR: u8 = 255;
+/// Offset of the root directory record in the primary volume descriptor
+pub const ROOT_RECORD_OFFSET: usize = 156;
+/// Directory record flag of subdirectories
+pub const FLAG_DIRECTORY: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoError {
+    Disk(DiskError),
+    /// There is no primary volume descriptor
+    NotIso9660,
+    /// There is no file with the given name
+    NotFound,
+}
+
+impl From<DiskError> for IsoError {
+    fn from(e: DiskError) -> Self {
+        IsoError::Disk(e)
+    }
+}
+
+/// A file or directory, stored in one contiguous extent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    /// First CD sector
+    pub lba: u32,
+    /// Size in bytes
+    pub size: u32,
+}
+
+impl Extent {
+    /// Reads the extent out of a directory record
+    fn from_record(record: &[u8]) -> Self {
+        let u32_at = |at: usize| u32::from_le_bytes(record[at..at + 4].try_into().unwrap());
+        Extent {
+            lba: u32_at(2),
+            size: u32_at(10),
+        }
+    }
+}
+
+/// Whether the identifier of a directory record names the file `name`. Identifiers end in a
+/// `;1` version, and names without an extension keep the dot.
+pub fn matches_name(identifier: &[u8], name: &str) -> bool {
+    let identifier = match identifier.iter().position(|c| *c == b';') {
+        Some(end) => &identifier[..end],
+        None => identifier,
+    };
+    let identifier = identifier.strip_suffix(b".").unwrap_or(identifier);
+    identifier == name.as_bytes()
+}
+
+/// An opened ISO 9660 volume
+pub struct Iso9660<'a, R: SectorReader> {
+    reader: &'a mut R,
+    /// Scratch space for descriptors, directories and file contents
+    sector: &'a mut [u8; CD_SECTOR_BYTES],
+    root: Extent,
+}
+
+impl<'a, R: SectorReader> Iso9660<'a, R> {
+    /// Finds the primary volume descriptor
+    pub fn open(
+        reader: &'a mut R,
+        sector: &'a mut [u8; CD_SECTOR_BYTES],
+    ) -> Result<Self, IsoError> {
+        let mut lba = VOLUME_DESCRIPTORS_LBA;
+        loop {
+            read_sector(reader, lba, sector)?;
+            if sector[1..6] != *STANDARD_ID {
+                return Err(IsoError::NotIso9660);
+            }
+            match sector[0] {
+                TYPE_PRIMARY => break,
+                TYPE_TERMINATOR => return Err(IsoError::NotIso9660),
+                _ => lba += 1,
+            }
+        }
+
+        let root = Extent::from_record(&sector[ROOT_RECORD_OFFSET..]);
+        Ok(Iso9660 {
+            reader,
+            sector,
+            root,
+        })
+    }
+
+    /// Finds a file in the root directory by name, like `KERNEL.ELF`
+    pub fn find(&mut self, name: &str) -> Result<Extent, IsoError> {
+        for ii in 0..self.root.size.div_ceil(CD_SECTOR_BYTES as u32) {
+            read_sector(self.reader, self.root.lba + ii, self.sector)?;
+            // Records don't cross sectors, the rest of a sector after the last one is zero
+            let mut at = 0;
+            while at < CD_SECTOR_BYTES && self.sector[at] != 0 {
+                let record = &self.sector[at..at + self.sector[at] as usize];
+                let identifier = &record[33..33 + record[32] as usize];
+                if record[25] & FLAG_DIRECTORY == 0 && matches_name(identifier, name) {
+                    return Ok(Extent::from_record(record));
+                }
+                at += record.len();
+            }
+        }
+        Err(IsoError::NotFound)
+    }
+
+    /// Reads a file, calling `f` with consecutive pieces of it. Every piece is a whole CD
+    /// sector except the last one.
+    pub fn read(&mut self, file: &Extent, mut f: impl FnMut(&[u8])) -> Result<(), IsoError> {
+        let mut remaining = file.size as usize;
+        let mut lba = file.lba;
+        while remaining > 0 {
+            read_sector(self.reader, lba, self.sector)?;
+            let len = remaining.min(CD_SECTOR_BYTES);
+            f(&self.sector[..len]);
+            remaining -= len;
+            lba += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a CD sector, `SectorReader`s count in 512 byte sectors
+fn read_sector(
+    reader: &mut impl SectorReader,
+    lba: u32,
+    sector: &mut [u8; CD_SECTOR_BYTES],
+) -> Result<(), DiskError> {
+    let per_sector = (CD_SECTOR_BYTES / SECTOR_SIZE) as u64;
+    reader.read_sectors(lba as u64 * per_sector, sector)
+}
+
+#[test]
+fn test_matches_name() {
+    assert!(matches_name(b"KERNEL.ELF;1", "KERNEL.ELF"));
+    assert!(matches_name(b"README.;1", "README"));
+    assert!(matches_name(b"BOOT.CFG", "BOOT.CFG"));
+    assert!(!matches_name(b"KERNEL.ELF;1", "KERNEL"));
+    assert!(!matches_name(b"\0", "KERNEL.ELF"));
+}
@@ -4,49 +4,38 @@
 #[cfg(test)]
 use core::assert;
 
-use core::mem::size_of;
-
 #[cfg(feature = "real_mode")]
 pub mod real_mode;
 
 #[cfg(feature = "protected_mode")]
 pub mod protected_mode;
 
+/// Stage sizes and addresses, shared with the stage build scripts
+#[path = "../../layout.rs"]
+pub mod layout;
+pub use layout::*;
+
+pub mod bios_info;
+pub use bios_info::*;
+
+pub mod config;
+pub mod cp437;
+pub mod disk;
+pub mod edid;
+pub mod elf;
+pub mod fat;
+pub mod font;
 pub mod gdt;
-
-/// Info passed to the kernel
-#[repr(C)]
-pub struct BiosInfo {
-    pub memory_map_start: *const u8,
-    pub memory_map_count: usize,
-    pub framebuffer: FrameBufferInfo,
-}
-
-/// Information about the framebuffer to write to the screen
-#[repr(C)]
-pub struct FrameBufferInfo {
-    pub width: u16,
-    pub height: u16,
-    pub depth: u8,
-    pub line_bytes: u16,
-    pub framebuffer: *mut u8,
-    pub font: &'static [u8; 0x1000],
-}
-
-/// The start of the first stage in memory, defined by BIOS
-pub const STAGE_0_START: usize = 0x7c00;
-/// Number of 512 byte sections stage 0 takes up
-pub const STAGE_0_SECTIONS: usize = 1;
-/// Number of 512 byte sections stage 1 takes up
-pub const STAGE_1_SECTIONS: usize = 0x30;
-/// Number of 512 byte sections stage 2 takes up
-pub const STAGE_2_SECTIONS: usize = 0x10;
-/// Number of 512 byte sections stage 3 takes up
-pub const STAGE_3_SECTIONS: usize = 0x20;
-
-/// Total number of boot sectors we need to read. Not including the 0th boot sector loaded into
-/// memory from the bios.
-pub const SECTORS_TO_READ: usize = STAGE_1_SECTIONS + STAGE_2_SECTIONS + STAGE_3_SECTIONS;
+pub mod iso9660;
+pub mod linux;
+pub mod memory;
+pub mod menu;
+pub mod multiboot2;
+pub mod partition;
+pub mod qemu;
+pub mod serial;
+pub mod splash;
+pub mod tga;
 
 // Pointers to memory. These should not overlap and be documented how large each of the sections
 // are needed
@@ -62,21 +51,65 @@ pub const PML4T_START: *mut [u64; 0x200] = 0x1000 as *mut [u64; 0x200];
 pub const PDPT_START: *mut [u64; 0x200] = 0x2000 as *mut [u64; 0x200];
 /// Start of the PDT,   takes up 0x1000 = 8 * 0x200 bytes
 pub const PDT_START: *mut [u64; 0x200] = 0x3000 as *mut [u64; 0x200];
-/// Start of the PT,    takes up 0x1000 = 8 * 0x200 bytes
-pub const PT_START: *mut [u64; 0x200] = 0x4000 as *mut [u64; 0x200];
+/// Start of the PDT for the GiB the framebuffer is in, takes up 0x1000 = 8 * 0x200 bytes
+pub const FRAMEBUFFER_PDT_START: *mut [u64; 0x200] = 0x4000 as *mut [u64; 0x200];
+
+/// Where stage 1 writes the info passed to the kernel, takes up to 0x1000 bytes
+pub const BIOS_INFO: *mut BiosInfo = BIOS_INFO_ADDRESS as *mut BiosInfo;
+
+/// Where stage 1 loads the kernel file to, see [`KERNEL_FILE_ADDRESS`]
+pub const KERNEL_FILE_START: *mut u8 = KERNEL_FILE_ADDRESS as *mut u8;
+
+/// Where stage 1 loads the initrd to, the first page after the kernel file
+pub const fn initrd_file_start(kernel_size: u32) -> *mut u8 {
+    (KERNEL_FILE_ADDRESS + (kernel_size as usize).next_multiple_of(0x1000)) as *mut u8
+}
+
+/// Start of the memory map, up to [`MEMORY_MAP_ENTRIES`] [`MemoryMapEntry`]s
+pub const MEMORY_MAP_START: *mut u8 = MEMORY_MAP_ADDRESS as *mut u8;
+
+#[test]
+fn test_stages_readable_with_chs() {
+    // Stage 0 falls back to CHS without extensions, even the smallest hard disk geometry has to
+    // reach the last sector of the stages
+    let geometry = disk::Geometry {
+        heads: 2,
+        sectors_per_track: 18,
+    };
+    assert!(geometry
+        .chs((STAGES_START_LBA + SECTORS_TO_READ - 1) as u32)
+        .is_some());
+}
+
+#[test]
+fn test_page_tables_free() {
+    assert!(FRAMEBUFFER_PDT_START as usize + 0x1000 <= BIOS_INFO_ADDRESS);
+}
+
+#[test]
+fn test_bios_info_fits() {
+    const { assert!(BIOS_INFO_ADDRESS + size_of::<BiosInfo>() <= MEMORY_MAP_ADDRESS) };
+}
 
-/// Pointer to the bios info. Can't get exact size without unstable feature
-pub const BIOS_INFO: *const BiosInfo = (0x5006 + 6 * 50) as *const BiosInfo;
-/// Next thing
-pub const NEXT: *const u8 = ((0x5006 + 6 * 50) + size_of::<BiosInfo>()) as *const u8;
+#[test]
+fn test_memory_map_fits() {
+    let end = MEMORY_MAP_ADDRESS + MEMORY_MAP_ENTRIES * size_of::<MemoryMapEntry>();
+    assert!(end <= STAGE_0_START);
+}
 
-/// Start of the memory map, each entry is 24 bytes, number of entries is not known at runtime, but
-/// in the emulator it is 7 entries which would be 7*24=168 bytes
-pub const MEMORY_MAP_START: *mut u8 = 0x6000 as *mut u8;
+#[test]
+fn test_multiboot_info_free() {
+    const { assert!(STAGES_END <= MULTIBOOT_INFO_ADDRESS) };
+    const { assert!(MULTIBOOT_INFO_ADDRESS + MULTIBOOT_INFO_SIZE <= 0x80000) };
+    const { assert!(MULTIBOOT_INFO_ADDRESS.is_multiple_of(8)) };
+}
 
 #[test]
-fn test_sectors_readable() {
-    assert!(SECTORS_TO_READ < u8::MAX as usize);
+fn test_linux_boot_params_free() {
+    const { assert!(MULTIBOOT_INFO_ADDRESS + MULTIBOOT_INFO_SIZE <= LINUX_BOOT_PARAMS_ADDRESS) };
+    const { assert!(LINUX_BOOT_PARAMS_ADDRESS + linux::BOOT_PARAMS_SIZE <= LINUX_CMDLINE_ADDRESS) };
+    // The command line has to be below the EBDA
+    const { assert!(LINUX_CMDLINE_ADDRESS + LINUX_CMDLINE_SIZE <= 0x80000) };
 }
 
 #[test]
@@ -84,5 +117,8 @@ fn test_pages_aligned() {
     assert!(PML4T_START as u64 % 4096 == 0, "Page not 4096 aligned");
     assert!(PDPT_START as u64 % 4096 == 0, "Page not 4096 aligned");
     assert!(PDT_START as u64 % 4096 == 0, "Page not 4096 aligned");
-    assert!(PT_START as u64 % 4096 == 0, "Page not 4096 aligned");
+    assert!(
+        FRAMEBUFFER_PDT_START as u64 % 4096 == 0,
+        "Page not 4096 aligned"
+    );
 }
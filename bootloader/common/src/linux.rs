@@ -0,0 +1,402 @@
+//! The Linux x86 boot protocol, <https://docs.kernel.org/arch/x86/boot.html>.
+//!
+//! A bzImage starts with the real mode setup code, whose header describes the protected mode
+//! kernel after it. The real mode setup is never run: stage 2 copies the protected mode kernel
+//! above 1 MiB, fills in the `boot_params` "zero page" the setup code would have, and jumps
+//! straight to the 32 or 64 bit entry point with the zero page's address in `esi`/`rsi`.
+
+use crate::multiboot2::basic_memory;
+use crate::{FrameBufferInfo, MemoryMapEntry};
+
+/// Size of `boot_params`
+pub const BOOT_PARAMS_SIZE: usize = 0x1000;
+/// The 64 bit entry point is this far into the protected mode kernel
+pub const ENTRY_64_OFFSET: u32 = 0x200;
+/// Oldest boot protocol supported, 2.10 added `pref_address` and `init_size`
+pub const MIN_VERSION: u16 = 0x020a;
+
+/// `type_of_loader` for bootloaders without an assigned id
+const LOADER_UNDEFINED: u8 = 0xff;
+/// `loadflags` bit saying the protected mode kernel is loaded at 1 MiB, which is a bzImage
+const LOADED_HIGH: u8 = 1 << 0;
+/// `xloadflags` bit saying the kernel has the 64 bit entry point at 0x200
+const XLF_KERNEL_64: u16 = 1 << 0;
+/// `screen_info.orig_video_isVGA` of a VESA linear framebuffer
+const VIDEO_TYPE_VLFB: u8 = 0x23;
+/// `screen_info.capabilities` bit saying `ext_lfb_base` is valid
+const VIDEO_CAPABILITY_64BIT_BASE: u32 = 1 << 1;
+/// Number of entries in `boot_params.e820_table`
+const E820_MAX_ENTRIES: usize = 128;
+
+// Offsets of the setup header fields, the same in the file and in boot_params
+const SETUP_SECTS: usize = 0x1f1;
+const BOOT_FLAG: usize = 0x1fe;
+const JUMP: usize = 0x200;
+const HEADER: usize = 0x202;
+const VERSION: usize = 0x206;
+const TYPE_OF_LOADER: usize = 0x210;
+const LOADFLAGS: usize = 0x211;
+const CODE32_START: usize = 0x214;
+const RAMDISK_IMAGE: usize = 0x218;
+const RAMDISK_SIZE: usize = 0x21c;
+const CMD_LINE_PTR: usize = 0x228;
+const INITRD_ADDR_MAX: usize = 0x22c;
+const RELOCATABLE_KERNEL: usize = 0x234;
+const XLOADFLAGS: usize = 0x236;
+const CMDLINE_SIZE: usize = 0x238;
+const PREF_ADDRESS: usize = 0x258;
+const INIT_SIZE: usize = 0x260;
+
+// Offsets of the boot_params fields outside of the setup header
+const SCREEN_INFO: usize = 0x000;
+const EXT_RAMDISK_IMAGE: usize = 0x0c0;
+const EXT_RAMDISK_SIZE: usize = 0x0c4;
+const EXT_CMD_LINE_PTR: usize = 0x0c8;
+const ALT_MEM_K: usize = 0x1e0;
+const E820_ENTRIES: usize = 0x1e8;
+const E820_TABLE: usize = 0x2d0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinuxError {
+    /// The file is smaller than its setup header says
+    TooShort,
+    /// Boot protocol older than [`MIN_VERSION`]
+    OldProtocol(u16),
+    /// A zImage, which is loaded below 1 MiB
+    NotLoadedHigh,
+    /// The command line is longer than the kernel takes
+    CmdlineTooLong,
+}
+
+/// The parts of the setup header needed to load the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupHeader {
+    /// Boot protocol version, major in the high byte
+    pub version: u16,
+    /// Offset of the protected mode kernel in the file, after the real mode setup code
+    pub kernel_offset: usize,
+    /// Where the protected mode kernel is loaded, and its 32 bit entry point
+    pub code32_start: u32,
+    /// Highest address the initrd can end at
+    pub initrd_addr_max: u32,
+    pub relocatable: bool,
+    /// The kernel has a 64 bit entry point
+    pub has_entry_64: bool,
+    /// Longest command line the kernel takes, not counting the nul
+    pub cmdline_size: u32,
+    /// Where the kernel would like to be, it moves itself here if loaded elsewhere
+    pub pref_address: u64,
+    /// Bytes of memory the kernel needs from where it is loaded, or `pref_address` if it
+    /// moves itself there
+    pub init_size: u32,
+    /// End of the setup header in the file
+    header_end: usize,
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+impl SetupHeader {
+    /// Whether `kernel` looks like a Linux kernel image at all
+    pub fn is_linux(kernel: &[u8]) -> bool {
+        kernel.len() > HEADER + 4
+            && u16_at(kernel, BOOT_FLAG) == 0xaa55
+            && kernel[HEADER..HEADER + 4] == *b"HdrS"
+    }
+
+    /// Reads the setup header of a bzImage, which has to pass [`SetupHeader::is_linux`]
+    pub fn parse(kernel: &[u8]) -> Result<SetupHeader, LinuxError> {
+        let version = u16_at(kernel, VERSION);
+        if version < MIN_VERSION {
+            return Err(LinuxError::OldProtocol(version));
+        }
+        // The header ends where the jump at its start goes to
+        let header_end = HEADER + kernel[JUMP + 1] as usize;
+        if header_end < INIT_SIZE + 4 || header_end > kernel.len() {
+            return Err(LinuxError::TooShort);
+        }
+        if kernel[LOADFLAGS] & LOADED_HIGH == 0 {
+            return Err(LinuxError::NotLoadedHigh);
+        }
+        // 0 means the old default of 4
+        let setup_sects = match kernel[SETUP_SECTS] {
+            0 => 4,
+            sects => sects as usize,
+        };
+        let kernel_offset = (setup_sects + 1) * 0x200;
+        if kernel_offset >= kernel.len() {
+            return Err(LinuxError::TooShort);
+        }
+
+        Ok(SetupHeader {
+            version,
+            kernel_offset,
+            code32_start: u32_at(kernel, CODE32_START),
+            initrd_addr_max: u32_at(kernel, INITRD_ADDR_MAX),
+            relocatable: kernel[RELOCATABLE_KERNEL] != 0,
+            // xloadflags were added in 2.12
+            has_entry_64: version >= 0x020c && u16_at(kernel, XLOADFLAGS) & XLF_KERNEL_64 != 0,
+            cmdline_size: u32_at(kernel, CMDLINE_SIZE),
+            pref_address: u64_at(kernel, PREF_ADDRESS),
+            init_size: u32_at(kernel, INIT_SIZE),
+            header_end,
+        })
+    }
+
+    /// End of the memory the kernel uses while starting up, which the initrd has to be above.
+    /// Relocatable kernels loaded below `pref_address` decompress themselves there.
+    pub fn reserved_end(&self) -> u64 {
+        let start = (self.code32_start as u64).max(self.pref_address);
+        start + self.init_size as u64
+    }
+}
+
+/// The zero page, filled in as the real mode setup code would
+pub struct BootParams<'a> {
+    page: &'a mut [u8; BOOT_PARAMS_SIZE],
+}
+
+impl<'a> BootParams<'a> {
+    /// Zeroes `page` and copies the setup header from the kernel file into it
+    pub fn new(page: &'a mut [u8; BOOT_PARAMS_SIZE], kernel: &[u8], header: &SetupHeader) -> Self {
+        page.fill(0);
+        page[SETUP_SECTS..header.header_end]
+            .copy_from_slice(&kernel[SETUP_SECTS..header.header_end]);
+        page[TYPE_OF_LOADER] = LOADER_UNDEFINED;
+        BootParams { page }
+    }
+
+    fn put_u16(&mut self, at: usize, value: u16) {
+        self.page[at..at + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(&mut self, at: usize, value: u32) {
+        self.page[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Where the nul terminated command line is, and how long it is without the nul
+    pub fn cmdline(&mut self, address: u64, len: usize) -> Result<&mut Self, LinuxError> {
+        if len > u32_at(&self.page[..], CMDLINE_SIZE) as usize {
+            return Err(LinuxError::CmdlineTooLong);
+        }
+        self.put_u32(CMD_LINE_PTR, address as u32);
+        self.put_u32(EXT_CMD_LINE_PTR, (address >> 32) as u32);
+        Ok(self)
+    }
+
+    pub fn initrd(&mut self, address: u64, size: u64) -> &mut Self {
+        self.put_u32(RAMDISK_IMAGE, address as u32);
+        self.put_u32(EXT_RAMDISK_IMAGE, (address >> 32) as u32);
+        self.put_u32(RAMDISK_SIZE, size as u32);
+        self.put_u32(EXT_RAMDISK_SIZE, (size >> 32) as u32);
+        self
+    }
+
+    /// Copies in the E820 memory map, dropping entries past the 128 the table has room for
+    pub fn memory_map(&mut self, entries: &[MemoryMapEntry]) -> &mut Self {
+        let count = entries.len().min(E820_MAX_ENTRIES);
+        for (ii, entry) in entries[..count].iter().enumerate() {
+            let at = E820_TABLE + ii * 20;
+            self.page[at..at + 8].copy_from_slice(&entry.base.to_le_bytes());
+            self.page[at + 8..at + 16].copy_from_slice(&entry.length.to_le_bytes());
+            self.put_u32(at + 16, entry.region_type);
+        }
+        self.page[E820_ENTRIES] = count as u8;
+
+        // KiB above 1 MiB, for kernels that don't read the E820 table
+        let (_, upper) = basic_memory(entries);
+        self.put_u32(ALT_MEM_K, upper);
+        self.put_u16(SCREEN_INFO + 0x02, upper.min(u16::MAX as u32) as u16);
+        self
+    }
+
+    /// Fills in `screen_info` for a VESA framebuffer, or for 80x25 VGA text mode if there is
+    /// no framebuffer
+    pub fn screen_info(&mut self, framebuffer: &FrameBufferInfo) -> &mut Self {
+        let at = SCREEN_INFO;
+        // orig_video_points, the font height
+        self.put_u16(at + 0x10, 16);
+        if framebuffer.address == 0 {
+            // orig_video_mode, orig_video_cols, orig_video_lines and orig_video_isVGA
+            self.page[at + 0x06] = 3;
+            self.page[at + 0x07] = 80;
+            self.page[at + 0x0e] = 25;
+            self.page[at + 0x0f] = 1;
+            return self;
+        }
+
+        let format = &framebuffer.format;
+        let size = framebuffer.pitch as u64 * framebuffer.height as u64;
+        self.page[at + 0x0f] = VIDEO_TYPE_VLFB;
+        self.put_u16(at + 0x12, framebuffer.width as u16);
+        self.put_u16(at + 0x14, framebuffer.height as u16);
+        self.put_u16(at + 0x16, framebuffer.bits_per_pixel as u16);
+        self.put_u32(at + 0x18, framebuffer.address as u32);
+        // In 64 KiB units for VESA framebuffers
+        self.put_u32(at + 0x1c, size.div_ceil(0x10000) as u32);
+        self.put_u16(at + 0x24, framebuffer.pitch as u16);
+        self.page[at + 0x26..at + 0x2e].copy_from_slice(&[
+            format.red_size,
+            format.red_shift,
+            format.green_size,
+            format.green_shift,
+            format.blue_size,
+            format.blue_shift,
+            format.reserved_size,
+            format.reserved_shift,
+        ]);
+        if framebuffer.address >> 32 != 0 {
+            self.put_u32(at + 0x36, VIDEO_CAPABILITY_64BIT_BASE);
+            self.put_u32(at + 0x3a, (framebuffer.address >> 32) as u32);
+        }
+        self
+    }
+}
+
+/// Highest page aligned address an initrd of `size` bytes fits at in usable memory, starting at
+/// or after `lowest` and ending at or before `highest`
+pub fn initrd_address(
+    memory_map: &[MemoryMapEntry],
+    size: u64,
+    lowest: u64,
+    highest: u64,
+) -> Option<u64> {
+    memory_map
+        .iter()
+        .filter(|entry| entry.region_type == 1)
+        .filter_map(|entry| {
+            let end = (entry.base + entry.length).min(highest);
+            let start = end.checked_sub(size)? & !0xfff;
+            (start >= entry.base.max(lowest)).then_some(start)
+        })
+        .max()
+}
+
+/// Builds a bzImage with 4 sectors of setup code and `kernel` after it
+#[cfg(test)]
+fn test_bzimage(version: u16, kernel: &[u8]) -> Vec<u8> {
+    let mut image = vec![0; 5 * 0x200];
+    image[SETUP_SECTS] = 4;
+    image[BOOT_FLAG..BOOT_FLAG + 2].copy_from_slice(&0xaa55u16.to_le_bytes());
+    // Short jump over the header
+    image[JUMP] = 0xeb;
+    image[JUMP + 1] = (INIT_SIZE + 4 - HEADER) as u8;
+    image[HEADER..HEADER + 4].copy_from_slice(b"HdrS");
+    image[VERSION..VERSION + 2].copy_from_slice(&version.to_le_bytes());
+    image[LOADFLAGS] = LOADED_HIGH;
+    image[CODE32_START..CODE32_START + 4].copy_from_slice(&0x100000u32.to_le_bytes());
+    image[INITRD_ADDR_MAX..INITRD_ADDR_MAX + 4].copy_from_slice(&0x7fffffffu32.to_le_bytes());
+    image[RELOCATABLE_KERNEL] = 1;
+    image[XLOADFLAGS..XLOADFLAGS + 2].copy_from_slice(&XLF_KERNEL_64.to_le_bytes());
+    image[CMDLINE_SIZE..CMDLINE_SIZE + 4].copy_from_slice(&0x7ffu32.to_le_bytes());
+    image[PREF_ADDRESS..PREF_ADDRESS + 8].copy_from_slice(&0x1000000u64.to_le_bytes());
+    image[INIT_SIZE..INIT_SIZE + 4].copy_from_slice(&0x800000u32.to_le_bytes());
+    image.extend_from_slice(kernel);
+    image
+}
+
+#[test]
+fn test_parse_setup_header() {
+    let image = test_bzimage(0x020f, b"protected mode kernel");
+    assert!(SetupHeader::is_linux(&image));
+    assert!(!SetupHeader::is_linux(&image[..0x100]));
+
+    let header = SetupHeader::parse(&image).unwrap();
+    assert_eq!(header.kernel_offset, 5 * 0x200);
+    assert_eq!(&image[header.kernel_offset..], b"protected mode kernel");
+    assert_eq!(header.code32_start, 0x100000);
+    assert!(header.relocatable && header.has_entry_64);
+    assert_eq!(header.reserved_end(), 0x1800000);
+
+    assert_eq!(
+        SetupHeader::parse(&test_bzimage(0x0209, b"")),
+        Err(LinuxError::OldProtocol(0x0209))
+    );
+    let mut zimage = image.clone();
+    zimage[LOADFLAGS] = 0;
+    assert_eq!(SetupHeader::parse(&zimage), Err(LinuxError::NotLoadedHigh));
+}
+
+#[test]
+fn test_boot_params() {
+    let image = test_bzimage(0x020f, b"kernel");
+    let header = SetupHeader::parse(&image).unwrap();
+    let memory_map = [
+        MemoryMapEntry {
+            base: 0,
+            length: 0x9fc00,
+            region_type: 1,
+            attributes: 0,
+        },
+        MemoryMapEntry {
+            base: 0x100000,
+            length: 0x7ee0000,
+            region_type: 1,
+            attributes: 0,
+        },
+    ];
+
+    let mut page = [0xff; BOOT_PARAMS_SIZE];
+    let mut params = BootParams::new(&mut page, &image, &header);
+    params
+        .cmdline(0x21000, 12)
+        .unwrap()
+        .initrd(0x7000000, 0x1234)
+        .memory_map(&memory_map);
+    assert_eq!(
+        params.cmdline(0x21000, 0x800).err(),
+        Some(LinuxError::CmdlineTooLong)
+    );
+
+    // Cleared, apart from what was filled in
+    assert!(page[0x100..ALT_MEM_K].iter().all(|&byte| byte == 0));
+    assert_eq!(&page[HEADER..HEADER + 4], b"HdrS");
+    assert_eq!(page[TYPE_OF_LOADER], LOADER_UNDEFINED);
+    assert_eq!(u32_at(&page, CMD_LINE_PTR), 0x21000);
+    assert_eq!(u32_at(&page, RAMDISK_IMAGE), 0x7000000);
+    assert_eq!(u32_at(&page, RAMDISK_SIZE), 0x1234);
+    assert_eq!(page[E820_ENTRIES], 2);
+    assert_eq!(u64_at(&page, E820_TABLE + 20), 0x100000);
+    assert_eq!(u32_at(&page, E820_TABLE + 20 + 16), 1);
+    assert_eq!(u32_at(&page, ALT_MEM_K), 0x7ee0000 / 1024);
+}
+
+#[test]
+fn test_initrd_address() {
+    let memory_map = [
+        MemoryMapEntry {
+            base: 0x100000,
+            length: 0x7ee0000,
+            region_type: 1,
+            attributes: 0,
+        },
+        MemoryMapEntry {
+            base: 0x7fe0000,
+            length: 0x20000,
+            region_type: 2,
+            attributes: 0,
+        },
+    ];
+    // As high as it goes, page aligned
+    assert_eq!(
+        initrd_address(&memory_map, 0x1800, 0x1800000, u64::MAX),
+        Some(0x7fde000)
+    );
+    assert_eq!(
+        initrd_address(&memory_map, 0x1000, 0x1800000, 0x2000000),
+        Some(0x1fff000)
+    );
+    assert_eq!(
+        initrd_address(&memory_map, 0x8000000, 0x1800000, u64::MAX),
+        None
+    );
+}
@@ -0,0 +1,195 @@
+//! Typed memory regions, and turning whatever the BIOS reports into a sorted map without overlaps
+
+use crate::MemoryMapEntry;
+
+const KIB: u64 = 0x400;
+const MIB: u64 = 0x100000;
+
+/// What a region of physical memory can be used for, with the E820 type numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Free to use, type 1
+    Usable,
+    /// In use by the firmware or hardware, type 2
+    Reserved,
+    /// ACPI tables, usable once they are read, type 3
+    AcpiReclaimable,
+    /// ACPI non-volatile storage, has to be kept across sleep states, type 4
+    AcpiNvs,
+    /// Faulty memory, type 5
+    Bad,
+    /// Any other type, treated like reserved memory
+    Other(u32),
+}
+
+impl MemoryKind {
+    pub fn from_e820(region_type: u32) -> Self {
+        match region_type {
+            1 => MemoryKind::Usable,
+            2 => MemoryKind::Reserved,
+            3 => MemoryKind::AcpiReclaimable,
+            4 => MemoryKind::AcpiNvs,
+            5 => MemoryKind::Bad,
+            other => MemoryKind::Other(other),
+        }
+    }
+
+    pub fn e820_type(&self) -> u32 {
+        match self {
+            MemoryKind::Usable => 1,
+            MemoryKind::Reserved => 2,
+            MemoryKind::AcpiReclaimable => 3,
+            MemoryKind::AcpiNvs => 4,
+            MemoryKind::Bad => 5,
+            MemoryKind::Other(other) => *other,
+        }
+    }
+
+    /// Which kind wins where regions overlap, the least usable one
+    fn priority(&self) -> u8 {
+        match self {
+            MemoryKind::Usable => 0,
+            MemoryKind::AcpiReclaimable => 1,
+            MemoryKind::AcpiNvs => 2,
+            MemoryKind::Reserved | MemoryKind::Other(_) => 3,
+            MemoryKind::Bad => 4,
+        }
+    }
+}
+
+/// A region of physical memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u64,
+    /// Size in bytes
+    pub len: u64,
+    pub kind: MemoryKind,
+}
+
+impl MemoryRegion {
+    pub const EMPTY: MemoryRegion = MemoryRegion {
+        base: 0,
+        len: 0,
+        kind: MemoryKind::Reserved,
+    };
+
+    /// First address after the region
+    pub fn end(&self) -> u64 {
+        self.base.saturating_add(self.len)
+    }
+
+    /// The region as stored in the memory map handed to the kernel
+    pub fn to_entry(&self) -> MemoryMapEntry {
+        MemoryMapEntry {
+            base: self.base,
+            length: self.len,
+            region_type: self.kind.e820_type(),
+            // ACPI 3.0 "enabled" bit, entries without it were already dropped
+            attributes: 1,
+        }
+    }
+}
+
+/// Calls `f` with the regions sorted by address, with overlaps resolved in favor of the least
+/// usable kind and adjacent regions of the same kind merged. Empty regions are dropped.
+///
+/// Every region start and end can split the map, so there are at most twice as many regions
+/// coming out as going in.
+pub fn sanitize(regions: &[MemoryRegion], mut f: impl FnMut(MemoryRegion)) {
+    let mut pending: Option<MemoryRegion> = None;
+    let mut start = regions
+        .iter()
+        .filter(|region| region.len != 0)
+        .map(|region| region.base)
+        .min();
+    while let Some(from) = start {
+        // Nothing changes until the next place a region starts or ends
+        let Some(to) = regions
+            .iter()
+            .flat_map(|region| [region.base, region.end()])
+            .filter(|&address| address > from)
+            .min()
+        else {
+            break;
+        };
+        let kind = regions
+            .iter()
+            .filter(|region| region.base <= from && from < region.end())
+            .map(|region| region.kind)
+            .max_by_key(MemoryKind::priority);
+
+        if let Some(kind) = kind {
+            match &mut pending {
+                Some(region) if region.kind == kind && region.end() == from => {
+                    region.len += to - from
+                }
+                _ => {
+                    let region = MemoryRegion {
+                        base: from,
+                        len: to - from,
+                        kind,
+                    };
+                    if let Some(done) = pending.replace(region) {
+                        f(done);
+                    }
+                }
+            }
+        }
+        start = Some(to);
+    }
+    if let Some(done) = pending {
+        f(done);
+    }
+}
+
+/// Memory map for BIOSes without E820, from the KiB of conventional memory below 640 KiB, the
+/// KiB of contiguous memory from 1 MiB, and the 64 KiB blocks above 16 MiB. The int 0x15 AX=0xE801 and
+/// AH=0x88 calls only report the usable memory, so that's all there is.
+pub fn legacy_map(conventional_kib: u16, low_kib: u16, high_blocks: u16) -> [MemoryRegion; 3] {
+    let usable = |base: u64, len: u64| MemoryRegion {
+        base,
+        len,
+        kind: MemoryKind::Usable,
+    };
+    [
+        usable(0, conventional_kib as u64 * KIB),
+        usable(MIB, low_kib as u64 * KIB),
+        usable(16 * MIB, high_blocks as u64 * 64 * KIB),
+    ]
+}
+
+#[test]
+fn test_sanitize() {
+    let region = |base: u64, len: u64, kind: MemoryKind| MemoryRegion { base, len, kind };
+    let regions = [
+        region(0x100000, 0x700000, MemoryKind::Usable),
+        region(0, 0x9fc00, MemoryKind::Usable),
+        // Reserved hole in the middle of usable memory
+        region(0x200000, 0x1000, MemoryKind::Reserved),
+        // Adjacent and overlapping usable regions
+        region(0x800000, 0x100000, MemoryKind::Usable),
+        region(0x880000, 0x100000, MemoryKind::Usable),
+        region(0x9fc00, 0x400, MemoryKind::Other(12)),
+        region(0x500000, 0, MemoryKind::Bad),
+    ];
+    let mut map = Vec::new();
+    sanitize(&regions, |region| map.push(region));
+    assert_eq!(
+        map,
+        [
+            region(0, 0x9fc00, MemoryKind::Usable),
+            region(0x9fc00, 0x400, MemoryKind::Other(12)),
+            region(0x100000, 0x100000, MemoryKind::Usable),
+            region(0x200000, 0x1000, MemoryKind::Reserved),
+            region(0x201000, 0x77f000, MemoryKind::Usable),
+        ]
+    );
+}
+
+#[test]
+fn test_legacy_map() {
+    let map = legacy_map(639, 15 * 1024, 0x100);
+    assert_eq!(map[0].end(), 639 * KIB);
+    assert_eq!((map[1].base, map[1].end()), (MIB, 16 * MIB));
+    assert_eq!((map[2].base, map[2].end()), (16 * MIB, 32 * MIB));
+}
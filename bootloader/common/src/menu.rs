@@ -0,0 +1,218 @@
+//! What the boot menu of stage 1 does with key presses and the BIOS timer, apart from reading the
+//! keyboard and drawing
+
+/// Ticks the BIOS timer counts in a day, after which it starts from 0 again
+pub const TICKS_PER_DAY: u32 = 0x1800b0;
+/// The BIOS timer ticks about 18.2 times a second
+const TICKS_PER_10_SECONDS: u32 = 182;
+
+/// Scan code of the up arrow
+const SCANCODE_UP: u8 = 0x48;
+/// Scan code of the down arrow
+const SCANCODE_DOWN: u8 = 0x50;
+/// Scan code of Home
+const SCANCODE_HOME: u8 = 0x47;
+/// Scan code of End
+const SCANCODE_END: u8 = 0x4f;
+const ENTER: u8 = b'\r';
+const ESCAPE: u8 = 0x1b;
+const BACKSPACE: u8 = 0x08;
+
+/// A key press the way int 0x16 reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub scancode: u8,
+    /// 0 for keys without a character, like the arrows
+    pub ascii: u8,
+}
+
+/// Time left before the default entry boots, counted in BIOS timer ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Countdown {
+    start: u32,
+    ticks: u32,
+}
+
+impl Countdown {
+    /// A countdown of `seconds` from the tick count `now`
+    pub fn new(seconds: u32, now: u32) -> Self {
+        Countdown {
+            start: now,
+            ticks: (seconds.saturating_mul(TICKS_PER_10_SECONDS)).div_ceil(10),
+        }
+    }
+
+    /// Whole seconds left at the tick count `now`, rounded up so it only shows 0 once it is over
+    pub fn seconds_left(&self, now: u32) -> u32 {
+        // The count goes back to 0 at midnight
+        let elapsed = if now >= self.start {
+            now - self.start
+        } else {
+            now + TICKS_PER_DAY - self.start
+        };
+        (self.ticks.saturating_sub(elapsed) * 10).div_ceil(TICKS_PER_10_SECONDS)
+    }
+}
+
+/// What the menu does after a key press
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    /// Nothing, or only the selection moved
+    None,
+    /// Boot the selected entry
+    Boot,
+    /// Edit the command line of the selected entry, then boot it
+    Edit,
+}
+
+/// The selection in a list of boot entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Menu {
+    selected: usize,
+    entries: usize,
+}
+
+impl Menu {
+    /// A menu of `entries` entries with the first one selected
+    pub fn new(entries: usize) -> Self {
+        Menu {
+            selected: 0,
+            entries,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Moves the selection with the arrows, Home and End, Enter boots and `e` edits
+    pub fn key(&mut self, key: Key) -> MenuAction {
+        match (key.scancode, key.ascii) {
+            (_, ENTER) => return MenuAction::Boot,
+            (_, b'e') => return MenuAction::Edit,
+            (SCANCODE_UP, 0) => self.selected = self.selected.saturating_sub(1),
+            (SCANCODE_DOWN, 0) => self.selected = (self.selected + 1).min(self.entries - 1),
+            (SCANCODE_HOME, 0) => self.selected = 0,
+            (SCANCODE_END, 0) => self.selected = self.entries - 1,
+            _ => {}
+        }
+        MenuAction::None
+    }
+}
+
+/// What the line editor does after a key press
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditAction {
+    /// Ignored, the line is as it was
+    None,
+    /// Added a character to the end
+    Insert(char),
+    /// Removed the last character
+    Erase,
+    /// Enter, use the line
+    Done,
+    /// Escape, go back to the menu
+    Cancel,
+}
+
+/// Edits a line of text in a fixed buffer, typing at the end and erasing with Backspace. Only
+/// printable ASCII can be typed, like the BIOS keyboard gives.
+pub struct LineEditor<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> LineEditor<'a> {
+    /// Starts out with as much of `text` as fits in `buffer`
+    pub fn new(buffer: &'a mut [u8], text: &str) -> Self {
+        let mut len = text.len().min(buffer.len());
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+        buffer[..len].copy_from_slice(&text.as_bytes()[..len]);
+        LineEditor { buffer, len }
+    }
+
+    pub fn key(&mut self, key: Key) -> EditAction {
+        match key.ascii {
+            ENTER => EditAction::Done,
+            ESCAPE => EditAction::Cancel,
+            BACKSPACE => {
+                let Some(c) = self.as_str().chars().next_back() else {
+                    return EditAction::None;
+                };
+                self.len -= c.len_utf8();
+                EditAction::Erase
+            }
+            c @ b' '..=b'~' if self.len < self.buffer.len() => {
+                self.buffer[self.len] = c;
+                self.len += 1;
+                EditAction::Insert(c as char)
+            }
+            _ => EditAction::None,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Made of a whole str and ASCII
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap()
+    }
+
+    /// The line, keeping the buffer borrowed for as long as it was given
+    pub fn into_str(self) -> &'a str {
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap()
+    }
+}
+
+#[test]
+fn test_countdown() {
+    let countdown = Countdown::new(5, 100);
+    assert_eq!(countdown.seconds_left(100), 5);
+    assert_eq!(countdown.seconds_left(100 + 19), 4);
+    assert_eq!(countdown.seconds_left(100 + 90), 1);
+    assert_eq!(countdown.seconds_left(100 + 91), 0);
+    assert_eq!(countdown.seconds_left(u32::MAX / 2), 0);
+
+    // Across midnight
+    let countdown = Countdown::new(1, TICKS_PER_DAY - 10);
+    assert_eq!(countdown.seconds_left(5), 1);
+    assert_eq!(countdown.seconds_left(9), 0);
+}
+
+#[test]
+fn test_menu_keys() {
+    let key = |scancode, ascii| Key { scancode, ascii };
+    let mut menu = Menu::new(3);
+    assert_eq!(menu.key(key(SCANCODE_UP, 0)), MenuAction::None);
+    assert_eq!(menu.selected(), 0);
+    menu.key(key(SCANCODE_DOWN, 0));
+    assert_eq!(menu.selected(), 1);
+    menu.key(key(SCANCODE_END, 0));
+    menu.key(key(SCANCODE_DOWN, 0));
+    assert_eq!(menu.selected(), 2);
+    assert_eq!(menu.key(key(0x12, b'e')), MenuAction::Edit);
+    assert_eq!(menu.key(key(0x1c, ENTER)), MenuAction::Boot);
+}
+
+#[test]
+fn test_line_editor() {
+    let key = |ascii| Key { scancode: 0, ascii };
+    let mut buffer = [0; 8];
+    // Cut at a character boundary
+    let mut editor = LineEditor::new(&mut buffer, "quiet é");
+    assert_eq!(editor.as_str(), "quiet é");
+    assert_eq!(editor.key(key(BACKSPACE)), EditAction::Erase);
+    assert_eq!(editor.as_str(), "quiet ");
+    assert_eq!(editor.key(key(b'x')), EditAction::Insert('x'));
+    assert_eq!(editor.key(key(b'y')), EditAction::Insert('y'));
+    assert_eq!(editor.key(key(b'z')), EditAction::None);
+    assert_eq!(editor.key(key(0x80)), EditAction::None);
+    assert_eq!(editor.key(key(ENTER)), EditAction::Done);
+    assert_eq!(editor.into_str(), "quiet xy");
+
+    let mut buffer = [0; 4];
+    assert_eq!(LineEditor::new(&mut buffer, "abcé").as_str(), "abc");
+    let mut editor = LineEditor::new(&mut buffer, "");
+    assert_eq!(editor.key(key(BACKSPACE)), EditAction::None);
+    assert_eq!(editor.key(key(ESCAPE)), EditAction::Cancel);
+}
@@ -0,0 +1,582 @@
+//! Multiboot2 kernels, <https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html>.
+//!
+//! A Multiboot2 kernel has a header somewhere in its first 32 KiB asking for where it should be
+//! loaded and what video mode it wants. Stage 2 loads it and builds the boot information
+//! structure (MBI) describing the machine, then jumps to it in 32 bit protected mode with
+//! [`BOOTLOADER_MAGIC`] in `eax` and the address of the MBI in `ebx`.
+
+use crate::{FrameBufferInfo, MemoryMapEntry};
+
+/// First field of the kernel's header
+pub const HEADER_MAGIC: u32 = 0xe852_50d6;
+/// Put in `eax` when jumping to the kernel
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+/// Name of the bootloader given to the kernel
+pub const BOOTLOADER_NAME: &str = "spenceros";
+
+/// The header has to be in this many bytes at the start of the file
+const SEARCH_LIMIT: usize = 0x8000;
+/// Size of the header before the tags
+const HEADER_SIZE: usize = 16;
+/// Architecture field for 32 bit protected mode i386
+const ARCHITECTURE_I386: u32 = 0;
+/// Tag flag saying the bootloader can ignore the tag
+const TAG_OPTIONAL: u16 = 1;
+
+// Header tag types
+const HEADER_TAG_END: u16 = 0;
+const HEADER_TAG_INFORMATION_REQUEST: u16 = 1;
+const HEADER_TAG_ADDRESS: u16 = 2;
+const HEADER_TAG_ENTRY_ADDRESS: u16 = 3;
+const HEADER_TAG_CONSOLE_FLAGS: u16 = 4;
+const HEADER_TAG_FRAMEBUFFER: u16 = 5;
+const HEADER_TAG_MODULE_ALIGN: u16 = 6;
+const HEADER_TAG_EFI_BOOT_SERVICES: u16 = 7;
+const HEADER_TAG_ENTRY_ADDRESS_EFI32: u16 = 8;
+const HEADER_TAG_ENTRY_ADDRESS_EFI64: u16 = 9;
+const HEADER_TAG_RELOCATABLE: u16 = 10;
+
+// Boot information tag types
+const INFO_TAG_END: u32 = 0;
+const INFO_TAG_CMDLINE: u32 = 1;
+const INFO_TAG_BOOTLOADER_NAME: u32 = 2;
+const INFO_TAG_BASIC_MEMORY: u32 = 4;
+const INFO_TAG_BOOT_DEVICE: u32 = 5;
+const INFO_TAG_MEMORY_MAP: u32 = 6;
+const INFO_TAG_FRAMEBUFFER: u32 = 8;
+
+/// Information tags the bootloader gives, anything else a kernel requires fails the boot
+const SUPPORTED_INFO: [u32; 7] = [
+    INFO_TAG_END,
+    INFO_TAG_CMDLINE,
+    INFO_TAG_BOOTLOADER_NAME,
+    INFO_TAG_BASIC_MEMORY,
+    INFO_TAG_BOOT_DEVICE,
+    INFO_TAG_MEMORY_MAP,
+    INFO_TAG_FRAMEBUFFER,
+];
+
+/// Framebuffer type of direct RGB color
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+/// Version of the memory map entries, which are the same as E820 entries
+const MEMORY_MAP_ENTRY_VERSION: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultibootError {
+    /// The header fields don't add up to zero
+    BadChecksum,
+    /// The header isn't for 32 bit protected mode
+    WrongArchitecture,
+    /// A tag runs past the end of the header, or is too small for its type
+    BadTag,
+    /// A required header tag of this type isn't supported
+    UnsupportedTag(u16),
+    /// The kernel requires this information tag, which isn't supported
+    UnsupportedInformation(u32),
+    /// The address tag doesn't describe part of the file
+    BadAddress,
+    /// The address tag was given without an entry address tag
+    MissingEntry,
+    /// The boot information doesn't fit in its buffer
+    InfoTooLarge,
+}
+
+/// Where to load a kernel that isn't an ELF file, or doesn't want to be loaded like one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressTag {
+    /// Address the header is loaded at, this is how the file is lined up with memory
+    pub header_address: u32,
+    /// Address of the first byte of the file to load
+    pub load_address: u32,
+    /// End of the bytes to load, 0 to load up to the end of the file
+    pub load_end_address: u32,
+    /// End of the zeroed memory after the loaded bytes, 0 if there is none
+    pub bss_end_address: u32,
+}
+
+/// The kernel's preferred video mode. Any field can be 0 for no preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramebufferRequest {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    /// The kernel can cope with being left in text mode
+    pub optional: bool,
+}
+
+/// Part of the kernel file to copy into memory, worked out from the [`AddressTag`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadPlan {
+    /// Offset of the first byte to copy in the file
+    pub file_offset: usize,
+    /// Number of bytes to copy
+    pub file_size: usize,
+    pub load_address: u32,
+    /// Bytes taken up in memory, anything past `file_size` is zeroed
+    pub memory_size: u32,
+}
+
+/// The parts of a Multiboot2 header the bootloader acts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Offset of the header in the kernel file
+    pub offset: usize,
+    pub address: Option<AddressTag>,
+    /// Where to jump to, instead of the ELF entry point
+    pub entry: Option<u32>,
+    pub framebuffer: Option<FramebufferRequest>,
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+impl Header {
+    /// Looks for a header in the first 32 KiB of `kernel`. Returns `None` if it isn't a
+    /// Multiboot2 kernel at all, and an error if it is one but can't be booted.
+    pub fn find(kernel: &[u8]) -> Option<Result<Header, MultibootError>> {
+        let searched = &kernel[..kernel.len().min(SEARCH_LIMIT)];
+        // The header is 8 byte aligned
+        (0..searched.len().saturating_sub(HEADER_SIZE - 1))
+            .step_by(8)
+            .find(|&offset| u32_at(searched, offset) == HEADER_MAGIC)
+            .map(|offset| Header::parse(searched, offset))
+    }
+
+    fn parse(bytes: &[u8], offset: usize) -> Result<Header, MultibootError> {
+        let architecture = u32_at(bytes, offset + 4);
+        let length = u32_at(bytes, offset + 8);
+        let checksum = u32_at(bytes, offset + 12);
+        if HEADER_MAGIC
+            .wrapping_add(architecture)
+            .wrapping_add(length)
+            .wrapping_add(checksum)
+            != 0
+        {
+            return Err(MultibootError::BadChecksum);
+        }
+        if architecture != ARCHITECTURE_I386 {
+            return Err(MultibootError::WrongArchitecture);
+        }
+        let end = offset + length as usize;
+        if (length as usize) < HEADER_SIZE || end > bytes.len() {
+            return Err(MultibootError::BadTag);
+        }
+
+        let mut header = Header {
+            offset,
+            address: None,
+            entry: None,
+            framebuffer: None,
+        };
+        let mut at = offset + HEADER_SIZE;
+        loop {
+            if at + 8 > end {
+                return Err(MultibootError::BadTag);
+            }
+            let kind = u16_at(bytes, at);
+            let optional = u16_at(bytes, at + 2) & TAG_OPTIONAL != 0;
+            let size = u32_at(bytes, at + 4) as usize;
+            if size < 8 || at + size > end {
+                return Err(MultibootError::BadTag);
+            }
+            let tag = &bytes[at..at + size];
+            let field = |index: usize| {
+                if 8 + 4 * (index + 1) > size {
+                    Err(MultibootError::BadTag)
+                } else {
+                    Ok(u32_at(tag, 8 + 4 * index))
+                }
+            };
+
+            match kind {
+                HEADER_TAG_END => break,
+                HEADER_TAG_INFORMATION_REQUEST => {
+                    for index in 0..(size - 8) / 4 {
+                        let requested = field(index)?;
+                        if !optional && !SUPPORTED_INFO.contains(&requested) {
+                            return Err(MultibootError::UnsupportedInformation(requested));
+                        }
+                    }
+                }
+                HEADER_TAG_ADDRESS => {
+                    header.address = Some(AddressTag {
+                        header_address: field(0)?,
+                        load_address: field(1)?,
+                        load_end_address: field(2)?,
+                        bss_end_address: field(3)?,
+                    })
+                }
+                HEADER_TAG_ENTRY_ADDRESS => header.entry = Some(field(0)?),
+                HEADER_TAG_FRAMEBUFFER => {
+                    header.framebuffer = Some(FramebufferRequest {
+                        width: field(0)?,
+                        height: field(1)?,
+                        depth: field(2)?,
+                        optional,
+                    })
+                }
+                // Nothing to do: there is always a console, modules aren't loaded, this isn't
+                // EFI, and the kernel is always loaded where it asks
+                HEADER_TAG_CONSOLE_FLAGS
+                | HEADER_TAG_MODULE_ALIGN
+                | HEADER_TAG_EFI_BOOT_SERVICES
+                | HEADER_TAG_ENTRY_ADDRESS_EFI32
+                | HEADER_TAG_ENTRY_ADDRESS_EFI64
+                | HEADER_TAG_RELOCATABLE => {}
+                _ if optional => {}
+                _ => return Err(MultibootError::UnsupportedTag(kind)),
+            }
+            // Tags are 8 byte aligned
+            at += size.next_multiple_of(8);
+        }
+
+        if header.address.is_some() && header.entry.is_none() {
+            return Err(MultibootError::MissingEntry);
+        }
+        Ok(header)
+    }
+
+    /// Works out what to copy where for a kernel with an address tag, `file_len` being the size
+    /// of the whole kernel file
+    pub fn load_plan(&self, file_len: usize) -> Option<Result<LoadPlan, MultibootError>> {
+        let address = self.address?;
+        Some(address.plan(self.offset, file_len))
+    }
+}
+
+impl AddressTag {
+    fn plan(&self, header_offset: usize, file_len: usize) -> Result<LoadPlan, MultibootError> {
+        // The header is at header_address in memory, which lines up the rest of the file
+        let before_header = self
+            .header_address
+            .checked_sub(self.load_address)
+            .ok_or(MultibootError::BadAddress)? as usize;
+        let file_offset = header_offset
+            .checked_sub(before_header)
+            .ok_or(MultibootError::BadAddress)?;
+        let file_size = match self.load_end_address {
+            0 => file_len - file_offset,
+            end => end
+                .checked_sub(self.load_address)
+                .ok_or(MultibootError::BadAddress)? as usize,
+        };
+        if file_offset + file_size > file_len {
+            return Err(MultibootError::BadAddress);
+        }
+        let loaded_end = self.load_address as u64 + file_size as u64;
+        let memory_end = match self.bss_end_address {
+            0 => loaded_end,
+            end if (end as u64) < loaded_end => return Err(MultibootError::BadAddress),
+            end => end as u64,
+        };
+        Ok(LoadPlan {
+            file_offset,
+            file_size,
+            load_address: self.load_address,
+            memory_size: (memory_end - self.load_address as u64) as u32,
+        })
+    }
+}
+
+/// Writes the boot information structure into a buffer, one tag at a time
+pub struct InfoBuilder<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    too_large: bool,
+}
+
+impl<'a> InfoBuilder<'a> {
+    /// Starts the structure at the start of `buffer`, which has to be 8 byte aligned
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        let too_large = buffer.len() < 8;
+        InfoBuilder {
+            buffer,
+            // Total size and a reserved field come first
+            len: 8,
+            too_large,
+        }
+    }
+
+    /// Appends a tag made of the given pieces, padding it out to 8 bytes
+    fn tag(&mut self, kind: u32, pieces: &[&[u8]]) -> &mut Self {
+        let size = 8 + pieces.iter().map(|piece| piece.len()).sum::<usize>();
+        let end = self.len + size.next_multiple_of(8);
+        if self.too_large || end > self.buffer.len() {
+            self.too_large = true;
+            return self;
+        }
+
+        let mut at = self.len;
+        for piece in [&kind.to_le_bytes()[..], &(size as u32).to_le_bytes()]
+            .iter()
+            .chain(pieces)
+        {
+            self.buffer[at..at + piece.len()].copy_from_slice(piece);
+            at += piece.len();
+        }
+        self.buffer[at..end].fill(0);
+        self.len = end;
+        self
+    }
+
+    /// The kernel command line
+    pub fn cmdline(&mut self, cmdline: &str) -> &mut Self {
+        self.tag(INFO_TAG_CMDLINE, &[cmdline.as_bytes(), &[0]])
+    }
+
+    pub fn bootloader_name(&mut self, name: &str) -> &mut Self {
+        self.tag(INFO_TAG_BOOTLOADER_NAME, &[name.as_bytes(), &[0]])
+    }
+
+    /// KiB of memory below 1 MiB and KiB of contiguous memory starting at 1 MiB, see
+    /// [`basic_memory`]
+    pub fn basic_memory(&mut self, lower: u32, upper: u32) -> &mut Self {
+        self.tag(
+            INFO_TAG_BASIC_MEMORY,
+            &[&lower.to_le_bytes(), &upper.to_le_bytes()],
+        )
+    }
+
+    /// The BIOS drive the kernel was loaded from. Partitions aren't given.
+    pub fn boot_device(&mut self, drive: u8) -> &mut Self {
+        let no_partition = u32::MAX.to_le_bytes();
+        self.tag(
+            INFO_TAG_BOOT_DEVICE,
+            &[&(drive as u32).to_le_bytes(), &no_partition, &no_partition],
+        )
+    }
+
+    /// The E820 memory map. Entries have the same layout as E820 ones, so they are copied as is.
+    pub fn memory_map(&mut self, entries: &[MemoryMapEntry]) -> &mut Self {
+        let entry_size = size_of::<MemoryMapEntry>() as u32;
+        // SAFETY: MemoryMapEntry is repr(C) and has no padding
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                entries.as_ptr() as *const u8,
+                entries.len() * entry_size as usize,
+            )
+        };
+        self.tag(
+            INFO_TAG_MEMORY_MAP,
+            &[
+                &entry_size.to_le_bytes(),
+                &MEMORY_MAP_ENTRY_VERSION.to_le_bytes(),
+                bytes,
+            ],
+        )
+    }
+
+    /// A direct color framebuffer
+    pub fn framebuffer(&mut self, framebuffer: &FrameBufferInfo) -> &mut Self {
+        let format = &framebuffer.format;
+        self.tag(
+            INFO_TAG_FRAMEBUFFER,
+            &[
+                &framebuffer.address.to_le_bytes(),
+                &framebuffer.pitch.to_le_bytes(),
+                &framebuffer.width.to_le_bytes(),
+                &framebuffer.height.to_le_bytes(),
+                &[framebuffer.bits_per_pixel as u8, FRAMEBUFFER_TYPE_RGB],
+                // Reserved
+                &[0, 0],
+                &[
+                    format.red_shift,
+                    format.red_size,
+                    format.green_shift,
+                    format.green_size,
+                    format.blue_shift,
+                    format.blue_size,
+                ],
+            ],
+        )
+    }
+
+    /// Adds the end tag and fills in the total size, returning it
+    pub fn finish(&mut self) -> Result<usize, MultibootError> {
+        self.tag(INFO_TAG_END, &[]);
+        if self.too_large {
+            return Err(MultibootError::InfoTooLarge);
+        }
+        self.buffer[0..4].copy_from_slice(&(self.len as u32).to_le_bytes());
+        self.buffer[4..8].fill(0);
+        Ok(self.len)
+    }
+}
+
+/// KiB of usable memory below 1 MiB and in the usable region starting at 1 MiB, as given in the
+/// basic memory information tag
+pub fn basic_memory(memory_map: &[MemoryMapEntry]) -> (u32, u32) {
+    const MIB: u64 = 0x100000;
+    let usable_at = |address: u64| {
+        memory_map
+            .iter()
+            .find(|entry| {
+                entry.region_type == 1
+                    && entry.base <= address
+                    && address < entry.base + entry.length
+            })
+            .map(|entry| entry.base + entry.length - address)
+            .unwrap_or(0)
+    };
+    let lower = usable_at(0).min(640 * 1024) / 1024;
+    let upper = usable_at(MIB).min(u32::MAX as u64 * 1024) / 1024;
+    (lower as u32, upper as u32)
+}
+
+/// Builds a header with the given tags, each being a type, flags and body
+#[cfg(test)]
+fn test_header(tags: &[(u16, u16, &[u32])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (kind, flags, fields) in tags.iter().chain([&(HEADER_TAG_END, 0, &[][..])]) {
+        body.extend_from_slice(&kind.to_le_bytes());
+        body.extend_from_slice(&flags.to_le_bytes());
+        body.extend_from_slice(&(8 + 4 * fields.len() as u32).to_le_bytes());
+        for field in fields.iter() {
+            body.extend_from_slice(&field.to_le_bytes());
+        }
+        body.resize(body.len().next_multiple_of(8), 0);
+    }
+    let length = (HEADER_SIZE + body.len()) as u32;
+    let checksum = 0u32.wrapping_sub(HEADER_MAGIC).wrapping_sub(length);
+    let mut header = Vec::new();
+    for field in [HEADER_MAGIC, ARCHITECTURE_I386, length, checksum] {
+        header.extend_from_slice(&field.to_le_bytes());
+    }
+    header.extend_from_slice(&body);
+    header
+}
+
+#[test]
+fn test_find_header() {
+    assert_eq!(Header::find(&[0; 0x100]), None);
+
+    let mut kernel = vec![0; 0x40];
+    kernel.extend_from_slice(&test_header(&[
+        (HEADER_TAG_INFORMATION_REQUEST, 0, &[INFO_TAG_MEMORY_MAP]),
+        (HEADER_TAG_ADDRESS, 0, &[0x100040, 0x100000, 0, 0x110000]),
+        (HEADER_TAG_ENTRY_ADDRESS, 0, &[0x100080]),
+        (HEADER_TAG_FRAMEBUFFER, TAG_OPTIONAL, &[1024, 768, 32]),
+    ]));
+    kernel.resize(0x1000, 0x90);
+
+    let header = Header::find(&kernel).unwrap().unwrap();
+    assert_eq!(header.offset, 0x40);
+    assert_eq!(header.entry, Some(0x100080));
+    let framebuffer = header.framebuffer.unwrap();
+    assert_eq!(
+        (framebuffer.width, framebuffer.height, framebuffer.depth),
+        (1024, 768, 32)
+    );
+    assert!(framebuffer.optional);
+
+    // The file is loaded from its start, since the header is 0x40 bytes into both
+    let plan = header.load_plan(kernel.len()).unwrap().unwrap();
+    assert_eq!(plan.file_offset, 0);
+    assert_eq!(plan.file_size, 0x1000);
+    assert_eq!(plan.load_address, 0x100000);
+    assert_eq!(plan.memory_size, 0x10000);
+}
+
+#[test]
+fn test_bad_header() {
+    let mut kernel = test_header(&[]);
+    kernel[12] ^= 1;
+    assert_eq!(
+        Header::find(&kernel),
+        Some(Err(MultibootError::BadChecksum))
+    );
+
+    let required = test_header(&[(HEADER_TAG_INFORMATION_REQUEST, 0, &[21])]);
+    assert_eq!(
+        Header::find(&required),
+        Some(Err(MultibootError::UnsupportedInformation(21)))
+    );
+    let optional = test_header(&[(HEADER_TAG_INFORMATION_REQUEST, TAG_OPTIONAL, &[21])]);
+    assert!(Header::find(&optional).unwrap().is_ok());
+
+    let unknown = test_header(&[(42, 0, &[])]);
+    assert_eq!(
+        Header::find(&unknown),
+        Some(Err(MultibootError::UnsupportedTag(42)))
+    );
+
+    let no_entry = test_header(&[(HEADER_TAG_ADDRESS, 0, &[0x100000, 0x100000, 0, 0])]);
+    assert_eq!(
+        Header::find(&no_entry),
+        Some(Err(MultibootError::MissingEntry))
+    );
+}
+
+#[test]
+fn test_build_info() {
+    let memory_map = [
+        MemoryMapEntry {
+            base: 0,
+            length: 0x9fc00,
+            region_type: 1,
+            attributes: 0,
+        },
+        MemoryMapEntry {
+            base: 0x100000,
+            length: 0x7ee0000,
+            region_type: 1,
+            attributes: 0,
+        },
+    ];
+    let (lower, upper) = basic_memory(&memory_map);
+    assert_eq!((lower, upper), (639, 0x7ee0000 / 1024));
+
+    let mut buffer = [0xff; 0x200];
+    let len = InfoBuilder::new(&mut buffer)
+        .cmdline("quiet")
+        .basic_memory(lower, upper)
+        .boot_device(0x80)
+        .memory_map(&memory_map)
+        .finish()
+        .unwrap();
+    assert_eq!(len, u32_at(&buffer, 0) as usize);
+
+    // Walk the tags back
+    let mut at = 8;
+    let mut kinds = Vec::new();
+    loop {
+        let kind = u32_at(&buffer, at);
+        let size = u32_at(&buffer, at + 4) as usize;
+        kinds.push(kind);
+        if kind == INFO_TAG_CMDLINE {
+            assert_eq!(&buffer[at + 8..at + size], b"quiet\0");
+        }
+        if kind == INFO_TAG_MEMORY_MAP {
+            assert_eq!(size, 16 + 2 * 24);
+            assert_eq!(u32_at(&buffer, at + 8), 24);
+        }
+        at += size.next_multiple_of(8);
+        if kind == INFO_TAG_END {
+            break;
+        }
+    }
+    assert_eq!(at, len);
+    assert_eq!(
+        kinds,
+        [
+            INFO_TAG_CMDLINE,
+            INFO_TAG_BASIC_MEMORY,
+            INFO_TAG_BOOT_DEVICE,
+            INFO_TAG_MEMORY_MAP,
+            INFO_TAG_END
+        ]
+    );
+
+    let mut small = [0; 0x20];
+    assert_eq!(
+        InfoBuilder::new(&mut small)
+            .cmdline("a long command line")
+            .finish(),
+        Err(MultibootError::InfoTooLarge)
+    );
+}
@@ -0,0 +1,97 @@
+//! Finds the partition holding the kernel in an MBR or GPT partition table
+
+use crate::disk::{DiskError, SectorReader, SECTOR_SIZE};
+
+/// MBR partition type of a FAT32 partition addressed with LBA
+pub const MBR_TYPE_FAT32_LBA: u8 = 0x0c;
+/// MBR partition type of a FAT32 partition addressed with CHS
+pub const MBR_TYPE_FAT32_CHS: u8 = 0x0b;
+/// MBR partition type covering the whole disk on GPT formatted disks
+pub const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+
+/// GPT partition type of the FAT32 boot partition, Microsoft basic data
+/// (EBD0A0A2-B9E5-4433-87C0-68B6B72699C7) in on disk byte order
+pub const GPT_TYPE_BASIC_DATA: [u8; 16] = [
+    0xa2, 0xa0, 0xd0, 0xeb, 0xe5, 0xb9, 0x33, 0x44, 0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7,
+];
+
+/// Offset of the partition table in the MBR
+pub const MBR_TABLE_OFFSET: usize = 446;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionError {
+    Disk(DiskError),
+    /// Sector 0 doesn't end in 0xaa55
+    NoBootSignature,
+    /// The protective MBR points at something that isn't a GPT header
+    BadGptHeader,
+    /// There is no FAT32 partition
+    NotFound,
+}
+
+impl From<DiskError> for PartitionError {
+    fn from(e: DiskError) -> Self {
+        PartitionError::Disk(e)
+    }
+}
+
+/// Returns the first LBA of the FAT32 boot partition.
+///
+/// `sector` is used as scratch space for reading the tables.
+pub fn find_boot_partition(
+    reader: &mut impl SectorReader,
+    sector: &mut [u8; SECTOR_SIZE],
+) -> Result<u64, PartitionError> {
+    reader.read_sectors(0, sector)?;
+    if sector[510..512] != [0x55, 0xaa] {
+        return Err(PartitionError::NoBootSignature);
+    }
+
+    let mut gpt = false;
+    for entry in sector[MBR_TABLE_OFFSET..510].chunks_exact(16) {
+        match entry[4] {
+            MBR_TYPE_FAT32_LBA | MBR_TYPE_FAT32_CHS => {
+                return Ok(u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64);
+            }
+            MBR_TYPE_GPT_PROTECTIVE => gpt = true,
+            _ => {}
+        }
+    }
+
+    if gpt {
+        find_gpt_partition(reader, sector)
+    } else {
+        Err(PartitionError::NotFound)
+    }
+}
+
+/// Looks for a basic data partition in the GPT entry array
+fn find_gpt_partition(
+    reader: &mut impl SectorReader,
+    sector: &mut [u8; SECTOR_SIZE],
+) -> Result<u64, PartitionError> {
+    reader.read_sectors(1, sector)?;
+    if &sector[0..8] != b"EFI PART" {
+        return Err(PartitionError::BadGptHeader);
+    }
+
+    let read_u32 = |s: &[u8], at: usize| u32::from_le_bytes(s[at..at + 4].try_into().unwrap());
+    let entries_lba = u64::from_le_bytes(sector[72..80].try_into().unwrap());
+    let entry_count = read_u32(&sector[..], 80) as usize;
+    let entry_size = read_u32(&sector[..], 84) as usize;
+    if entry_size < 128 || !SECTOR_SIZE.is_multiple_of(entry_size) {
+        return Err(PartitionError::BadGptHeader);
+    }
+
+    let entries_per_sector = SECTOR_SIZE / entry_size;
+    for ii in 0..entry_count.div_ceil(entries_per_sector) {
+        reader.read_sectors(entries_lba + ii as u64, sector)?;
+        for entry in sector.chunks_exact(entry_size) {
+            if entry[0..16] == GPT_TYPE_BASIC_DATA {
+                return Ok(u64::from_le_bytes(entry[32..40].try_into().unwrap()));
+            }
+        }
+    }
+
+    Err(PartitionError::NotFound)
+}
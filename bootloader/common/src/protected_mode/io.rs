@@ -27,12 +27,9 @@ macro_rules! println {
 
 impl core::fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // The VGA text mode font is code page 437
         for c in s.chars() {
-            if c.is_ascii() {
-                self.print_char(c as u8);
-            } else {
-                self.print_char(b'?');
-            }
+            self.print_char(crate::cp437::encode(c));
         }
 
         Ok(())
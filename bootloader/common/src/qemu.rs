@@ -0,0 +1,41 @@
+//! Exiting QEMU from inside the guest through the `isa-debug-exit` device
+
+use core::arch::asm;
+
+/// I/O port the runner maps the `isa-debug-exit` device to
+pub const DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// Value written to [`DEBUG_EXIT_PORT`]. QEMU exits with `(code << 1) | 1`, so neither of these
+/// can be confused with QEMU itself exiting with 0 or 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+impl QemuExitCode {
+    /// Exit status of the QEMU process when the guest exits with this code
+    pub const fn process_code(self) -> i32 {
+        ((self as i32) << 1) | 1
+    }
+}
+
+/// Exits QEMU with the given code. Does nothing when the runner didn't add the
+/// `isa-debug-exit` device, so it is safe to call outside of tests.
+pub fn exit_qemu(code: QemuExitCode) {
+    unsafe {
+        asm!(
+            "out dx, eax",
+            in("dx") DEBUG_EXIT_PORT,
+            in("eax") code as u32,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+#[test]
+fn test_process_code() {
+    assert_eq!(QemuExitCode::Success.process_code(), 33);
+    assert_eq!(QemuExitCode::Failed.process_code(), 35);
+}
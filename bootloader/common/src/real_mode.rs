@@ -1,5 +1,10 @@
 use core::arch::asm;
 
+pub mod a20;
+pub mod disk;
+pub mod keyboard;
+pub mod memory;
+
 /// Prints a single characetr to the screen
 #[inline]
 pub fn print_char(c: u8) {
@@ -12,6 +17,11 @@ pub fn print_char(c: u8) {
     }
 }
 
+/// Clears the screen and puts the cursor in the top left, by setting VGA text mode 3 again
+pub fn clear_screen() {
+    unsafe { asm!("int 0x10", inout("ax") 0x0003u16 => _) }
+}
+
 #[macro_export]
 macro_rules! print_bios {
     ($($arg:tt)*) => {{
@@ -41,17 +51,14 @@ impl core::fmt::Write for BiosWriter {
 
 fn print_str(s: &str) {
     for c in s.chars() {
-        if c.is_ascii() {
-            if c == '\n' {
-                let ax = 0x0e00 | b'\r' as u16;
-                unsafe { asm!("int 0x10", in("ax") ax) }
-                let ax = 0x0e00 | b'\n' as u16;
-                unsafe { asm!("int 0x10", in("ax") ax) }
-            } else {
-                print_char(c as u8);
-            }
+        if c == '\n' {
+            let ax = 0x0e00 | b'\r' as u16;
+            unsafe { asm!("int 0x10", in("ax") ax) }
+            let ax = 0x0e00 | b'\n' as u16;
+            unsafe { asm!("int 0x10", in("ax") ax) }
         } else {
-            print_char(b'?')
+            // The BIOS font is code page 437
+            print_char(crate::cp437::encode(c));
         }
     }
 }
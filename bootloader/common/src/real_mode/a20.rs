@@ -0,0 +1,173 @@
+//! Enables the A20 line, without which every odd MiB of memory is a mirror of the one below it
+
+use core::arch::asm;
+
+/// Keyboard controller status and command port
+const KBC_COMMAND: u16 = 0x64;
+/// Keyboard controller data port
+const KBC_DATA: u16 = 0x60;
+/// Status bit set while the controller hasn't taken the last byte written to it
+const KBC_INPUT_FULL: u8 = 1 << 1;
+/// Status bit set when the controller has a byte for us to read
+const KBC_OUTPUT_FULL: u8 = 1 << 0;
+/// System control port A, the "fast A20" port
+const SYSTEM_CONTROL: u16 = 0x92;
+/// How often a status or the A20 line is polled before giving up. Real keyboard controllers can
+/// take a while, and machines without one never answer.
+const POLL_ATTEMPTS: u32 = 0x10000;
+
+/// How the A20 line got enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum A20Method {
+    /// The BIOS or emulator left it on
+    AlreadyEnabled,
+    /// int 0x15 AX=0x2401
+    Bios,
+    /// Output port of the 8042 keyboard controller
+    KeyboardController,
+    /// Bit 1 of system control port A
+    Fast,
+}
+
+impl A20Method {
+    pub fn name(&self) -> &'static str {
+        match self {
+            A20Method::AlreadyEnabled => "already enabled",
+            A20Method::Bios => "BIOS",
+            A20Method::KeyboardController => "keyboard controller",
+            A20Method::Fast => "fast A20",
+        }
+    }
+}
+
+#[inline(always)]
+fn outb(port: u16, value: u8) {
+    unsafe { asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack)) }
+}
+
+#[inline(always)]
+fn inb(port: u16) -> u8 {
+    let value: u8;
+    unsafe { asm!("in al, dx", in("dx") port, out("al") value, options(nomem, nostack)) }
+    value
+}
+
+/// Whether the A20 line is on, by checking if 0xffff:0x0510 wraps around to 0x0000:0x0500.
+/// Both bytes are put back afterwards.
+pub fn is_enabled() -> bool {
+    let wrapped: u8;
+    unsafe {
+        asm!(
+            "pushf",
+            "cli",
+            "push ds",
+            "push es",
+            "xor {segment:x}, {segment:x}",
+            "mov ds, {segment:x}",
+            "not {segment:x}",
+            "mov es, {segment:x}",
+            "mov {low}, byte ptr ds:[0x0500]",
+            "mov {high}, byte ptr es:[0x0510]",
+            // Only the same byte if the address wrapped
+            "mov byte ptr ds:[0x0500], 0x00",
+            "mov byte ptr es:[0x0510], 0xff",
+            "cmp byte ptr ds:[0x0500], 0xff",
+            "sete {wrapped}",
+            // Low byte last, in case both are the same
+            "mov byte ptr es:[0x0510], {high}",
+            "mov byte ptr ds:[0x0500], {low}",
+            "pop es",
+            "pop ds",
+            "popf",
+            segment = out(reg) _,
+            low = out(reg_byte) _,
+            high = out(reg_byte) _,
+            wrapped = out(reg_byte) wrapped,
+        );
+    }
+    wrapped == 0
+}
+
+/// Polls [`is_enabled`] for a while, the keyboard controller and port 0x92 can take some time to
+/// switch the line
+fn wait_enabled() -> bool {
+    (0..POLL_ATTEMPTS).any(|_| is_enabled())
+}
+
+/// Asks the BIOS to turn the line on with int 0x15 AX=0x2401
+fn enable_bios() {
+    unsafe {
+        asm!(
+            "int 0x15",
+            inout("ax") 0x2401u16 => _,
+        );
+    }
+}
+
+/// Waits until the keyboard controller has status `bit` equal to `set`, false if it never does
+fn kbc_wait(bit: u8, set: bool) -> bool {
+    (0..POLL_ATTEMPTS).any(|_| (inb(KBC_COMMAND) & bit != 0) == set)
+}
+
+/// Writes a command to the keyboard controller once it can take it
+fn kbc_command(command: u8) -> bool {
+    if !kbc_wait(KBC_INPUT_FULL, false) {
+        return false;
+    }
+    outb(KBC_COMMAND, command);
+    true
+}
+
+/// Sets the A20 bit in the output port of the keyboard controller, giving up if it stops
+/// answering
+fn kbc_set_a20() {
+    // Read the output port
+    if !(kbc_command(0xd0) && kbc_wait(KBC_OUTPUT_FULL, true)) {
+        return;
+    }
+    let port = inb(KBC_DATA);
+    // Write it back with A20 set
+    if kbc_command(0xd1) && kbc_wait(KBC_INPUT_FULL, false) {
+        outb(KBC_DATA, port | 0x02);
+    }
+}
+
+/// Enables A20 through the keyboard controller, with the keyboard disabled so a key press
+/// doesn't get read as the output port
+fn enable_keyboard_controller() {
+    unsafe { asm!("cli") };
+    if kbc_command(0xad) {
+        kbc_set_a20();
+        kbc_command(0xae);
+    }
+    unsafe { asm!("sti") };
+}
+
+/// Sets bit 1 of port 0x92. Bit 0 resets the machine, so it has to stay clear.
+fn enable_fast() {
+    let value = inb(SYSTEM_CONTROL);
+    if value & 0x02 == 0 {
+        outb(SYSTEM_CONTROL, (value | 0x02) & !0x01);
+    }
+}
+
+/// Enables the A20 line, trying the BIOS first, then the keyboard controller and then fast A20.
+/// Returns which one worked, or `None` if the line is still off.
+pub fn enable() -> Option<A20Method> {
+    if is_enabled() {
+        return Some(A20Method::AlreadyEnabled);
+    }
+
+    let methods: [(A20Method, fn()); 3] = [
+        (A20Method::Bios, enable_bios),
+        (A20Method::KeyboardController, enable_keyboard_controller),
+        (A20Method::Fast, enable_fast),
+    ];
+    for (method, enable) in methods {
+        enable();
+        if wait_enabled() {
+            return Some(method);
+        }
+    }
+    None
+}
@@ -0,0 +1,201 @@
+//! Disk access through the BIOS int 0x13 extensions
+
+use core::arch::asm;
+
+use crate::disk::{DiskError, SectorReader, SECTOR_SIZE};
+
+/// Packet describing an extended read, passed to int 0x13 in DS:SI
+#[allow(dead_code)]
+#[repr(C, packed)]
+struct DiskAddressPacket {
+    /// Size of the packet, always 0x10
+    size: u8,
+    zero: u8,
+    /// Number of sectors to transfer
+    sectors: u16,
+    /// Buffer offset
+    offset: u16,
+    /// Buffer segment
+    segment: u16,
+    lba: u64,
+}
+
+/// Reads sectors with int 0x13 AH=0x42 into a buffer in the first MiB of memory
+pub fn read_lba(drive: u8, lba: u64, sectors: u16, buffer: *mut u8) -> Result<(), DiskError> {
+    let address = buffer as u32;
+    let dap = DiskAddressPacket {
+        size: 0x10,
+        zero: 0,
+        sectors,
+        offset: (address & 0xf) as u16,
+        segment: (address >> 4) as u16,
+        lba,
+    };
+
+    let ax: u16;
+    let carry: u8;
+    // SI can't be used as an operand, save it around the call instead
+    unsafe {
+        asm!(
+            "push si",
+            "mov si, {dap:x}",
+            "int 0x13",
+            "pop si",
+            "setc {carry}",
+            dap = in(reg) &dap as *const DiskAddressPacket as u32,
+            carry = out(reg_byte) carry,
+            inout("ax") 0x4200u16 => ax,
+            in("dl") drive,
+        );
+    }
+
+    if carry != 0 {
+        Err(DiskError((ax >> 8) as u8))
+    } else {
+        Ok(())
+    }
+}
+
+/// Most bytes read in one call, a whole number of sectors for both disks and CDs. Keeps every
+/// read inside one 64 KiB segment, since the buffer offset is always below 16.
+pub const MAX_READ_BYTES: usize = 127 * SECTOR_SIZE;
+
+/// Result buffer of int 0x13 AH=0x48
+#[allow(dead_code)]
+#[repr(C, packed)]
+struct DriveParameters {
+    /// Size of the buffer, filled in by the caller
+    size: u16,
+    flags: u16,
+    cylinders: u32,
+    heads: u32,
+    sectors_per_track: u32,
+    sectors: u64,
+    bytes_per_sector: u16,
+}
+
+/// Reads the sector size of `drive` with int 0x13 AH=0x48, 2048 for CDs. Needs the int 0x13
+/// extensions.
+pub fn sector_size(drive: u8) -> Result<u16, DiskError> {
+    let mut parameters = DriveParameters {
+        size: size_of::<DriveParameters>() as u16,
+        flags: 0,
+        cylinders: 0,
+        heads: 0,
+        sectors_per_track: 0,
+        sectors: 0,
+        bytes_per_sector: 0,
+    };
+
+    let ax: u16;
+    let carry: u8;
+    // SI can't be used as an operand, save it around the call instead
+    unsafe {
+        asm!(
+            "push si",
+            "mov si, {parameters:x}",
+            "int 0x13",
+            "pop si",
+            "setc {carry}",
+            parameters = in(reg) &mut parameters as *mut DriveParameters as u32,
+            carry = out(reg_byte) carry,
+            inout("ax") 0x4800u16 => ax,
+            in("dl") drive,
+        );
+    }
+
+    if carry != 0 {
+        Err(DiskError((ax >> 8) as u8))
+    } else {
+        Ok(parameters.bytes_per_sector)
+    }
+}
+
+/// How many times a read is tried before giving up. Some USB sticks fail the first read after
+/// boot.
+pub const READ_ATTEMPTS: usize = 3;
+
+/// Resets `drive` with int 0x13 AH=0x00, done between failed reads
+pub fn reset(drive: u8) {
+    unsafe {
+        asm!(
+            "int 0x13",
+            inout("ax") 0u16 => _,
+            in("dl") drive,
+        );
+    }
+}
+
+/// Calls `read` up to [`READ_ATTEMPTS`] times, resetting `drive` after each failure. Returns the
+/// last error if every attempt fails.
+pub fn retry(drive: u8, mut read: impl FnMut() -> Result<(), DiskError>) -> Result<(), DiskError> {
+    let mut attempt = 1;
+    loop {
+        match read() {
+            Err(_) if attempt < READ_ATTEMPTS => {
+                reset(drive);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The drive we were booted from
+pub struct BiosDisk {
+    drive: u8,
+    /// Bytes per sector of the drive, 2048 when booted from a CD
+    sector_size: usize,
+}
+
+impl BiosDisk {
+    /// Opens `drive`, asking the BIOS for its sector size
+    pub fn new(drive: u8) -> Result<Self, DiskError> {
+        let mut bytes = 0;
+        retry(drive, || {
+            bytes = sector_size(drive)?;
+            Ok(())
+        })?;
+        Ok(BiosDisk {
+            drive,
+            sector_size: bytes as usize,
+        })
+    }
+
+    /// Bytes per sector of the drive. LBAs passed to [`SectorReader::read_sectors`] are always
+    /// in 512 byte sectors, on drives with bigger sectors reads have to be whole sectors.
+    pub fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+}
+
+impl SectorReader for BiosDisk {
+    /// `buffer` has to be in the first MiB of memory. A failed read prints the decoded BIOS
+    /// error and the LBA before returning it.
+    fn read_sectors(&mut self, lba: u64, buffer: &mut [u8]) -> Result<(), DiskError> {
+        let per_sector = (self.sector_size / SECTOR_SIZE) as u64;
+        if !lba.is_multiple_of(per_sector) || !buffer.len().is_multiple_of(self.sector_size) {
+            // Bad command
+            return Err(DiskError(0x01));
+        }
+
+        let chunk_size = MAX_READ_BYTES / self.sector_size * self.sector_size;
+        for (ii, chunk) in buffer.chunks_mut(chunk_size).enumerate() {
+            let chunk_lba = lba / per_sector + (ii * chunk_size / self.sector_size) as u64;
+            let sectors = (chunk.len() / self.sector_size) as u16;
+            let buffer = chunk.as_mut_ptr();
+            retry(self.drive, || {
+                read_lba(self.drive, chunk_lba, sectors, buffer)
+            })
+            .inspect_err(|error| {
+                // The callers only see the error, so say where it happened here
+                crate::println_bios!(
+                    "Disk error at LBA {chunk_lba:#x}: {} ({:#04x})",
+                    error.reason(),
+                    error.0
+                );
+            })?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+//! Reads the keyboard and the timer through the BIOS
+
+use core::arch::asm;
+
+use crate::menu::Key;
+
+/// Takes the next key press, waiting for one with int 0x16 AH=0x00
+pub fn read_key() -> Key {
+    let ax: u16;
+    unsafe { asm!("int 0x16", inout("ax") 0x0000u16 => ax) }
+    Key {
+        scancode: (ax >> 8) as u8,
+        ascii: ax as u8,
+    }
+}
+
+/// Takes the next key press if there is one, without waiting. int 0x16 AH=0x01 clears ZF when
+/// there is a key but leaves it in the buffer.
+pub fn poll_key() -> Option<Key> {
+    let empty: u8;
+    unsafe {
+        asm!("int 0x16", "setz {empty}", empty = out(reg_byte) empty, inout("ax") 0x0100u16 => _)
+    }
+    (empty == 0).then(read_key)
+}
+
+/// Ticks of the BIOS timer since midnight from int 0x1a AH=0x00, about 18.2 a second
+pub fn ticks() -> u32 {
+    let (high, low): (u16, u16);
+    unsafe { asm!("int 0x1a", inout("ax") 0x0000u16 => _, out("cx") high, out("dx") low) }
+    ((high as u32) << 16) | low as u32
+}
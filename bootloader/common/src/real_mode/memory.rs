@@ -0,0 +1,162 @@
+//! Asks the BIOS for the memory map, with E820 or the older calls that came before it
+
+use core::arch::asm;
+
+use crate::memory::{legacy_map, MemoryKind, MemoryRegion};
+
+/// "SMAP", which E820 wants in EDX and answers with in EAX
+const SMAP: u32 = 0x534d4150;
+
+/// An entry as written by E820. BIOSes before ACPI 3.0 only fill in the first 20 bytes.
+#[repr(C)]
+struct E820Entry {
+    base: u64,
+    length: u64,
+    region_type: u32,
+    attributes: u32,
+}
+
+/// Where the memory map came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemorySource {
+    /// int 0x15 EAX=0xE820
+    E820,
+    /// int 0x15 AX=0xE801
+    E801,
+    /// int 0x15 AH=0x88
+    Extended,
+}
+
+impl MemorySource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemorySource::E820 => "E820",
+            MemorySource::E801 => "E801",
+            MemorySource::Extended => "88h",
+        }
+    }
+}
+
+/// Reads the memory map with int 0x15 EAX=0xE820, calling `f` with every region. False if the
+/// BIOS doesn't support it.
+pub fn e820(mut f: impl FnMut(MemoryRegion)) -> bool {
+    let mut continuation = 0u32;
+    let mut first = true;
+    loop {
+        // Attributes are preset to "enabled" for BIOSes that only write 20 bytes
+        let mut entry = E820Entry {
+            base: 0,
+            length: 0,
+            region_type: 0,
+            attributes: 1,
+        };
+        let eax: u32;
+        let ecx: u32;
+        unsafe {
+            asm!(
+                "int 0x15",
+                // Every register is taken, so a set carry turns into a bad signature
+                "jnc 2f",
+                "xor eax, eax",
+                "2:",
+                inout("eax") 0xe820u32 => eax,
+                inout("ebx") continuation,
+                inout("ecx") size_of::<E820Entry>() as u32 => ecx,
+                inout("edx") SMAP => _,
+                inout("di") &mut entry as *mut E820Entry as u16 => _,
+            );
+        }
+
+        // Carry on the first call means no E820, on a later one that the last entry was already
+        // read
+        if eax != SMAP {
+            return !first;
+        }
+        first = false;
+        // ACPI 3.0 entries with bit 0 clear are to be ignored
+        if ecx < 24 || entry.attributes & 1 != 0 {
+            f(MemoryRegion {
+                base: entry.base,
+                len: entry.length,
+                kind: MemoryKind::from_e820(entry.region_type),
+            });
+        }
+        if continuation == 0 {
+            return true;
+        }
+    }
+}
+
+/// KiB of conventional memory from int 0x12
+pub fn conventional_kib() -> u16 {
+    let kib: u16;
+    unsafe { asm!("int 0x12", out("ax") kib) };
+    kib
+}
+
+/// KiB between 1 MiB and 16 MiB, and 64 KiB blocks above 16 MiB, with int 0x15 AX=0xE801
+pub fn e801() -> Option<(u16, u16)> {
+    let (ax, bx, cx, dx): (u16, u16, u16, u16);
+    unsafe {
+        asm!(
+            "int 0x15",
+            "jnc 2f",
+            "xor ax, ax",
+            "xor bx, bx",
+            "xor cx, cx",
+            "xor dx, dx",
+            "2:",
+            inout("ax") 0xe801u16 => ax,
+            out("bx") bx,
+            inout("cx") 0u16 => cx,
+            inout("dx") 0u16 => dx,
+        );
+    }
+    // Some BIOSes answer in CX and DX, others in AX and BX
+    match (ax, bx, cx, dx) {
+        (0, 0, 0, 0) => None,
+        (_, _, 0, 0) => Some((ax, bx)),
+        _ => Some((cx, dx)),
+    }
+}
+
+/// KiB above 1 MiB with int 0x15 AH=0x88, at most 64 MiB worth
+pub fn extended_kib() -> Option<u16> {
+    let ax: u16;
+    let carry: u16;
+    unsafe {
+        asm!(
+            "int 0x15",
+            "sbb {carry:x}, {carry:x}",
+            carry = lateout(reg) carry,
+            inout("ax") 0x8800u16 => ax,
+        );
+    }
+    (carry == 0 && ax != 0).then_some(ax)
+}
+
+/// Reads the memory map into `regions`, trying E820 first and falling back to E801 and then
+/// AH=0x88. Regions that don't fit are dropped. Returns how many regions were read and where
+/// they came from, or `None` if every call failed.
+pub fn read_memory_map(regions: &mut [MemoryRegion]) -> Option<(usize, MemorySource)> {
+    let mut count = 0;
+    let found = e820(|region| {
+        if let Some(slot) = regions.get_mut(count) {
+            *slot = region;
+            count += 1;
+        }
+    });
+    if found {
+        return Some((count, MemorySource::E820));
+    }
+
+    let (low_kib, high_blocks, source) = match (e801(), extended_kib()) {
+        (Some((low_kib, high_blocks)), _) => (low_kib, high_blocks, MemorySource::E801),
+        (None, Some(kib)) => (kib, 0, MemorySource::Extended),
+        (None, None) => return None,
+    };
+    let map = legacy_map(conventional_kib(), low_kib, high_blocks);
+    let count = map.len().min(regions.len());
+    regions[..count].copy_from_slice(&map[..count]);
+    Some((count, source))
+}
@@ -0,0 +1,95 @@
+//! Minimal COM1 driver, used to report boot progress to whoever is on the other end of the
+//! serial port. Plain port I/O, so it works the same in real, protected and long mode.
+
+use core::arch::asm;
+
+/// I/O port of the first serial port
+pub const COM1: u16 = 0x3f8;
+/// Offset of the line status register
+const LINE_STATUS: u16 = 5;
+/// Line status bit set when the transmit buffer can take another byte
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// Printed by stage 0 once the rest of the bootloader is read from disk
+pub const STAGE_0_LOADED: &str = "stage0: loaded";
+/// Printed by stage 1 once the A20 line is enabled
+pub const STAGE_1_A20: &str = "stage1: a20 ok";
+/// Printed by stage 1 once the memory map is read
+pub const STAGE_1_E820: &str = "stage1: e820 ok";
+/// Printed by stage 1 once the kernel is copied to `KERNEL_START`
+pub const STAGE_1_KERNEL: &str = "stage1: kernel loaded";
+/// Printed by stage 2 right after the jump to protected mode
+pub const STAGE_2_PROTECTED: &str = "stage2: protected mode";
+/// Printed by stage 2 once paging is on and long mode is active
+pub const STAGE_2_LONG: &str = "stage2: long mode";
+/// Printed by the kernel as soon as it starts
+pub const STAGE_3_STARTED: &str = "stage3: started";
+
+/// Every milestone in the order a successful boot reaches them
+pub const MILESTONES: [&str; 7] = [
+    STAGE_0_LOADED,
+    STAGE_1_A20,
+    STAGE_1_E820,
+    STAGE_1_KERNEL,
+    STAGE_2_PROTECTED,
+    STAGE_2_LONG,
+    STAGE_3_STARTED,
+];
+
+#[inline(always)]
+fn outb(port: u16, value: u8) {
+    unsafe { asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack)) }
+}
+
+#[inline(always)]
+fn inb(port: u16) -> u8 {
+    let value: u8;
+    unsafe { asm!("in al, dx", in("dx") port, out("al") value, options(nomem, nostack)) }
+    value
+}
+
+/// Sets COM1 to 115200 baud, 8 data bits, no parity and one stop bit with FIFOs enabled.
+///
+/// Stage 0 doesn't have room for this and relies on the BIOS or emulator defaults.
+pub fn init() {
+    // Disable interrupts
+    outb(COM1 + 1, 0x00);
+    // Set the divisor to 1 with DLAB on
+    outb(COM1 + 3, 0x80);
+    outb(COM1, 0x01);
+    outb(COM1 + 1, 0x00);
+    // 8N1 with DLAB off
+    outb(COM1 + 3, 0x03);
+    // Enable and clear the FIFOs
+    outb(COM1 + 2, 0xc7);
+    // Data terminal ready and request to send
+    outb(COM1 + 4, 0x03);
+}
+
+/// Writes a single byte, waiting for the transmit buffer to be empty
+pub fn write_byte(byte: u8) {
+    while inb(COM1 + LINE_STATUS) & TRANSMIT_EMPTY == 0 {}
+    outb(COM1, byte);
+}
+
+pub fn write_str(s: &str) {
+    for byte in s.bytes() {
+        write_byte(byte);
+    }
+}
+
+/// Reports reaching a milestone, one per line
+pub fn milestone(name: &str) {
+    write_str(name);
+    write_str("\r\n");
+}
+
+/// Writes formatted text to COM1
+pub struct SerialWriter;
+
+impl core::fmt::Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        write_str(s);
+        Ok(())
+    }
+}
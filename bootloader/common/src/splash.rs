@@ -0,0 +1,87 @@
+//! Where the boot splash progress bar goes, so every stage with access to the framebuffer can
+//! move it along
+
+use crate::serial::MILESTONES;
+use crate::FrameBufferInfo;
+
+/// Logos up to this many pixels high fit above the bar when centered on the screen
+pub const LOGO_MAX_HEIGHT: u32 = 128;
+/// Pixels between the logo and the bar
+const BAR_GAP: u32 = 16;
+/// Height of the bar in pixels, including its 1 pixel border
+const BAR_HEIGHT: u32 = 12;
+/// Color of the bar and its border, 8 bits per channel
+pub const BAR_COLOR: (u8, u8, u8) = (0x3d, 0x8b, 0xe0);
+
+/// A rectangle under the logo, with a border and filled from the left as the boot goes on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressBar {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ProgressBar {
+    /// The bar on a screen of the given size, a third of its width and centered
+    pub fn new(screen_width: u32, screen_height: u32) -> Self {
+        let width = screen_width / 3;
+        let y = screen_height / 2 + LOGO_MAX_HEIGHT / 2 + BAR_GAP;
+        ProgressBar {
+            x: (screen_width - width) / 2,
+            y: y.min(screen_height.saturating_sub(BAR_HEIGHT)),
+            width,
+            height: BAR_HEIGHT.min(screen_height),
+        }
+    }
+
+    /// Pixels of the inside of the bar that are filled once `milestone` is reached, out of
+    /// `width - 2`. Unknown milestones fill nothing.
+    pub fn filled(&self, milestone: &str) -> u32 {
+        let done = MILESTONES
+            .iter()
+            .position(|m| *m == milestone)
+            .map_or(0, |index| index + 1);
+        self.width.saturating_sub(2) * done as u32 / MILESTONES.len() as u32
+    }
+
+    /// Fills the bar up to `milestone` by writing straight to video memory, for the stages after
+    /// stage 1 which don't keep a console of their own. Does nothing in text mode.
+    ///
+    /// # Safety
+    ///
+    /// The framebuffer `fb` describes has to be identity mapped
+    pub unsafe fn advance(fb: &FrameBufferInfo, milestone: &str) {
+        if fb.address == 0 {
+            return;
+        }
+        let bar = ProgressBar::new(fb.width, fb.height);
+        let (r, g, b) = BAR_COLOR;
+        let pixel = fb.format.encode(r, g, b).to_le_bytes();
+        let bytes_per_pixel = (fb.bits_per_pixel as usize).div_ceil(8);
+        for y in bar.y + 1..bar.y + bar.height - 1 {
+            let row = fb.address as usize + y as usize * fb.pitch as usize;
+            for x in bar.x + 1..bar.x + 1 + bar.filled(milestone) {
+                let addr = (row + x as usize * bytes_per_pixel) as *mut u8;
+                for (ii, byte) in pixel.iter().take(bytes_per_pixel).enumerate() {
+                    // SAFETY: The bar is on the screen, which the caller says is mapped
+                    unsafe { addr.add(ii).write_volatile(*byte) };
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_progress_bar() {
+    let bar = ProgressBar::new(640, 480);
+    assert_eq!((bar.x, bar.y, bar.width, bar.height), (213, 320, 213, 12));
+    assert_eq!(bar.filled("not a milestone"), 0);
+    let filled: Vec<u32> = MILESTONES.iter().map(|m| bar.filled(m)).collect();
+    assert!(filled.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(filled.last(), Some(&(bar.width - 2)));
+
+    // Tiny screens still get a bar on them
+    let bar = ProgressBar::new(320, 200);
+    assert!(bar.y + bar.height <= 200);
+}
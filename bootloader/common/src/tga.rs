@@ -0,0 +1,208 @@
+//! Truevision TGA images, the uncompressed and run length encoded true color kinds, which are
+//! easy to decode a pixel at a time and small enough to embed in a stage
+
+/// Size of the header in front of the image ID
+const HEADER_SIZE: usize = 18;
+/// Image type of uncompressed true color images
+const TYPE_TRUE_COLOR: u8 = 2;
+/// Image type of run length encoded true color images
+const TYPE_RLE_TRUE_COLOR: u8 = 10;
+/// Descriptor bit set when the first pixel is on the right
+const RIGHT_TO_LEFT: u8 = 1 << 4;
+/// Descriptor bit set when the first pixel is at the top
+const TOP_TO_BOTTOM: u8 = 1 << 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TgaError {
+    /// The file ends before the last pixel
+    Truncated,
+    /// Color mapped, black and white or not 24 or 32 bits per pixel
+    Unsupported,
+}
+
+/// A pixel of an image, with its position from the top left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pixel {
+    pub x: u16,
+    pub y: u16,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    /// Opacity, 0 is fully transparent. Always 0xff for images without alpha.
+    pub a: u8,
+}
+
+/// A TGA image that was checked to have every pixel
+#[derive(Debug, Clone, Copy)]
+pub struct Tga<'a> {
+    width: u16,
+    height: u16,
+    bytes_per_pixel: usize,
+    rle: bool,
+    descriptor: u8,
+    /// The pixel data, or packets of it for run length encoded images
+    data: &'a [u8],
+}
+
+impl<'a> Tga<'a> {
+    pub fn parse(file: &'a [u8]) -> Result<Self, TgaError> {
+        let header = file.get(..HEADER_SIZE).ok_or(TgaError::Truncated)?;
+        let rle = match header[2] {
+            TYPE_TRUE_COLOR => false,
+            TYPE_RLE_TRUE_COLOR => true,
+            _ => return Err(TgaError::Unsupported),
+        };
+        let bytes_per_pixel = match header[16] {
+            24 => 3,
+            32 => 4,
+            _ => return Err(TgaError::Unsupported),
+        };
+        // True color images have no color map, so the pixels come right after the image ID
+        if header[1] != 0 {
+            return Err(TgaError::Unsupported);
+        }
+        let image = Tga {
+            width: u16::from_le_bytes([header[12], header[13]]),
+            height: u16::from_le_bytes([header[14], header[15]]),
+            bytes_per_pixel,
+            rle,
+            descriptor: header[17],
+            data: file
+                .get(HEADER_SIZE + header[0] as usize..)
+                .ok_or(TgaError::Truncated)?,
+        };
+        // Going through the packets once means drawing never runs out of data half way
+        if image.pixels().count() != image.width as usize * image.height as usize {
+            return Err(TgaError::Truncated);
+        }
+        Ok(image)
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Every pixel in the order they are stored, which isn't necessarily top to bottom
+    pub fn pixels(&self) -> Pixels<'a> {
+        Pixels {
+            image: *self,
+            data: self.data,
+            index: 0,
+            run: 0,
+            repeat: false,
+        }
+    }
+}
+
+/// Decodes the pixels of a [`Tga`] one at a time
+pub struct Pixels<'a> {
+    image: Tga<'a>,
+    /// What is left of the pixel data
+    data: &'a [u8],
+    /// Number of the next pixel in storage order
+    index: usize,
+    /// Pixels left in the current packet
+    run: u8,
+    /// Whether the current packet is one pixel repeated, rather than a run of different ones
+    repeat: bool,
+}
+
+impl Iterator for Pixels<'_> {
+    type Item = Pixel;
+
+    fn next(&mut self) -> Option<Pixel> {
+        let image = &self.image;
+        if image.width == 0 || self.index == image.width as usize * image.height as usize {
+            return None;
+        }
+        if self.run == 0 {
+            if image.rle {
+                // The top bit says if the packet repeats a pixel, the rest is its length - 1
+                let (&packet, data) = self.data.split_first()?;
+                self.data = data;
+                self.run = (packet & 0x7f) + 1;
+                self.repeat = packet & 0x80 != 0;
+            } else {
+                self.run = 1;
+            }
+        }
+
+        let color = self.data.get(..image.bytes_per_pixel)?;
+        let pixel = Pixel {
+            x: 0,
+            y: 0,
+            r: color[2],
+            g: color[1],
+            b: color[0],
+            a: color.get(3).copied().unwrap_or(0xff),
+        };
+        self.run -= 1;
+        if !self.repeat || self.run == 0 {
+            self.data = &self.data[image.bytes_per_pixel..];
+        }
+
+        let column = (self.index % image.width as usize) as u16;
+        let row = (self.index / image.width as usize) as u16;
+        self.index += 1;
+        Some(Pixel {
+            x: if image.descriptor & RIGHT_TO_LEFT != 0 {
+                image.width - 1 - column
+            } else {
+                column
+            },
+            y: if image.descriptor & TOP_TO_BOTTOM != 0 {
+                row
+            } else {
+                image.height - 1 - row
+            },
+            ..pixel
+        })
+    }
+}
+
+#[test]
+fn test_tga() {
+    let header = |image_type: u8, depth: u8, descriptor: u8| {
+        let mut header = vec![0; HEADER_SIZE];
+        header[2] = image_type;
+        header[12] = 2;
+        header[14] = 2;
+        header[16] = depth;
+        header[17] = descriptor;
+        header
+    };
+    let pixel = |x, y, r, g, b, a| Pixel { x, y, r, g, b, a };
+
+    // 2x2 stored bottom row first, a red run across both pixels and a raw packet of two
+    let mut file = header(TYPE_RLE_TRUE_COLOR, 32, 8);
+    file.extend([0x81, 0, 0, 0xff, 0xff]);
+    file.extend([0x01, 0xff, 0, 0, 0xff, 0, 0xff, 0, 0]);
+    let image = Tga::parse(&file).unwrap();
+    assert_eq!((image.width(), image.height()), (2, 2));
+    let pixels: Vec<Pixel> = image.pixels().collect();
+    assert_eq!(
+        pixels,
+        [
+            pixel(0, 1, 0xff, 0, 0, 0xff),
+            pixel(1, 1, 0xff, 0, 0, 0xff),
+            pixel(0, 0, 0, 0, 0xff, 0xff),
+            pixel(1, 0, 0, 0xff, 0, 0),
+        ]
+    );
+    file.pop();
+    assert_eq!(Tga::parse(&file).err(), Some(TgaError::Truncated));
+
+    // Uncompressed, top row first and right to left
+    let mut file = header(TYPE_TRUE_COLOR, 24, TOP_TO_BOTTOM | RIGHT_TO_LEFT);
+    file.extend((0..12).map(|byte| byte as u8));
+    let image = Tga::parse(&file).unwrap();
+    assert_eq!(image.pixels().next(), Some(pixel(1, 0, 2, 1, 0, 0xff)));
+    assert_eq!(image.pixels().last(), Some(pixel(0, 1, 11, 10, 9, 0xff)));
+
+    let file = header(1, 8, 0);
+    assert_eq!(Tga::parse(&file).err(), Some(TgaError::Unsupported));
+}
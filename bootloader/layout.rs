@@ -0,0 +1,87 @@
+//! Where the bootloader stages live, on disk and in memory.
+//!
+//! This is the only place the layout is written down. `common` re-exports everything here, and
+//! the stage build scripts include this file to fill in their linker script templates, see
+//! link_script.rs. Change a stage size here and everything else follows.
+//!
+//! Only plain `usize` constants go in here so build scripts can include it as is.
+
+/// Size of a disk sector in bytes
+pub const SECTOR_BYTES: usize = 0x200;
+/// Size of a CD sector in bytes
+pub const CD_SECTOR_BYTES: usize = 0x800;
+
+/// Where the chainloading MBR copies itself to, out of the way of the boot sector it loads
+pub const MBR_START: usize = 0x600;
+
+/// The start of the first stage in memory, defined by BIOS
+pub const STAGE_0_START: usize = 0x7c00;
+/// Number of 512 byte sections stage 0 takes up
+pub const STAGE_0_SECTIONS: usize = 1;
+/// Number of 512 byte sections stage 1 takes up
+pub const STAGE_1_SECTIONS: usize = 0x40;
+/// Number of 512 byte sections stage 2 takes up
+pub const STAGE_2_SECTIONS: usize = 0x18;
+
+/// Where stage 1 is loaded, right after stage 0
+pub const STAGE_1_START: usize = STAGE_0_START + STAGE_0_SECTIONS * SECTOR_BYTES;
+/// Where stage 2 is loaded, right after stage 1
+pub const STAGE_2_START: usize = STAGE_1_START + STAGE_1_SECTIONS * SECTOR_BYTES;
+/// End of the memory the stages are loaded to
+pub const STAGES_END: usize = STAGE_2_START + STAGE_2_SECTIONS * SECTOR_BYTES;
+
+/// First sector the following stages go in by default, the image builder tells stage 0 where they
+/// really are. Sectors 1 to 33 are left free for a GPT header and partition entry array, so the
+/// same stage 0 boots both MBR and GPT disk images.
+///
+/// This is also CD sector 20, right after the ISO 9660 volume descriptors and El Torito boot
+/// catalog, so CD images keep the stages at the same byte offset.
+pub const STAGES_START_LBA: usize = 80;
+
+/// Offset in stage 0 of the table the image builder fills in with where the following stages are,
+/// see `common::disk::StageLocation`. Right in front of the partition table.
+pub const STAGE_LOCATION_OFFSET: usize = 446 - 8;
+
+/// Total number of boot sectors we need to read. Not including the 0th boot sector loaded into
+/// memory from the bios.
+pub const SECTORS_TO_READ: usize = STAGE_1_SECTIONS + STAGE_2_SECTIONS;
+
+/// Where stage 1 writes the `BiosInfo` handed to the kernel
+pub const BIOS_INFO_ADDRESS: usize = 0x5000;
+
+/// Where stage 1 stores the memory map
+pub const MEMORY_MAP_ADDRESS: usize = 0x6000;
+/// Most entries the memory map holds, twice what stage 1 reads from the BIOS since sorting out
+/// overlaps can split regions
+pub const MEMORY_MAP_ENTRIES: usize = 128;
+
+/// Where stage 2 builds the boot information for Multiboot2 kernels, in the free memory after
+/// the stages
+pub const MULTIBOOT_INFO_ADDRESS: usize = 0x20000;
+/// Most bytes the Multiboot2 boot information can take up
+pub const MULTIBOOT_INFO_SIZE: usize = 0x10000;
+
+/// Where stage 2 fills in the `boot_params` zero page for Linux kernels
+pub const LINUX_BOOT_PARAMS_ADDRESS: usize = 0x30000;
+/// Where stage 2 copies the command line for Linux kernels, which has to be nul terminated
+pub const LINUX_CMDLINE_ADDRESS: usize = 0x31000;
+/// Most bytes the Linux command line can take up, including the nul
+pub const LINUX_CMDLINE_SIZE: usize = 0x1000;
+
+/// Where the kernel is linked, the start of memory above the first MiB. Kernel segments can't be
+/// loaded below this, that's where the bootloader and BIOS live.
+pub const KERNEL_ADDRESS: usize = 0x100000;
+/// Where stage 1 loads the kernel file from the boot partition to. Stage 2 copies the segments
+/// from here to where they are linked, so this leaves the kernel 15 MiB to grow into.
+pub const KERNEL_FILE_ADDRESS: usize = 0x1000000;
+
+// Stage 1 runs in real mode with zero segments, so all of it has to be in the first 64 KiB. Stage
+// 0 moves on to the next segment as it reads, stage 2 can go past that.
+const _: () = assert!(STAGE_2_START <= 0x10000, "stage 1 doesn't fit below 64 KiB");
+
+// Stage 0 reads whole sectors, so on CDs the stages have to start and end on a CD sector
+const _: () = assert!(
+    (STAGES_START_LBA * SECTOR_BYTES).is_multiple_of(CD_SECTOR_BYTES)
+        && (SECTORS_TO_READ * SECTOR_BYTES).is_multiple_of(CD_SECTOR_BYTES),
+    "stages aren't CD sector aligned"
+);
@@ -0,0 +1,61 @@
+//! Fills in a stage's link.ld.in template with the values from layout.rs. Included by the stage
+//! build scripts next to layout.rs:
+//!
+//! ```ignore
+//! #[path = "../layout.rs"]
+//! mod layout;
+//! #[path = "../link_script.rs"]
+//! mod link_script;
+//! ```
+//!
+//! Templates refer to layout constants as `@NAME@`.
+
+use std::path::{Path, PathBuf};
+
+use crate::layout::*;
+
+/// Every constant a template can use
+const VARIABLES: [(&str, usize); 11] = [
+    ("SECTOR_BYTES", SECTOR_BYTES),
+    ("MBR_START", MBR_START),
+    ("STAGE_0_START", STAGE_0_START),
+    ("STAGE_1_START", STAGE_1_START),
+    ("STAGE_1_END", STAGE_2_START),
+    ("STAGE_2_START", STAGE_2_START),
+    ("STAGE_2_END", STAGES_END),
+    ("STAGES_START_LBA", STAGES_START_LBA),
+    ("SECTORS_TO_READ", SECTORS_TO_READ),
+    ("STAGE_LOCATION_OFFSET", STAGE_LOCATION_OFFSET),
+    ("KERNEL_ADDRESS", KERNEL_ADDRESS),
+];
+
+/// Replaces every `@NAME@` in `template`, panicking on names that aren't defined
+pub fn render(template: &str) -> String {
+    let mut script = template.to_string();
+    for (name, value) in VARIABLES {
+        script = script.replace(&format!("@{name}@"), &format!("0x{value:x}"));
+    }
+    if let Some(at) = script.find('@') {
+        let name: String = script[at..]
+            .chars()
+            .take_while(|c| !c.is_whitespace())
+            .collect();
+        panic!("Unknown layout variable {name} in linker script template");
+    }
+    script
+}
+
+/// Renders link.ld.in from the package directory into OUT_DIR and tells cargo to link with it
+pub fn generate() {
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let template_path = manifest_dir.join("link.ld.in");
+    let layout_path = manifest_dir.join("..").join("layout.rs");
+    let template = std::fs::read_to_string(&template_path).unwrap();
+
+    let out = Path::new(&std::env::var("OUT_DIR").unwrap()).join("link.ld");
+    std::fs::write(&out, render(&template)).unwrap();
+
+    println!("cargo:rerun-if-changed={}", template_path.display());
+    println!("cargo:rerun-if-changed={}", layout_path.display());
+    println!("cargo:rustc-link-arg-bins=--script={}", out.display());
+}
@@ -0,0 +1,10 @@
+#[allow(dead_code)]
+#[path = "../layout.rs"]
+mod layout;
+#[path = "../link_script.rs"]
+mod link_script;
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/boot.s");
+    link_script::generate();
+}
@@ -0,0 +1,117 @@
+.section .boot, "awx"
+.global _start
+.code16
+
+# Number of times the partition's boot sector is read before giving up
+.set READ_ATTEMPTS, 3
+
+_start:
+  # zero segment registers and put the stack below where we were loaded
+  cli
+  xor ax, ax
+  mov ds, ax
+  mov es, ax
+  mov ss, ax
+  mov sp, 0x7c00
+  sti
+  cld
+
+  # The partition's boot sector goes where we were loaded, so copy ourselves to where we are
+  # linked and continue there. Everything before the jump has to be position independent.
+  mov si, 0x7c00
+  mov di, offset _mbr_start
+  mov cx, 0x100
+  rep movsw
+  ljmp 0, offset relocated
+
+relocated:
+  # Find the active partition, DL still holds the boot drive
+  mov bp, offset _partition_table
+  mov cx, 4
+find_active:
+  test byte ptr [bp], 0x80
+  jnz found
+  add bp, 16
+  loop find_active
+  mov si, offset no_active_partition
+  jmp fail
+
+found:
+  mov di, READ_ATTEMPTS
+read:
+  # Prefer the int 0x13 extensions, the CHS address in the entry can't reach past 8 GiB
+  push dx
+  mov ah, 0x41
+  mov bx, 0x55aa
+  int 0x13
+  pop dx
+  jc read_chs
+  cmp bx, 0xaa55
+  jne read_chs
+  test cl, 1
+  jz read_chs
+
+  # Disk address packet for one sector to 0x0000:0x7c00, built on the stack
+  push 0
+  push 0
+  push word ptr [bp + 10]
+  push word ptr [bp + 8]
+  push 0
+  push 0x7c00
+  push 1
+  push 0x10
+  mov si, sp
+  mov ah, 0x42
+  int 0x13
+  # lea leaves the carry flag alone
+  lea sp, [si + 16]
+  jmp check_read
+
+read_chs:
+  mov dh, [bp + 1]
+  mov cx, [bp + 2]
+  mov bx, 0x7c00
+  mov ax, 0x0201
+  int 0x13
+
+check_read:
+  jnc loaded
+  dec di
+  jz read_failed
+  # Reset the drive before trying again
+  xor ah, ah
+  int 0x13
+  jmp read
+
+read_failed:
+  mov si, offset disk_error
+  jmp fail
+
+loaded:
+  cmp word ptr [0x7dfe], 0xaa55
+  jne not_bootable
+
+  # Hand over like the BIOS would, plus DS:SI pointing at the partition entry
+  mov si, bp
+  ljmp 0, 0x7c00
+
+not_bootable:
+  mov si, offset missing_signature
+
+# Prints the nul terminated string at SI and halts
+fail:
+  lodsb
+  test al, al
+  jz spin
+  mov ah, 0x0e
+  xor bx, bx
+  int 0x10
+  jmp fail
+
+spin:
+  hlt
+  jmp spin
+
+no_active_partition: .asciz "No active partition"
+disk_error: .asciz "Disk error"
+missing_signature: .asciz "Missing boot signature"
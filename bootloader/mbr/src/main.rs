@@ -0,0 +1,17 @@
+//! A standard master boot record for images where the bootloader lives in a partition. Moves
+//! itself out of the way, loads the first sector of the active partition to where the BIOS loads
+//! boot sectors and jumps to it, with DL holding the drive and DS:SI pointing at the partition
+//! entry. All of it is in boot.s, it has to fit in front of the partition table.
+
+#![no_std]
+#![no_main]
+
+use core::arch::global_asm;
+
+global_asm!(include_str!("boot.s"));
+
+use core::panic::PanicInfo;
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {}
+}
@@ -1,15 +1,10 @@
-use std::path::Path;
+#[allow(dead_code)]
+#[path = "../layout.rs"]
+mod layout;
+#[path = "../link_script.rs"]
+mod link_script;
 
 fn main() {
-    let local_path = Path::new(env!("CARGO_MANIFEST_DIR"));
-    let asm_path = local_path
-        .join("bootloader")
-        .join("stage-0")
-        .join("src")
-        .join("boot.s");
-    println!("cargo:rerun-if-changed={}", asm_path.display());
-    println!(
-        "cargo:rustc-link-arg-bins=--script={}",
-        local_path.join("link.ld").display()
-    )
+    println!("cargo:rerun-if-changed=src/boot.s");
+    link_script::generate();
 }
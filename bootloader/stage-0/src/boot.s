@@ -2,7 +2,57 @@
 .global _start
 .code16
 
-# Initialize the stack and jump to rust
+# Number of times a read is tried before giving up. Some USB sticks fail the first read after
+# boot.
+.set READ_ATTEMPTS, 3
+
+# The disk address packet for extended reads, followed by the variables of load_stages. SI
+# points at dap while it runs.
+.set DAP_COUNT, 2
+.set DAP_BUFFER, 4
+.set DAP_SEGMENT, 6
+.set DAP_LBA, 8
+.set DRIVE, 16
+# 0 while using extended reads
+.set SECTORS_PER_TRACK, 17
+.set LAST_HEAD, 18
+.set PARAGRAPHS_PER_SECTOR, 19
+
+# Stops the build if a variable isn't at its offset in dap
+.macro check_offset label, offset
+.if \label - dap != \offset
+.error "\label isn't at offset \offset of dap"
+.endif
+.endm
+
+.section .data.load_stages, "aw"
+dap:
+  .byte 0x10, 0
+dap_count: .word 1
+dap_buffer: .word 0
+dap_segment: .word 0
+dap_lba: .quad 0
+drive: .byte 0
+sectors_per_track: .byte 0
+last_head: .byte 0
+paragraphs_per_sector: .word 512 / 16
+
+check_offset dap_count, DAP_COUNT
+check_offset dap_buffer, DAP_BUFFER
+check_offset dap_segment, DAP_SEGMENT
+check_offset dap_lba, DAP_LBA
+check_offset drive, DRIVE
+check_offset sectors_per_track, SECTORS_PER_TRACK
+check_offset last_head, LAST_HEAD
+check_offset paragraphs_per_sector, PARAGRAPHS_PER_SECTOR
+
+.section .boot, "awx"
+
+.set COM1, 0x3f8
+.set LINE_STATUS, 5
+.set TRANSMIT_EMPTY, 0x20
+
+# Initialize the stack, load the following stages and jump to them
 _start:
   # Some BIOS' may load us at 0x0000:0x7C00 while other may load us at 0x07C0:0x0000.
   # Do a far jump to fix this issue, and reload CS to 0x0000.
@@ -25,11 +75,222 @@ _start:
   # instructions like lodsb)
   cld
 
-rust:
-  # push disk number as argument
+  # keep the disk number for the next stage
   push dx
-  call main
+  call load_stages
+
+  # Report the milestone on COM1, serial::STAGE_0_LOADED
+  mov si, offset loaded_message
+milestone:
+  mov dx, COM1 + LINE_STATUS
+wait_transmit:
+  in al, dx
+  test al, TRANSMIT_EMPTY
+  jz wait_transmit
+  mov dx, COM1
+  lodsb
+  test al, al
+  jz next_stage
+  out dx, al
+  jmp milestone
+
+  # Call the next stage like an extern "C" fn(disk_number: u16), the arguments and return
+  # address are 32 bits
+next_stage:
+  pop dx
+  push edx
+  movzx eax, word ptr [_stage_location + 6]
+  shl eax, 4
+  call eax
 
 spin:
   hlt
   jmp spin
+
+# Reads the following stages from where _stage_location says, DL holds the boot drive. Uses
+# extended reads when the BIOS has them, and falls back to CHS reads when it doesn't. Reads one
+# sector at a time, which keeps every read inside a segment and a track. The table counts 512
+# byte sectors on CDs too, the image builder keeps the stages in whole CD sectors there.
+load_stages:
+  mov si, offset dap
+  mov [si + DRIVE], dl
+  mov eax, [_stage_location]
+  mov [si + DAP_LBA], eax
+  mov ax, [_stage_location + 6]
+  mov [si + DAP_SEGMENT], ax
+
+  # DL stays the drive through the checks below
+  mov ah, 0x41
+  mov bx, 0x55aa
+  int 0x13
+  jc no_extensions
+  cmp bx, 0xaa55
+  jne no_extensions
+  test cl, 1
+  jz no_extensions
+
+  # Drive parameters for the sector size, 2048 on CDs. They go where stage 1 is read to later.
+  mov si, offset _second_stage_start
+  mov word ptr [si], 0x1a
+  mov ah, 0x48
+  int 0x13
+  mov si, offset dap
+  jc disk_error
+  mov ax, [_second_stage_start + 0x18]
+  jmp sector_count
+
+no_extensions:
+  # ES:DI should start as 0 to work around BIOS bugs, and is set to a table for floppies
+  mov ah, 0x08
+  xor di, di
+  push es
+  int 0x13
+  pop es
+  jc disk_error
+  # The low 6 bits of CL are the highest sector number, DH the highest head number
+  and cl, 0x3f
+  mov [si + SECTORS_PER_TRACK], cl
+  mov [si + LAST_HEAD], dh
+  mov ax, 512
+
+# AX is the sector size of the drive, count in those from here on
+sector_count:
+  mov di, [_stage_location + 4]
+sector_size:
+  cmp ax, 512
+  jbe read_next
+  shr ax, 1
+  shr dword ptr [si + DAP_LBA], 1
+  shr di, 1
+  shl word ptr [si + PARAGRAPHS_PER_SECTOR], 1
+  jmp sector_size
+
+# DI sectors are left to read
+read_next:
+  test di, di
+  jz loaded
+  mov bp, READ_ATTEMPTS
+read:
+  mov dl, [si + DRIVE]
+  cmp byte ptr [si + SECTORS_PER_TRACK], 0
+  jne read_chs
+  mov byte ptr [si + DAP_COUNT], 1
+  mov ah, 0x42
+  int 0x13
+  jmp check_read
+
+read_chs:
+  # The LBA is far below 2^31, so CDQ clears EDX
+  mov eax, [si + DAP_LBA]
+  cdq
+  movzx ebx, byte ptr [si + SECTORS_PER_TRACK]
+  div ebx
+  inc dx
+  mov cl, dl
+  cdq
+  movzx ebx, byte ptr [si + LAST_HEAD]
+  inc bx
+  div ebx
+  # CHS only has 10 bits for the cylinder
+  cmp eax, 1023
+  ja chs_too_far
+  # CH has the low 8 bits of the cylinder, CL the sector and the top 2 bits of the cylinder
+  mov ch, al
+  shl ah, 6
+  or cl, ah
+  mov dh, dl
+  mov dl, [si + DRIVE]
+  les bx, [si + DAP_BUFFER]
+  mov ax, 0x0201
+  int 0x13
+  # DS is 0 too
+  push ds
+  pop es
+
+check_read:
+  jnc read_done
+  dec bp
+  jz disk_error
+  # Reset the drive before trying again, DL is still the drive
+  xor ah, ah
+  int 0x13
+  jmp read
+
+read_done:
+  inc dword ptr [si + DAP_LBA]
+  mov ax, [si + PARAGRAPHS_PER_SECTOR]
+  add [si + DAP_SEGMENT], ax
+  dec di
+  jmp read_next
+
+loaded:
+  ret
+
+# Past the cylinders CHS can address, reported as status 0
+chs_too_far:
+  xor ah, ah
+# Prints a letter for the BIOS status in AH, the status and the LBA of the read in hex, then
+# halts. There is no room for DiskError::reason() here.
+disk_error:
+  mov dh, ah
+  # The last entry takes the status, so the search always stops
+  mov [unknown_status], dh
+  mov si, offset status_letters
+find_letter:
+  lodsw
+  cmp al, dh
+  jne find_letter
+  mov al, ah
+  mov di, offset error_letter
+  stosb
+  # Past the space
+  inc di
+
+  # The status goes in the top byte of EDX, followed by the LBA after two digits
+  shl edx, 16
+  mov cx, 10
+write_digit:
+  cmp cl, 8
+  jne 1f
+  mov edx, [dap + DAP_LBA]
+  # Past the space
+  inc di
+1:
+  rol edx, 4
+  mov al, dl
+  and al, 0x0f
+  # 0-9 to '0'-'9' and 10-15 to 'A'-'F'
+  cmp al, 10
+  sbb al, 0x69
+  das
+  stosb
+  loop write_digit
+
+  mov si, offset disk_error_message
+  xor bx, bx
+print:
+  lodsb
+  test al, al
+  jz spin
+  mov ah, 0x0e
+  int 0x10
+  jmp print
+
+loaded_message: .asciz "stage0: loaded\r\n"
+disk_error_message: .ascii "Disk error "
+error_letter: .asciz "? 00 00000000"
+
+# Letters for the statuses that come up the most, in pairs of status and letter
+status_letters:
+  # Timeout
+  .byte 0x80, 'T'
+  # Media changed
+  .byte 0x06, 'M'
+  # Bad sector
+  .byte 0x0a, 'B'
+  # CRC error, usually a bad sector too
+  .byte 0x10, 'C'
+  # DMA boundary
+  .byte 0x09, 'D'
+unknown_status:
+  .byte 0, '?'
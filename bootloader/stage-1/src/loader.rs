@@ -0,0 +1,196 @@
+//! Loads the kernel named in the boot configuration from the FAT32 boot partition, or from the
+//! ISO 9660 file system when booted from a CD
+
+use core::ptr::addr_of_mut;
+
+use common::config::{BootConfig, FontChoice, CONFIG_FILE, MAX_CONFIG_SIZE};
+use common::disk::SECTOR_SIZE;
+use common::fat::{short_name, Fat32, FatError};
+use common::iso9660::{Iso9660, IsoError};
+use common::partition::find_boot_partition;
+use common::println_bios;
+use common::real_mode::disk::BiosDisk;
+use common::{initrd_file_start, CD_SECTOR_BYTES, KERNEL_FILE_START};
+
+use crate::menu;
+use crate::vbe::FontSource;
+
+/// Scratch space for the partition tables, directories and file contents, a CD sector or two
+/// disk sectors. Lives here instead of on the stack, which is only a few KiB, and has to be in
+/// the first MiB for the BIOS.
+static mut SCRATCH: [u8; CD_SECTOR_BYTES] = [0; CD_SECTOR_BYTES];
+/// Contents of the configuration file
+static mut CONFIG: [u8; MAX_CONFIG_SIZE] = [0; MAX_CONFIG_SIZE];
+
+/// Where the boot files are
+enum BootVolume<'a> {
+    Fat(Fat32<'a, BiosDisk>),
+    Iso(Iso9660<'a, BiosDisk>),
+}
+
+impl BootVolume<'_> {
+    /// Calls `f` with consecutive pieces of the file called `name`, returning its size. `None`
+    /// if there is no such file.
+    fn read(&mut self, name: &str, f: impl FnMut(&[u8])) -> Option<u32> {
+        match self {
+            BootVolume::Fat(volume) => {
+                let Some(short) = short_name(name) else {
+                    panic!("File name {name} isn't 8.3");
+                };
+                let file = match volume.find(&short) {
+                    Ok(file) => file,
+                    Err(FatError::NotFound) => return None,
+                    Err(e) => panic!("Finding {name}: {e:?}"),
+                };
+                volume
+                    .read(&file, f)
+                    .unwrap_or_else(|e| panic!("Reading {name}: {e:?}"));
+                Some(file.size)
+            }
+            BootVolume::Iso(volume) => {
+                let file = match volume.find(name) {
+                    Ok(file) => file,
+                    Err(IsoError::NotFound) => return None,
+                    Err(e) => panic!("Finding {name}: {e:?}"),
+                };
+                volume
+                    .read(&file, f)
+                    .unwrap_or_else(|e| panic!("Reading {name}: {e:?}"));
+                Some(file.size)
+            }
+        }
+    }
+}
+
+/// The kernel file in memory, and what the configuration says to pass to it
+pub struct LoadedKernel {
+    /// Size of the file at `KERNEL_FILE_START` in bytes
+    pub size: u32,
+    pub cmdline: &'static str,
+    /// Size of the initrd at `initrd_file_start(size)`, 0 if there isn't one
+    pub initrd_size: u32,
+    /// The console font, a font file goes in the first page after the initrd
+    pub font: FontSource,
+    /// First address after the files
+    pub end: usize,
+}
+
+impl LoadedKernel {
+    /// The kernel file
+    pub fn file(&self) -> &'static [u8] {
+        // SAFETY: load_kernel read this many bytes here, and nothing else uses this memory
+        unsafe { core::slice::from_raw_parts(KERNEL_FILE_START, self.size as usize) }
+    }
+}
+
+/// Loads the kernel file to `KERNEL_FILE_START`, and the initrd and font file after it if the
+/// configuration names them. The boot menu picks the entry first if it is shown. A missing font
+/// file falls back to the built-in font.
+///
+/// Has to be in unreal mode, the files are copied above the first MiB.
+pub fn load_kernel(drive: u8) -> LoadedKernel {
+    let mut disk = match BiosDisk::new(drive) {
+        Ok(disk) => disk,
+        Err(e) => panic!("Drive {drive:#x}: {}", e.reason()),
+    };
+    // SAFETY: Stage 1 is single threaded and the buffers are only used here
+    let (scratch, config) = unsafe { (&mut *addr_of_mut!(SCRATCH), &mut *addr_of_mut!(CONFIG)) };
+
+    let mut volume = if disk.sector_size() == CD_SECTOR_BYTES {
+        match Iso9660::open(&mut disk, scratch) {
+            Ok(volume) => BootVolume::Iso(volume),
+            Err(e) => panic!("Bad CD: {e:?}"),
+        }
+    } else {
+        let (sector, fat_sector) = scratch.split_at_mut(SECTOR_SIZE);
+        let sector: &mut [u8; SECTOR_SIZE] = sector.try_into().unwrap();
+        let fat_sector = fat_sector.first_chunk_mut::<SECTOR_SIZE>().unwrap();
+        let partition = match find_boot_partition(&mut disk, sector) {
+            Ok(lba) => lba,
+            Err(e) => panic!("No boot partition: {e:?}"),
+        };
+        match Fat32::open(&mut disk, sector, fat_sector, partition) {
+            Ok(volume) => BootVolume::Fat(volume),
+            Err(e) => panic!("Bad boot partition: {e:?}"),
+        }
+    };
+
+    // Missing config means defaults for everything
+    let mut config_len = 0;
+    volume.read(CONFIG_FILE, |piece| {
+        let len = piece.len().min(MAX_CONFIG_SIZE - config_len);
+        config[config_len..config_len + len].copy_from_slice(&piece[..len]);
+        config_len += len;
+    });
+    // The command line is handed to the kernel, so the file has to stay where it is
+    let config: &'static [u8] = config;
+    let config = BootConfig::parse(core::str::from_utf8(&config[..config_len]).unwrap_or(""));
+    let entry = menu::choose(&config);
+    println_bios!("Booting {}", entry.title);
+
+    // SAFETY: Nothing else lives above the first MiB yet
+    let size = unsafe { load_file(&mut volume, entry.kernel, KERNEL_FILE_START) };
+    let Some(size) = size else {
+        panic!("{} not found", entry.kernel);
+    };
+    let initrd_size = match entry.initrd {
+        // SAFETY: The initrd goes after the kernel file
+        Some(initrd) => match unsafe { load_file(&mut volume, initrd, initrd_file_start(size)) } {
+            Some(initrd_size) => initrd_size,
+            None => panic!("{initrd} not found"),
+        },
+        None => 0,
+    };
+
+    let mut end = initrd_file_start(size) as usize + initrd_size as usize;
+    let font = match config.font {
+        FontChoice::Builtin => FontSource::Builtin,
+        FontChoice::Bios => FontSource::Bios,
+        FontChoice::File(name) => {
+            let start = end.next_multiple_of(0x1000);
+            // SAFETY: The font goes after the initrd
+            match unsafe { load_file(&mut volume, name, start as *mut u8) } {
+                Some(len) => {
+                    end = start + len as usize;
+                    // SAFETY: Just read here, and nothing else uses this memory
+                    FontSource::Psf(unsafe {
+                        core::slice::from_raw_parts(start as *const u8, len as usize)
+                    })
+                }
+                None => {
+                    println_bios!("{name} not found, using the built-in font");
+                    FontSource::Builtin
+                }
+            }
+        }
+    };
+
+    LoadedKernel {
+        size,
+        cmdline: entry.cmdline,
+        initrd_size,
+        font,
+        end,
+    }
+}
+
+/// Copies the file called `name` to `destination`, returning its size in bytes. `None` if there
+/// is no such file.
+///
+/// # Safety
+///
+/// The file has to fit in free memory at `destination`
+unsafe fn load_file(volume: &mut BootVolume, name: &str, destination: *mut u8) -> Option<u32> {
+    let mut offset = 0;
+    let size = volume.read(name, |piece| {
+        // SAFETY: The caller checked there is room
+        unsafe {
+            core::ptr::copy_nonoverlapping(piece.as_ptr(), destination.add(offset), piece.len());
+        }
+        offset += piece.len();
+    });
+    if let Some(size) = size {
+        println_bios!("Loaded {name} ({size} bytes)");
+    }
+    size
+}
@@ -4,12 +4,24 @@
 #![deny(unsafe_op_in_unsafe_fn)]
 
 use core::arch::asm;
+use core::ptr::addr_of_mut;
 
 use common::gdt::*;
+use common::memory::{self, MemoryKind, MemoryRegion};
+use common::multiboot2;
 use common::println_bios;
+use common::real_mode::a20;
 use common::real_mode::hlt;
-use common::MEMORY_MAP_START;
+use common::real_mode::memory::read_memory_map;
+use common::serial::{self, SerialWriter};
+use common::{
+    BiosInfo, CmdlineInfo, MemoryMapEntry, MemoryMapInfo, BIOS_INFO, MEMORY_MAP_ENTRIES,
+    MEMORY_MAP_START, STAGE_2_START,
+};
+use loader::LoadedKernel;
+use vbe::Color;
 use vbe::FrameBuffer;
+use vbe::ModeRequest;
 use vbe::Screen;
 
 static GDT_PROTECTED: Gdt = Gdt::protected_mode();
@@ -17,8 +29,13 @@ static GDT_PROTECTED: Gdt = Gdt::protected_mode();
 use core::panic::PanicInfo;
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    {
+        use core::fmt::Write;
+        let _ = writeln!(SerialWriter, "PANIC: {info}\r");
+    }
     if vbe::Screen.font().is_some() {
         use core::fmt::Write;
+        Screen.set_colors(Color::RED, Color::BLACK);
         Screen.reset();
         writeln!(Screen, "PANIC: {info}");
     } else {
@@ -28,105 +45,154 @@ fn panic(info: &PanicInfo) -> ! {
 }
 
 use vbe::init_graphical;
+pub mod loader;
+mod menu;
+mod splash;
 pub mod vbe;
 
 #[link_section = ".start"]
 #[no_mangle]
-pub extern "C" fn _start(_disk_number: u16) {
+pub extern "C" fn _start(disk_number: u16) {
+    serial::init();
     println_bios!("Starting stage 1");
 
-    unsafe {
-        enable_a20();
+    match a20::enable() {
+        Some(method) => println_bios!("A20 line: {}", method.name()),
+        None => panic!("Couldn't enable the A20 line"),
     }
+    serial::milestone(serial::STAGE_1_A20);
+    unsafe { enter_unreal_mode() };
 
     if !has_cpuid() {
         panic!("CPUID not present");
     }
 
-    let count = unsafe { detect_memory() };
-    init_graphical();
+    let count = detect_memory();
+    serial::milestone(serial::STAGE_1_E820);
+    let kernel = loader::load_kernel(disk_number as u8);
+    serial::milestone(serial::STAGE_1_KERNEL);
+    match video_mode(&kernel) {
+        Some(request) => {
+            init_graphical(request, kernel.font, free_memory(&kernel, count));
+            splash::show(serial::STAGE_1_KERNEL);
+        }
+        None => println_bios!("Staying in text mode for the kernel"),
+    }
+    write_bios_info(disk_number as u8, count, kernel.cmdline);
 
-    panic!("Not ready for next stage");
     unsafe {
         load_gdt();
-        next_stage(count);
+        next_stage(kernel.size, kernel.initrd_size);
     }
     panic!("Returned back to stage 1");
 }
 
-/// Detects memory using int 0x15 with eax = 0xE820, returns number of entries read
-unsafe fn detect_memory() -> u16 {
-    let int15_ax = 0xE820;
-    let magic_number = 0x534d4150;
-    let mem_address: u16 = MEMORY_MAP_START as u16;
-
-    // Registers
-    let mut eax = int15_ax;
-    let mut di = mem_address;
-    let mut ebx = 0;
-    let mut ecx = 24;
-    let mut edx = magic_number;
-
-    let mut count = 0;
-    loop {
-        count += 1;
-        unsafe {
-            asm!(
-                "int 0x15",
-                // TODO: Put this check outside and remove "fail_asm" call. This doesn't even work
-                // really
-                "jc fail_asm",
-                // https://wiki.osdev.org/Detecting_Memory_(x86)#BIOS_Function:_INT_0x15,_EAX_=_0xE820
-                // If success:
-                // Carry is clear
-                // check EAX is magic number
-                // EBX is nonzero, should be preserved to next call
-                // CL has number of bytes stored (probably 20)
-                // If end:
-                // ebx == 0 or carry flag is set
-                inout("di") di,
-                inout("eax") eax,
-                inout("ebx") ebx,
-                inout("ecx") ecx,
-                inout("edx") edx,
-            );
-        }
+/// Regions as the BIOS reported them, before sorting out overlaps. Not on the stack, which is
+/// only a few KiB.
+static mut BIOS_MEMORY_MAP: [MemoryRegion; MEMORY_MAP_ENTRIES / 2] =
+    [MemoryRegion::EMPTY; MEMORY_MAP_ENTRIES / 2];
 
-        if eax != magic_number {
-            panic!("bad eax mem");
-        }
+/// Reads the memory map from the BIOS and stores it sorted and without overlaps at
+/// `MEMORY_MAP_START`, returns the number of entries
+fn detect_memory() -> u16 {
+    // SAFETY: Nothing else uses the buffer
+    let regions = unsafe { &mut *addr_of_mut!(BIOS_MEMORY_MAP) };
+    let Some((count, source)) = read_memory_map(regions) else {
+        panic!("Couldn't read the memory map");
+    };
 
-        if ecx != 20 {
-            panic!("mem offset bad");
-        }
+    let map = MEMORY_MAP_START as *mut MemoryMapEntry;
+    let mut entries = 0;
+    memory::sanitize(&regions[..count], |region| {
+        // SAFETY: There is room for MEMORY_MAP_ENTRIES, twice as many as the BIOS regions
+        unsafe { map.add(entries).write(region.to_entry()) };
+        entries += 1;
+    });
+    println_bios!("Memory map: {entries} regions from {}", source.name());
+    entries as u16
+}
 
-        // TODO: Also check carry is clear
-        if ebx == 0 {
-            break;
-        }
+/// The usable memory after the kernel file, initrd and font file, up to the end of its region of
+/// the memory map. Nothing else is there until stage 2 runs.
+fn free_memory(kernel: &LoadedKernel, memory_map_count: u16) -> Option<&'static mut [u8]> {
+    let start = (kernel.end as u64).next_multiple_of(0x1000);
+    // SAFETY: detect_memory wrote this many entries
+    let memory_map = unsafe {
+        let map = MEMORY_MAP_START as *const MemoryMapEntry;
+        core::slice::from_raw_parts(map, memory_map_count as usize)
+    };
+    let region = memory_map.iter().find(|entry| {
+        MemoryKind::from_e820(entry.region_type) == MemoryKind::Usable
+            && entry.base <= start
+            && start < entry.base + entry.length
+    })?;
+    // Unreal mode only reaches the first 4 GiB
+    let end = (region.base + region.length).min(1 << 32);
+    // SAFETY: Usable memory that nothing else uses
+    Some(unsafe { core::slice::from_raw_parts_mut(start as *mut u8, (end - start) as usize) })
+}
 
-        di += 24;
-        eax = int15_ax;
-        ecx = 24;
-    }
+/// The video mode the kernel wants, `None` to leave it in VGA text mode
+fn video_mode(kernel: &LoadedKernel) -> Option<ModeRequest> {
+    let header = match multiboot2::Header::find(kernel.file()) {
+        None => return Some(ModeRequest::preferred()),
+        Some(Ok(header)) => header,
+        Some(Err(e)) => panic!("Bad Multiboot2 header: {e:?}"),
+    };
 
-    // Reference: https://wiki.osdev.org/Detecting_Memory_(x86)
-    // TODO: Increment di, reset eax and ecx, until ebx==0 or carry is set
-    count
+    // Multiboot2 kernels without a framebuffer tag expect text mode, and 0 is no preference
+    let request = header.framebuffer?;
+    let preferred = ModeRequest::preferred();
+    let or_default = |value: u32, default: u16| match value {
+        0 => default,
+        value => value.min(u16::MAX as u32) as u16,
+    };
+    Some(ModeRequest {
+        width: or_default(request.width, preferred.width),
+        height: or_default(request.height, preferred.height),
+        // Anything else can't be drawn to, so settle for the default
+        depth: match request.depth {
+            depth @ (15 | 16 | 24 | 32) => depth as u8,
+            _ => preferred.depth,
+        },
+    })
 }
 
-unsafe fn next_stage(count: u16) {
+/// Fills in the info handed to the kernel
+fn write_bios_info(boot_drive: u8, memory_map_count: u16, cmdline: &'static str) {
+    let memory_map = MemoryMapInfo {
+        address: MEMORY_MAP_START as u64,
+        count: memory_map_count as u32,
+        entry_size: size_of::<MemoryMapEntry>() as u32,
+    };
+    let info = BiosInfo::new(
+        boot_drive,
+        memory_map,
+        vbe::framebuffer_info(),
+        vbe::font_info(),
+        CmdlineInfo {
+            address: cmdline.as_ptr() as u64,
+            len: cmdline.len() as u64,
+        },
+    );
+    // SAFETY: Nothing else uses this memory
+    unsafe { BIOS_INFO.write(info) };
+}
+
+unsafe fn next_stage(kernel_size: u32, initrd_size: u32) {
     // Perform long jump
     unsafe {
-        let entry_point = 0x7c00 + 0x600;
+        let entry_point = STAGE_2_START;
         asm!(
             // align the stack
             "and esp, 0xffffff00",
-            // push arguments
-            "push {info:e}",
+            // push arguments, last one first
+            "push {initrd_size:e}",
+            "push {kernel_size:e}",
             // push entry point address
             "push {entry_point:e}",
-            info = in(reg) count as u32,
+            kernel_size = in(reg) kernel_size,
+            initrd_size = in(reg) initrd_size,
             entry_point = in(reg) entry_point as u32,
         );
         // Perform a "long jump" to one line down.
@@ -175,28 +241,36 @@ unsafe fn load_gdt() {
     }
 }
 
-#[inline(always)]
-unsafe fn enable_a20() {
-    // enable A20-Line via IO-Port 92, might not work on all motherboards
-    // Check if A20 is enabled
-    let al: u8;
+/// Enters unreal mode, so `ds` and `es` can address all 4 GiB with 32 bit offsets while we keep
+/// using the BIOS. Needed to copy the kernel above the first MiB and to draw to the framebuffer.
+///
+/// Loading a segment register in protected mode caches its 4 GiB limit, and going back to real
+/// mode only changes the base when the register is reloaded.
+unsafe fn enter_unreal_mode() {
+    GDT_PROTECTED.load();
     unsafe {
         asm!(
-            "in {al}, 0x92",
-            //"test al, 2",
-            al = out(reg_byte) al
+            "cli",
+            "push ds",
+            "push es",
+            // Enter protected mode
+            "mov eax, cr0",
+            "or al, 1",
+            "mov cr0, eax",
+            // Load the data segment from the GDT
+            "mov {selector:x}, 0x10",
+            "mov ds, {selector:x}",
+            "mov es, {selector:x}",
+            // Back to real mode, restoring the real mode segment bases
+            "and al, 0xfe",
+            "mov cr0, eax",
+            "pop es",
+            "pop ds",
+            "sti",
+            selector = out(reg) _,
+            out("eax") _,
         );
     }
-
-    if al != 2 {
-        //println(b"A20 already enabled");
-        return;
-    }
-
-    // Enable a20
-    unsafe {
-        asm!("or al, 2", "and al, 0xFE", "out 0x92, al",);
-    }
 }
 
 /// Checks if CPUID exists or not
@@ -0,0 +1,98 @@
+//! The boot menu, shown in text mode before anything is loaded when the configuration has a
+//! timeout or a key is pressed while stage 1 starts
+
+use core::arch::asm;
+use core::ptr::addr_of_mut;
+
+use common::config::{BootConfig, BootEntry};
+use common::menu::{Countdown, EditAction, LineEditor, Menu, MenuAction};
+use common::real_mode::clear_screen;
+use common::real_mode::keyboard::{poll_key, read_key, ticks};
+use common::{print_bios, println_bios};
+
+/// Longest command line that can be typed in the menu
+const MAX_CMDLINE: usize = 0x100;
+/// An edited command line, which is handed to the kernel so it can't be on the stack
+static mut CMDLINE: [u8; MAX_CMDLINE] = [0; MAX_CMDLINE];
+
+/// The entry to boot. Without a timeout the default one boots right away unless a key was
+/// pressed, otherwise the menu counts down until a key is pressed and then waits for a choice.
+pub fn choose(config: &BootConfig<'static>) -> BootEntry<'static> {
+    let entries = config.entries();
+    if config.timeout == 0 && poll_key().is_none() {
+        return entries[0];
+    }
+
+    let mut menu = Menu::new(entries.len());
+    let mut countdown = (config.timeout != 0).then(|| Countdown::new(config.timeout, ticks()));
+    loop {
+        draw(config, menu.selected());
+        let mut shown = None;
+        let key = loop {
+            if let Some(key) = poll_key() {
+                break key;
+            }
+            if let Some(countdown) = countdown {
+                let left = countdown.seconds_left(ticks());
+                if left == 0 {
+                    clear_screen();
+                    return entries[menu.selected()];
+                }
+                if shown != Some(left) {
+                    print_bios!("\rBooting in {left}s ");
+                    shown = Some(left);
+                }
+            }
+            // Sleep until the next timer tick or key press
+            unsafe { asm!("hlt") };
+        };
+        // Any key stops the countdown
+        countdown = None;
+
+        let entry = entries[menu.selected()];
+        match menu.key(key) {
+            MenuAction::None => {}
+            MenuAction::Boot => {
+                clear_screen();
+                return entry;
+            }
+            MenuAction::Edit => {
+                if let Some(cmdline) = edit(entry.cmdline) {
+                    clear_screen();
+                    return BootEntry { cmdline, ..entry };
+                }
+            }
+        }
+    }
+}
+
+/// Clears the screen and lists the entries with a marker on the selected one
+fn draw(config: &BootConfig, selected: usize) {
+    clear_screen();
+    println_bios!("spenceros boot menu\n");
+    for (index, entry) in config.entries().iter().enumerate() {
+        let marker = if index == selected { '►' } else { ' ' };
+        println_bios!(" {marker} {}", entry.title);
+    }
+    println_bios!("\nUp and Down to choose, Enter to boot, e to edit the command line\n");
+}
+
+/// Lets the command line be changed under the menu, `None` if Escape was pressed
+fn edit(cmdline: &str) -> Option<&'static str> {
+    // SAFETY: Stage 1 is single threaded and nothing else uses the buffer
+    let buffer = unsafe { &mut *addr_of_mut!(CMDLINE) };
+    let mut editor = LineEditor::new(buffer, cmdline);
+    print_bios!(
+        "\rCommand line, Enter to boot or Escape to go back:\n{}",
+        editor.as_str()
+    );
+    loop {
+        match editor.key(read_key()) {
+            EditAction::None => {}
+            EditAction::Insert(c) => print_bios!("{c}"),
+            EditAction::Erase => print_bios!("\x08 \x08"),
+            EditAction::Done => return Some(editor.into_str()),
+            EditAction::Cancel => return None,
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! The boot splash shown once the video mode is set, a logo in the middle of the screen with a
+//! progress bar under it that the following stages fill in further
+
+use common::splash::{ProgressBar, BAR_COLOR, LOGO_MAX_HEIGHT};
+use common::tga::Tga;
+
+use crate::vbe::{Color, FrameBuffer, Screen};
+
+/// Run length encoded TGA drawn in the middle of the screen, at most `LOGO_MAX_HEIGHT` high
+static LOGO: &[u8] = include_bytes!("../logo.tga");
+
+/// Clears the screen and draws the logo and the progress bar filled up to `milestone`
+pub fn show(milestone: &str) {
+    let (width, height) = (Screen.width(), Screen.height());
+    Screen.fill(0, 0, width, height, &Color::BLACK);
+
+    let logo = match Tga::parse(LOGO) {
+        Ok(logo) => logo,
+        Err(e) => panic!("Bad logo: {e:?}"),
+    };
+    assert!(logo.height() as u32 <= LOGO_MAX_HEIGHT);
+    let x = (width as i32 - logo.width() as i32) / 2;
+    let y = (height as i32 - logo.height() as i32) / 2;
+    Screen.blit(x, y, &logo);
+
+    let bar = ProgressBar::new(width as u32, height as u32);
+    let (r, g, b) = BAR_COLOR;
+    let color = Color::new(r, g, b);
+    let (x, y) = (bar.x as u16, bar.y as u16);
+    Screen.draw_rect(x, y, bar.width as u16, bar.height as u16, &color);
+    let filled = bar.filled(milestone) as u16;
+    Screen.fill(x + 1, y + 1, filled, bar.height as u16 - 2, &color);
+    Screen.flush();
+}
@@ -1,14 +1,25 @@
+mod dirty;
 mod vbe_impl;
 use core::{
+    cell::Cell,
     fmt::Write,
-    sync::atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
+use common::edid::{Edid, EDID_SIZE};
+use common::font::Font;
 use common::println_bios;
-use vbe_impl::set_bitmap_font_from_bios;
+use common::tga::Tga;
+use common::{FontInfo, FrameBufferInfo, PixelFormat};
+use dirty::{DirtyRects, Rect};
+use vbe_impl::{read_edid, set_bitmap_font_from_bios, BiosFont};
 
 static mut FRAME_BUFFER: Option<FramebufferInfo> = None;
-static mut FONT: Option<[u8; 0x1000]> = None;
+static mut FONT: Option<Font<'static>> = None;
+
+/// The 8x16 PSF2 font used unless the configuration picks another one, code page 437 ordered
+/// with ASCII, box drawing, blocks and a few symbols
+static BUILTIN_FONT: &[u8] = include_bytes!("../font.psf");
 
 pub trait FrameBuffer {
     /// Gets number of pixels wide the screen is
@@ -17,41 +28,99 @@ pub trait FrameBuffer {
     fn height(&self) -> u16;
     /// Sets the given pixel the given color
     fn set_pixel(&self, x: u16, y: u16, c: &Color) -> bool;
-    /// Gets font bitmap
-    fn font(&self) -> Option<&'static [u8; 0x1000]>;
-    /// Sets the characer at the given position (in units of characters
-    fn set_char(&self, x: u16, y: u16, c: u8) -> bool {
-        if (x + 1) * 8 > self.width() || (y + 1) * 16 > self.height() {
-            panic!("Bad char position {x},{y}");
-            //return false;
+    /// Gets the font characters are drawn with
+    fn font(&self) -> Option<Font<'static>>;
+    /// Moves everything up by `rows` pixel rows, leaving the bottom rows as they were
+    fn scroll_up(&self, rows: u16);
+    /// Inverts the pixels of the given rectangle, doing it twice puts them back
+    fn invert(&self, x: u16, y: u16, width: u16, height: u16);
+    /// Sets every pixel of the given rectangle to a color
+    fn fill(&self, x: u16, y: u16, width: u16, height: u16, c: &Color) {
+        for y in y..y + height {
+            for x in x..x + width {
+                self.set_pixel(x, y, c);
+            }
         }
-
+    }
+    /// Draws a character at the given position, in units of characters, with its whole cell
+    /// in the background color. Characters the font doesn't have are drawn as `?`, false if it
+    /// doesn't have that either.
+    fn set_char(&self, x: u16, y: u16, c: char, fg: &Color, bg: &Color) -> bool {
         let font = match self.font() {
             Some(f) => f,
             None => panic!("No font set"),
-            //return false,
         };
+        let (width, height) = (font.width(), font.height());
+        if (x + 1) * width > self.width() || (y + 1) * height > self.height() {
+            panic!("Bad char position {x},{y}");
+        }
 
-        let offset = c as usize * 16;
-        for ii in 0..16 {
-            let mut mask = font[offset + ii];
-            let mut shift = 0;
-            while mask != 0 {
-                if mask & 1 != 0 {
-                    let x_px = 8 * x + 7 - shift;
-                    let y_px = 16 * y + ii as u16;
-                    self.set_pixel(x_px, y_px, &Color::WHITE);
-                }
-                shift += 1;
-                mask >>= 1;
+        let index = font.glyph_index(c).or_else(|| font.glyph_index('?'));
+        let Some(glyph) = index.and_then(|index| font.glyph(index)) else {
+            return false;
+        };
+        for (ii, row) in glyph.chunks(font.bytes_per_row()).enumerate() {
+            for jj in 0..width {
+                let color = if Font::is_set(row, jj) { fg } else { bg };
+                self.set_pixel(width * x + jj, height * y + ii as u16, color);
             }
         }
 
         true
     }
+    /// Draws the 1 pixel wide outline of a rectangle
+    fn draw_rect(&self, x: u16, y: u16, width: u16, height: u16, c: &Color) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.fill(x, y, width, 1, c);
+        self.fill(x, y + height - 1, width, 1, c);
+        self.fill(x, y, 1, height, c);
+        self.fill(x + width - 1, y, 1, height, c);
+    }
+    /// Draws a line between two points, leaving out the parts that are off the screen
+    fn draw_line(&self, x0: i32, y0: i32, x1: i32, y1: i32, c: &Color) {
+        // Bresenham's, with one error term for both axes so lines can go in any direction
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+        loop {
+            if (0..self.width() as i32).contains(&x) && (0..self.height() as i32).contains(&y) {
+                self.set_pixel(x as u16, y as u16, c);
+            }
+            if x == x1 && y == y1 {
+                break;
+            }
+            if 2 * error >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if 2 * error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+    /// Draws an image with its top left corner at the given position, leaving out the parts
+    /// that are off the screen and pixels that are less than half opaque
+    fn blit(&self, x: i32, y: i32, image: &Tga) {
+        for pixel in image.pixels().filter(|pixel| pixel.a >= 0x80) {
+            let (x, y) = (x + pixel.x as i32, y + pixel.y as i32);
+            if (0..self.width() as i32).contains(&x) && (0..self.height() as i32).contains(&y) {
+                self.set_pixel(x as u16, y as u16, &Color::new(pixel.r, pixel.g, pixel.b));
+            }
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// Tabs move to the next multiple of this many columns
+const TAB_WIDTH: usize = 8;
+/// Pixel rows of the underline showing the cursor, at the bottom of its cell
+const CURSOR_HEIGHT: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
     r: u8,
     g: u8,
@@ -83,11 +152,60 @@ impl Color {
         b: 0xff,
     };
 
-    pub fn new(r: u8, g: u8, b: u8) -> Self {
+    pub const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+
+    pub const DARK_GRAY: Color = Color {
+        r: 20,
+        g: 20,
+        b: 20,
+    };
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
         Color { r, g, b }
     }
 }
 
+/// The video mode to look for. The mode with the closest size and the same depth is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeRequest {
+    pub width: u16,
+    pub height: u16,
+    pub depth: u8,
+}
+
+impl ModeRequest {
+    /// Used when the display doesn't tell us its native resolution
+    pub const DEFAULT: ModeRequest = ModeRequest {
+        width: 1280,
+        height: 720,
+        depth: 32,
+    };
+
+    /// The native resolution of the display from its EDID, or [`Self::DEFAULT`] if there is no
+    /// EDID, like under some QEMU `-vga` types
+    pub fn preferred() -> ModeRequest {
+        let mut block = [0; EDID_SIZE];
+        if !read_edid(&mut block) {
+            return ModeRequest::DEFAULT;
+        }
+        match Edid::parse(&block).map(|edid| edid.native_resolution()) {
+            Ok(Some((width, height))) => {
+                println_bios!("Display prefers {width}x{height}");
+                ModeRequest {
+                    width,
+                    height,
+                    ..ModeRequest::DEFAULT
+                }
+            }
+            Ok(None) => ModeRequest::DEFAULT,
+            Err(e) => {
+                println_bios!("Ignoring bad EDID: {e:?}");
+                ModeRequest::DEFAULT
+            }
+        }
+    }
+}
+
 impl FrameBuffer for FramebufferInfo {
     fn width(&self) -> u16 {
         self.width
@@ -98,8 +216,88 @@ impl FrameBuffer for FramebufferInfo {
     fn set_pixel(&self, x: u16, y: u16, c: &Color) -> bool {
         self.set_pixel_impl(x, y, c)
     }
-    fn font(&self) -> Option<&'static [u8; 0x1000]> {
-        unsafe { FONT.as_ref() }
+    fn font(&self) -> Option<Font<'static>> {
+        unsafe { FONT }
+    }
+    fn scroll_up(&self, rows: u16) {
+        let pitch = self.bytes_per_scan_line as usize;
+        let len = pitch * self.height.saturating_sub(rows) as usize;
+        // SAFETY: Both ranges are inside the canvas, copy handles the overlap
+        unsafe {
+            let from = self.canvas().add(pitch * rows as usize);
+            core::ptr::copy(from, self.canvas(), len);
+        }
+        self.mark_dirty(Rect::new(0, 0, self.width, self.height));
+    }
+    fn invert(&self, x: u16, y: u16, width: u16, height: u16) {
+        let rect = Rect::new(x, y, width, height).clamp(self.width, self.height);
+        let bytes = rect.width as usize * self.bytes_per_pixel();
+        for y in rect.y..rect.y + rect.height {
+            let addr = self.get_pixel_address(rect.x, y);
+            for ii in 0..bytes {
+                // SAFETY: The rectangle is on the screen
+                unsafe { addr.add(ii).write(!addr.add(ii).read()) };
+            }
+        }
+        self.mark_dirty(rect);
+    }
+    fn fill(&self, x: u16, y: u16, width: u16, height: u16, c: &Color) {
+        let rect = Rect::new(x, y, width, height).clamp(self.width, self.height);
+        let pixel = self.format.encode(c.r, c.g, c.b);
+        for y in rect.y..rect.y + rect.height {
+            let row = self.get_pixel_address(rect.x, y);
+            for x in 0..rect.width as usize {
+                // SAFETY: The rectangle is on the screen
+                unsafe { self.write_pixel(row.add(x * self.bytes_per_pixel()), pixel) };
+            }
+        }
+        self.mark_dirty(rect);
+    }
+    fn set_char(&self, x: u16, y: u16, c: char, fg: &Color, bg: &Color) -> bool {
+        let Some(font) = self.font() else {
+            panic!("No font set");
+        };
+        let (width, height) = (font.width(), font.height());
+        let rect = Rect::new(x * width, y * height, width, height);
+        if rect.clamp(self.width, self.height) != rect {
+            panic!("Bad char position {x},{y}");
+        }
+        let index = font.glyph_index(c).or_else(|| font.glyph_index('?'));
+        let Some(glyph) = index.and_then(|index| font.glyph(index)) else {
+            return false;
+        };
+
+        let fg = self.format.encode(fg.r, fg.g, fg.b);
+        let bg = self.format.encode(bg.r, bg.g, bg.b);
+        for (ii, row) in glyph.chunks(font.bytes_per_row()).enumerate() {
+            let addr = self.get_pixel_address(rect.x, rect.y + ii as u16);
+            for jj in 0..width {
+                let pixel = if Font::is_set(row, jj) { fg } else { bg };
+                // SAFETY: The cell is on the screen
+                unsafe { self.write_pixel(addr.add(jj as usize * self.bytes_per_pixel()), pixel) };
+            }
+        }
+        self.mark_dirty(rect);
+
+        true
+    }
+    fn blit(&self, x: i32, y: i32, image: &Tga) {
+        // The part of the image on the screen
+        let left = x.clamp(0, self.width as i32);
+        let top = y.clamp(0, self.height as i32);
+        let right = (x + image.width() as i32).clamp(0, self.width as i32);
+        let bottom = (y + image.height() as i32).clamp(0, self.height as i32);
+        for pixel in image.pixels().filter(|pixel| pixel.a >= 0x80) {
+            let (px, py) = (x + pixel.x as i32, y + pixel.y as i32);
+            if (left..right).contains(&px) && (top..bottom).contains(&py) {
+                let addr = self.get_pixel_address(px as u16, py as u16);
+                let color = self.format.encode(pixel.r, pixel.g, pixel.b);
+                // SAFETY: The pixel is on the screen
+                unsafe { self.write_pixel(addr, color) };
+            }
+        }
+        let (width, height) = ((right - left) as u16, (bottom - top) as u16);
+        self.mark_dirty(Rect::new(left as u16, top as u16, width, height));
     }
 }
 
@@ -113,21 +311,95 @@ pub struct FramebufferInfo {
     width: u16,
     /// How many pixels high the screen is
     height: u16,
-    /// How many bits per pixes, should be 4 or 6
+    /// How many bits per pixel, 15, 16, 24 or 32
     bits_per_pixel: u8,
     /// Start address of the framebuffer
     framebuffer: *mut u8,
-    // TODO: Put color mask
+    /// Where the colors are in a pixel
+    format: PixelFormat,
+    /// Copy of the framebuffer in normal memory that gets drawn to instead, null if there is
+    /// none. Reading video memory is slow, and so is writing it a pixel at a time.
+    back_buffer: *mut u8,
+    /// Parts of the back buffer that changed since the last flush
+    dirty: Cell<DirtyRects>,
 }
 
 impl FramebufferInfo {
+    /// Bytes in a pixel, 15 bit pixels take 2 bytes too
+    #[inline]
+    fn bytes_per_pixel(&self) -> usize {
+        (self.bits_per_pixel as usize).div_ceil(8)
+    }
+
+    /// Size of the framebuffer in bytes
+    fn size(&self) -> usize {
+        self.bytes_per_scan_line as usize * self.height as usize
+    }
+
+    /// Where drawing goes, the back buffer if there is one
+    #[inline]
+    fn canvas(&self) -> *mut u8 {
+        if self.back_buffer.is_null() {
+            self.framebuffer
+        } else {
+            self.back_buffer
+        }
+    }
+
     #[inline]
     fn get_pixel_address(&self, x: u16, y: u16) -> *mut u8 {
         let y_offset = y as usize * self.bytes_per_scan_line as usize;
-        let x_offset = x as usize * (self.bits_per_pixel as usize / 8);
+        let x_offset = x as usize * self.bytes_per_pixel();
         let offset = y_offset + x_offset;
 
-        unsafe { self.framebuffer.add(offset) }
+        unsafe { self.canvas().add(offset) }
+    }
+
+    /// Writes the low bytes of an encoded pixel
+    ///
+    /// # Safety
+    ///
+    /// `addr` has to be a pixel of the canvas
+    #[inline]
+    unsafe fn write_pixel(&self, addr: *mut u8, pixel: u32) {
+        if self.bytes_per_pixel() == 4 {
+            unsafe { (addr as *mut u32).write_unaligned(pixel) };
+        } else {
+            for (ii, byte) in pixel
+                .to_le_bytes()
+                .iter()
+                .take(self.bytes_per_pixel())
+                .enumerate()
+            {
+                unsafe { addr.add(ii).write(*byte) };
+            }
+        }
+    }
+
+    fn mark_dirty(&self, rect: Rect) {
+        if !self.back_buffer.is_null() {
+            let mut dirty = self.dirty.get();
+            dirty.add(rect);
+            self.dirty.set(dirty);
+        }
+    }
+
+    /// Copies what changed in the back buffer to the screen
+    fn flush(&self) {
+        let dirty = self.dirty.replace(DirtyRects::EMPTY);
+        let pitch = self.bytes_per_scan_line as usize;
+        for rect in dirty.rects() {
+            let offset = rect.x as usize * self.bytes_per_pixel();
+            let len = rect.width as usize * self.bytes_per_pixel();
+            for y in rect.y as usize..(rect.y + rect.height) as usize {
+                let at = y * pitch + offset;
+                // SAFETY: The rectangle is on the screen, and the buffers don't overlap
+                unsafe {
+                    let from = self.back_buffer.add(at);
+                    core::ptr::copy_nonoverlapping(from, self.framebuffer.add(at), len);
+                }
+            }
+        }
     }
 
     /// Sets the given pixel a color, returns false if pixel is out of range
@@ -138,24 +410,20 @@ impl FramebufferInfo {
         }
 
         let addr = self.get_pixel_address(x, y);
-        unsafe {
-            match self.bits_per_pixel {
-                24 => {
-                    // TODO: Check mask
-                    addr.add(0).write(color.b);
-                    addr.add(1).write(color.g);
-                    addr.add(2).write(color.r);
-                }
-                // TODO: Implement 32 bpp and 16 bpp modes
-                n => panic!("{n} bits per pixel not supported"),
-            }
-        }
+        let pixel = self.format.encode(color.r, color.g, color.b);
+        // SAFETY: The pixel is on the screen
+        unsafe { self.write_pixel(addr, pixel) };
+        self.mark_dirty(Rect::new(x, y, 1, 1));
 
         true
     }
 }
 
+/// Where the next character goes, in characters from the top left
 static CHAR_INDEX: AtomicUsize = AtomicUsize::new(0);
+/// Colors of the characters written from now on
+static mut FOREGROUND: Color = Color::WHITE;
+static mut BACKGROUND: Color = Color::DARK_GRAY;
 
 pub struct Screen;
 
@@ -169,106 +437,256 @@ impl FrameBuffer for Screen {
     fn set_pixel(&self, x: u16, y: u16, c: &Color) -> bool {
         unsafe { FRAME_BUFFER.as_ref().unwrap().set_pixel(x, y, c) }
     }
-    fn font(&self) -> Option<&'static [u8; 0x1000]> {
-        unsafe { FONT.as_ref() }
+    fn font(&self) -> Option<Font<'static>> {
+        unsafe { FONT }
+    }
+    fn scroll_up(&self, rows: u16) {
+        unsafe { FRAME_BUFFER.as_ref().unwrap().scroll_up(rows) }
+    }
+    fn invert(&self, x: u16, y: u16, width: u16, height: u16) {
+        unsafe { FRAME_BUFFER.as_ref().unwrap().invert(x, y, width, height) }
+    }
+    fn fill(&self, x: u16, y: u16, width: u16, height: u16, c: &Color) {
+        unsafe { FRAME_BUFFER.as_ref().unwrap().fill(x, y, width, height, c) }
+    }
+    fn set_char(&self, x: u16, y: u16, c: char, fg: &Color, bg: &Color) -> bool {
+        unsafe { FRAME_BUFFER.as_ref().unwrap().set_char(x, y, c, fg, bg) }
+    }
+    fn blit(&self, x: i32, y: i32, image: &Tga) {
+        unsafe { FRAME_BUFFER.as_ref().unwrap().blit(x, y, image) }
     }
 }
 
 impl Write for Screen {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // The cursor is drawn by inverting, so it has to be gone before anything under it changes
+        self.toggle_cursor();
         for c in s.chars() {
             self.write_char_impl(c);
         }
+        self.toggle_cursor();
+        self.flush();
 
         Ok(())
     }
 }
 
 impl Screen {
+    /// Clears the screen to the background color and puts the cursor in the top left
     pub fn reset(&self) {
         CHAR_INDEX.store(0, Ordering::Relaxed);
-        // Set everything to dark gray
-        for x in 0..Screen.width() {
-            for y in 0..Screen.height() {
-                Screen.set_pixel(x, y, &Color::new(0, 0, 0));
-            }
+        let bg = unsafe { BACKGROUND };
+        Screen.fill(0, 0, Screen.width(), Screen.height(), &bg);
+        self.toggle_cursor();
+        self.flush();
+    }
+    /// Copies everything drawn since the last flush to the screen, if drawing goes to a back
+    /// buffer
+    pub fn flush(&self) {
+        unsafe { FRAME_BUFFER.as_ref().unwrap().flush() }
+    }
+    /// Sets the colors of the characters written from now on
+    pub fn set_colors(&self, fg: Color, bg: Color) {
+        // SAFETY: Stage 1 is single threaded
+        unsafe {
+            FOREGROUND = fg;
+            BACKGROUND = bg;
         }
     }
+    /// Width and height of a character in pixels
+    fn char_size(&self) -> (u16, u16) {
+        let font = Screen.font().unwrap();
+        (font.width(), font.height())
+    }
     fn width_char(&self) -> u16 {
-        Screen.width() / 8
+        Screen.width() / self.char_size().0
     }
     fn height_char(&self) -> u16 {
-        Screen.height() / 16
+        Screen.height() / self.char_size().1
+    }
+    /// Shows or hides the cursor under the next character
+    fn toggle_cursor(&self) {
+        let (char_width, char_height) = self.char_size();
+        let char_idx = CHAR_INDEX.load(Ordering::Acquire);
+        let x = char_idx as u16 % self.width_char();
+        let y = char_idx as u16 / self.width_char();
+        let cursor_height = CURSOR_HEIGHT.min(char_height);
+        let y_px = (y + 1) * char_height - cursor_height;
+        Screen.invert(x * char_width, y_px, char_width, cursor_height);
+    }
+    /// Draws a character at the given index with the current colors
+    fn draw_char(&self, char_idx: usize, c: char) {
+        let (fg, bg) = unsafe { (FOREGROUND, BACKGROUND) };
+        let y = char_idx as u16 / self.width_char();
+        let x = char_idx as u16 % self.width_char();
+        Screen.set_char(x, y, c, &fg, &bg);
+    }
+    /// Moves every line up by one and clears the last one
+    fn scroll(&self) {
+        let char_height = self.char_size().1;
+        Screen.scroll_up(char_height);
+        let bg = unsafe { BACKGROUND };
+        let y_px = (self.height_char() - 1) * char_height;
+        Screen.fill(0, y_px, Screen.width(), Screen.height() - y_px, &bg);
     }
     fn write_char_impl(&self, c: char) {
+        let width = self.width_char() as usize;
         let mut char_idx = CHAR_INDEX.load(Ordering::Acquire);
-        if char_idx >= self.width_char() as usize * self.height_char() as usize {
-            char_idx -= self.width_char() as usize;
-            CHAR_INDEX.store(char_idx, Ordering::Release);
-            panic!("Scrolling not implemented");
+        let column = char_idx % width;
+
+        match c {
+            '\n' => char_idx += width - column,
+            '\r' => char_idx -= column,
+            '\t' => char_idx += (TAB_WIDTH - column % TAB_WIDTH).min(width - column),
+            // Backspace erases the character before the cursor
+            '\x08' => {
+                if char_idx > 0 {
+                    char_idx -= 1;
+                    self.draw_char(char_idx, ' ');
+                }
+            }
+            c => {
+                self.draw_char(char_idx, c);
+                char_idx += 1;
+            }
         }
 
-        if c == '\n' {
-            char_idx += self.width_char() as usize - char_idx % self.width_char() as usize;
-        } else {
-            let y = char_idx as u16 / self.width_char();
-            let x = char_idx as u16 % self.width_char();
-            if c.is_ascii() {
-                Screen.set_char(x, y, c as u8);
-            } else {
-                Screen.set_char(x, y, b'?');
-            }
-            char_idx += 1;
+        // Past the last line, scroll so the cursor is on the last line again
+        if char_idx >= width * self.height_char() as usize {
+            self.scroll();
+            char_idx -= width;
         }
 
         CHAR_INDEX.store(char_idx, Ordering::Release);
     }
 }
 
-/// Enters the best fit VBE mode
+/// Where the console font comes from, see `common::config::FontChoice`
+#[derive(Debug, Clone, Copy)]
+pub enum FontSource {
+    Builtin,
+    Bios,
+    /// A PSF1 or PSF2 file loaded from the boot volume
+    Psf(&'static [u8]),
+}
+
+/// Enters the VBE mode closest to `request` and sets up the font. The BIOS font is copied to
+/// `free`, and drawing goes to a back buffer in the rest of it if it is big enough.
 ///
 /// SAFETY: Writes to static variables, can't be used accross threads
-pub fn init_graphical() -> Screen {
+pub fn init_graphical(
+    request: ModeRequest,
+    font: FontSource,
+    free: Option<&'static mut [u8]>,
+) -> Screen {
+    let mut free = free;
+    let font = load_font(font, &mut free, &request);
+    let mut mode = vbe_impl::init(request);
+    if let Some(free) = free.filter(|free| free.len() >= mode.size()) {
+        mode.back_buffer = free.as_mut_ptr();
+    }
     unsafe {
-        FONT = Some([0; 0x1000]);
-        set_bitmap_font_from_bios(FONT.as_mut().unwrap());
+        FONT = Some(font);
+        FRAME_BUFFER = Some(mode);
     }
 
-    //for ii in 0..16 {
-    //    unsafe {
-    //        println_bios!("{:08b}", FONT.as_ref().unwrap()[b'?' as usize * 16 + ii]);
-    //    }
-    //}
-
-    //loop {}
+    Screen.reset();
+    Screen
+}
 
-    let mode = vbe_impl::init();
-    unsafe {
-        FRAME_BUFFER = Some(mode);
+/// The framebuffer as handed to the kernel, zeroed if graphics were never set up
+pub fn framebuffer_info() -> FrameBufferInfo {
+    // SAFETY: Stage 1 is single threaded
+    match unsafe { FRAME_BUFFER.as_ref() } {
+        Some(fb) => FrameBufferInfo {
+            address: fb.framebuffer as u64,
+            width: fb.width as u32,
+            height: fb.height as u32,
+            pitch: fb.bytes_per_scan_line as u32,
+            bits_per_pixel: fb.bits_per_pixel as u32,
+            format: fb.format,
+        },
+        None => FrameBufferInfo {
+            address: 0,
+            width: 0,
+            height: 0,
+            pitch: 0,
+            bits_per_pixel: 0,
+            format: PixelFormat::default(),
+        },
     }
+}
+
+/// Fewest characters a font has to fit across and down the screen, anything bigger falls back to
+/// the built-in font
+const MIN_COLUMNS: u32 = 40;
+const MIN_ROWS: u32 = 12;
 
-    // Set everything to dark gray
-    for x in 0..Screen.width() {
-        for y in 0..Screen.height() {
-            Screen.set_pixel(x, y, &Color::new(20, 20, 20));
+/// The font `source` picks, or the built-in one if that can't be used. The BIOS font is copied
+/// to the start of `free`, which is left with the rest.
+///
+/// Runs before the mode is set so the BIOS can still print why a font isn't used, so fonts are
+/// checked against the requested size rather than the size of the mode.
+fn load_font(
+    source: FontSource,
+    free: &mut Option<&'static mut [u8]>,
+    request: &ModeRequest,
+) -> Font<'static> {
+    let font = match source {
+        FontSource::Builtin => None,
+        FontSource::Bios => match free.take() {
+            Some(memory) if memory.len() >= size_of::<BiosFont>() => {
+                let (font, rest) = memory.split_at_mut(size_of::<BiosFont>());
+                *free = Some(rest);
+                let font: &'static mut BiosFont = font.try_into().unwrap();
+                set_bitmap_font_from_bios(font);
+                Some(Font::new(font, 8, 16))
+            }
+            _ => {
+                println_bios!("No room for the BIOS font");
+                None
+            }
+        },
+        FontSource::Psf(file) => match Font::parse_psf(file) {
+            Ok(font) => Some(font),
+            Err(e) => {
+                println_bios!("Bad font: {e:?}");
+                None
+            }
+        },
+    };
+    let fits = |font: &Font| {
+        font.width() as u32 * MIN_COLUMNS <= request.width as u32
+            && font.height() as u32 * MIN_ROWS <= request.height as u32
+    };
+    match font {
+        Some(font) if fits(&font) => font,
+        Some(font) => {
+            let (width, height) = (font.width(), font.height());
+            println_bios!("{width}x{height} font too big for the screen");
+            builtin_font()
         }
+        None => builtin_font(),
     }
+}
 
-    // 1024x720
-    for x in 0..Screen.width() {
-        let y = 400;
-        //let (width, height, depth) = (1280, 720, 24);
-        Screen.set_pixel(x, y, &Color::new(0, 0xff, 0));
+fn builtin_font() -> Font<'static> {
+    match Font::parse_psf(BUILTIN_FONT) {
+        Ok(font) => font,
+        Err(e) => panic!("Bad built-in font: {e:?}"),
     }
+}
 
-    // 160x45
-    //write!(Screen, "{}x{}", Screen.width_char(), Screen.height_char());
-    //loop {}
-
-    for ii in 0..(720 + 1) {
-        let v = ii % 10;
-        write!(Screen, "{v}12345678_").unwrap();
+/// The console font as handed to the kernel, zeroed if graphics were never set up
+pub fn font_info() -> FontInfo {
+    // SAFETY: Stage 1 is single threaded
+    match unsafe { FONT } {
+        Some(font) => font.info(),
+        None => FontInfo {
+            address: 0,
+            glyph_count: 0,
+            width: 0,
+            height: 0,
+        },
     }
-    //write!(Screen, "Here is a long string\n with some new linese \n");
-    loop {}
 }
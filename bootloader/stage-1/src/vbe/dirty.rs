@@ -0,0 +1,111 @@
+//! Keeps track of which parts of the back buffer still have to be copied to the screen
+
+/// Most rectangles tracked separately, any more get merged into one
+const MAX_RECTS: usize = 8;
+
+/// A rectangle of pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub const EMPTY: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 0,
+        height: 0,
+    };
+
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn right(&self) -> u16 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> u16 {
+        self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// The part of the rectangle inside a screen of the given size
+    pub fn clamp(&self, width: u16, height: u16) -> Rect {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Rect::new(
+            x,
+            y,
+            self.right().min(width) - x,
+            self.bottom().min(height) - y,
+        )
+    }
+
+    /// Whether the rectangles overlap or share an edge
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right()
+            && other.x <= self.right()
+            && self.y <= other.bottom()
+            && other.y <= self.bottom()
+    }
+
+    /// Smallest rectangle covering both
+    fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+/// Rectangles changed since the last flush
+#[derive(Debug, Clone, Copy)]
+pub struct DirtyRects {
+    rects: [Rect; MAX_RECTS],
+    count: usize,
+}
+
+impl DirtyRects {
+    pub const EMPTY: DirtyRects = DirtyRects {
+        rects: [Rect::EMPTY; MAX_RECTS],
+        count: 0,
+    };
+
+    /// Marks a rectangle dirty, growing one it touches if there is one. Copying a few clean
+    /// pixels along is cheaper than keeping track of lots of small rectangles.
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        if let Some(dirty) = self.rects[..self.count]
+            .iter_mut()
+            .find(|r| r.touches(&rect))
+        {
+            *dirty = dirty.union(&rect);
+        } else if self.count < MAX_RECTS {
+            self.rects[self.count] = rect;
+            self.count += 1;
+        } else {
+            let all = self.rects.iter().fold(rect, |all, r| all.union(r));
+            self.rects[0] = all;
+            self.count = 1;
+        }
+    }
+
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects[..self.count]
+    }
+}
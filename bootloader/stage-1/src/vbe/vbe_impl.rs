@@ -1,21 +1,27 @@
 use core::arch::asm;
+use core::cell::Cell;
 use core::mem::MaybeUninit;
 
-use super::FramebufferInfo;
-pub type Font = [u8; 0x1000];
+use common::edid::EDID_SIZE;
+use common::PixelFormat;
 
-pub fn init() -> FramebufferInfo {
+use super::dirty::DirtyRects;
+use super::{FramebufferInfo, ModeRequest};
+/// The 256 8x16 glyphs of the VGA BIOS font
+pub type BiosFont = [u8; 0x1000];
+
+pub fn init(request: ModeRequest) -> FramebufferInfo {
     assert_eq!(size_of::<VesaVbeBlockDef>(), 512, "VbeInfoBlock bad size");
     assert_eq!(
         size_of::<VesaVbeModeDef>(),
         256,
         "VesaModeInfoBlock bad size"
     );
-    set_best_vbe_mode()
+    set_best_vbe_mode(request)
 }
 
 /// Loads BIOS VGA font into a given address
-pub fn set_bitmap_font_from_bios(font: &mut Font) {
+pub fn set_bitmap_font_from_bios(font: &mut BiosFont) {
     // ES:BP is address of font we want to save
     let mut bp: u16;
     let mut es: u16;
@@ -50,6 +56,24 @@ pub fn set_bitmap_font_from_bios(font: &mut Font) {
     }
 }
 
+/// Reads the EDID block of the first display with int 0x10 AX=0x4f15, false if the display or
+/// video card doesn't do DDC
+pub fn read_edid(block: &mut [u8; EDID_SIZE]) -> bool {
+    let ax: u16;
+    unsafe {
+        asm!(
+            "int 0x10",
+            inout("ax") 0x4f15u16 => ax,
+            // Read the first 128 byte block of controller unit 0
+            inout("bx") 0x0001u16 => _,
+            inout("cx") 0u16 => _,
+            inout("dx") 0u16 => _,
+            in("di") block as *mut [u8; EDID_SIZE] as u16,
+        );
+    }
+    ax == 0x004f
+}
+
 /// Checks that the ax value indicates return success for VBE function calls. Panics if not success
 macro_rules! check_vbe_ax {
     ($ax:ident, $($args:tt)*) => {
@@ -63,9 +87,10 @@ macro_rules! check_vbe_ax {
     };
 }
 
-/// Gets the best vbe mode given desired width, height, depth, and a list of supported mode ids
+/// Gets the best vbe mode given desired width, height, depth, and a list of supported mode ids.
+/// Modes with the requested depth win, other depths are only used if it has none.
 fn get_best_mode(width: u16, height: u16, depth: u8, modes: &[u16]) -> FramebufferInfo {
-    let mut diff = u16::MAX;
+    let mut diff = (true, u16::MAX);
     let mut best_mode = None;
 
     // SAFETY: This gets init with the load() function at the beginning of each loop. If it
@@ -78,14 +103,15 @@ fn get_best_mode(width: u16, height: u16, depth: u8, modes: &[u16]) -> Framebuff
             continue;
         }
         // Check the residual
-        let mode_diff = vbe_mode.width.abs_diff(width) + vbe_mode.height.abs_diff(height);
-        if vbe_mode.bits_per_pixel == depth && mode_diff <= diff {
+        let size_diff = vbe_mode.width.abs_diff(width) + vbe_mode.height.abs_diff(height);
+        let mode_diff = (vbe_mode.bits_per_pixel != depth, size_diff);
+        if mode_diff <= diff {
             diff = mode_diff;
             best_mode = Some(*mode_id);
         }
     }
 
-    if modes.is_empty() || diff == u16::MAX || best_mode.is_none() {
+    if modes.is_empty() || best_mode.is_none() {
         panic!("no VBE modes found");
     }
     let best_mode = best_mode.unwrap();
@@ -100,11 +126,13 @@ fn get_best_mode(width: u16, height: u16, depth: u8, modes: &[u16]) -> Framebuff
 }
 
 /// SAFETY: Can only be called by one thread at a time, contains mutable static information
-fn set_best_vbe_mode() -> FramebufferInfo {
+fn set_best_vbe_mode(request: ModeRequest) -> FramebufferInfo {
     // Get the best mode relative to these target numbers
-    // TODO: Get these numbers from EDID: https://wiki.osdev.org/EDID
-    //let (width, height) = (1920, 1080, 24);
-    let (width, height, depth) = (1280, 720, 24);
+    let ModeRequest {
+        width,
+        height,
+        depth,
+    } = request;
 
     let vbe_block = VesaVbeBlockDef::new();
     let modes = vbe_block.get_modes();
@@ -239,18 +267,67 @@ impl FramebufferInfo {
         let required_flags =
             SUPPORTED_BY_HARDWARE | LINEAR_FRAME_BUFFER | NO_VGA_COMPAT | GRAPICS_MODE;
         let has_flags = vbe_mode_def.mode_attributes & required_flags == required_flags;
-        let good_mode = memory_model_works && has_flags;
+        let depth_works = matches!(vbe_mode_def.bits_per_pixel, 15 | 16 | 24 | 32);
+        let good_mode = memory_model_works && has_flags && depth_works;
         if !good_mode {
             return Err(VbeError::ModeNotGood);
         }
 
+        // VBE 3 has separate masks and pitch for linear framebuffers, older versions only fill in
+        // the banked ones. Packed pixel modes don't have masks at all.
+        let def = &vbe_mode_def;
+        let masks = if def.linear_red_mask_size != 0 {
+            [
+                def.linear_red_mask_size,
+                def.linear_red_field_pos,
+                def.linear_green_mask_size,
+                def.linear_green_field_pos,
+                def.linear_blue_mask_size,
+                def.linear_blue_field_pos,
+                def.linear_rsv_mask_size,
+                def.linear_rsv_field_pos,
+            ]
+        } else {
+            [
+                def.red_mask,
+                def.red_position,
+                def.green_mask,
+                def.green_position,
+                def.blue_mask,
+                def.blue_position,
+                def.reserved_mask,
+                def.reserved_position,
+            ]
+        };
+        let format = if masks[0] != 0 {
+            PixelFormat {
+                red_size: masks[0],
+                red_shift: masks[1],
+                green_size: masks[2],
+                green_shift: masks[3],
+                blue_size: masks[4],
+                blue_shift: masks[5],
+                reserved_size: masks[6],
+                reserved_shift: masks[7],
+            }
+        } else {
+            PixelFormat::rgb(def.bits_per_pixel).ok_or(VbeError::ModeNotGood)?
+        };
+        let bytes_per_scan_line = match def.linear_bytes_per_scan_line {
+            0 => def.bytes_per_scan_line,
+            linear => linear,
+        };
+
         *self = FramebufferInfo {
             mode_id,
-            bits_per_pixel: vbe_mode_def.bits_per_pixel,
-            bytes_per_scan_line: vbe_mode_def.bytes_per_scan_line,
-            width: vbe_mode_def.width,
-            height: vbe_mode_def.height,
-            framebuffer: vbe_mode_def.framebuffer as *mut u8,
+            bits_per_pixel: def.bits_per_pixel,
+            bytes_per_scan_line,
+            width: def.width,
+            height: def.height,
+            framebuffer: def.framebuffer as *mut u8,
+            format,
+            back_buffer: core::ptr::null_mut(),
+            dirty: Cell::new(DirtyRects::EMPTY),
         };
 
         Ok(())
@@ -1,9 +1,9 @@
-use std::path::Path;
+#[allow(dead_code)]
+#[path = "../layout.rs"]
+mod layout;
+#[path = "../link_script.rs"]
+mod link_script;
 
 fn main() {
-    let local_path = Path::new(env!("CARGO_MANIFEST_DIR"));
-    println!(
-        "cargo:rustc-link-arg-bins=--script={}",
-        local_path.join("link.ld").display()
-    )
+    link_script::generate();
 }
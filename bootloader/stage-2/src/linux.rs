@@ -0,0 +1,156 @@
+//! Boots Linux bzImages. The protected mode kernel is entered through its 64 bit entry point
+//! when it has one, and through the 32 bit one otherwise.
+
+use core::arch::asm;
+
+use common::gdt::LinuxGdt;
+use common::linux::{self, BootParams, SetupHeader, BOOT_PARAMS_SIZE, ENTRY_64_OFFSET};
+use common::{print, println};
+use common::{BIOS_INFO, LINUX_BOOT_PARAMS_ADDRESS, LINUX_CMDLINE_ADDRESS, LINUX_CMDLINE_SIZE};
+
+use crate::{enable_long_mode, has_long_mode, load_segment, IDENTITY_MAPPED_END};
+
+static LINUX_GDT_PROTECTED: LinuxGdt = LinuxGdt::protected_mode();
+static LINUX_GDT_LONG: LinuxGdt = LinuxGdt::long_mode();
+
+/// Without paging the kernel can be anywhere in the first 4 GiB
+const ADDRESS_LIMIT_32: u64 = 1 << 32;
+
+/// Loads the kernel in `file` and the `initrd` stage 1 loaded after it, then jumps to it
+pub fn boot(file: &[u8], initrd: &[u8]) -> ! {
+    let header = match SetupHeader::parse(file) {
+        Ok(header) => header,
+        Err(e) => panic!("Bad Linux kernel: {e:?}"),
+    };
+    // Everything the 64 bit entry point touches has to be identity mapped
+    let long_mode =
+        header.has_entry_64 && has_long_mode() && header.reserved_end() <= IDENTITY_MAPPED_END;
+    let limit = if long_mode {
+        IDENTITY_MAPPED_END
+    } else {
+        ADDRESS_LIMIT_32
+    };
+
+    // The protected mode kernel goes to code32_start, 1 MiB for every bzImage
+    let kernel = &file[header.kernel_offset..];
+    let load_address = header.code32_start as u64;
+    load_segment(file, kernel, load_address, kernel.len() as u64, limit);
+
+    // SAFETY: Stage 1 filled this in
+    let (info, memory_map, cmdline) = unsafe {
+        let info = &*BIOS_INFO;
+        (info, info.memory_map(), info.cmdline())
+    };
+    // SAFETY: Nothing else uses the boot params page
+    let page = unsafe { &mut *(LINUX_BOOT_PARAMS_ADDRESS as *mut [u8; BOOT_PARAMS_SIZE]) };
+    let mut params = BootParams::new(page, file, &header);
+    params.memory_map(memory_map).screen_info(&info.framebuffer);
+
+    if cmdline.len() >= LINUX_CMDLINE_SIZE {
+        panic!("Kernel command line is {} bytes long", cmdline.len());
+    }
+    // SAFETY: Checked it fits, with the nul, and nothing else uses this memory
+    unsafe {
+        let destination = LINUX_CMDLINE_ADDRESS as *mut u8;
+        core::ptr::copy_nonoverlapping(cmdline.as_ptr(), destination, cmdline.len());
+        destination.add(cmdline.len()).write(0);
+    }
+    if let Err(e) = params.cmdline(LINUX_CMDLINE_ADDRESS as u64, cmdline.len()) {
+        panic!("Bad kernel command line: {e:?}");
+    }
+
+    if !initrd.is_empty() {
+        // As high as it goes, out of the way of the kernel decompressing itself
+        let size = initrd.len() as u64;
+        let highest = (header.initrd_addr_max as u64 + 1).min(limit);
+        let Some(address) = linux::initrd_address(memory_map, size, header.reserved_end(), highest)
+        else {
+            panic!("No room for the {size} byte initrd");
+        };
+        // SAFETY: Usable memory above everything the kernel uses. The copy can overlap where
+        // stage 1 put the initrd, which `copy` handles.
+        unsafe { core::ptr::copy(initrd.as_ptr(), address as *mut u8, initrd.len()) };
+        params.initrd(address, size);
+        println!("Initrd at {address:#x}");
+    }
+
+    println!(
+        "Linux boot protocol {}.{:02}, entering the {} bit entry point",
+        header.version >> 8,
+        header.version & 0xff,
+        if long_mode { 64 } else { 32 },
+    );
+    if long_mode {
+        // SAFETY: The kernel is loaded and identity mapped, and so is everything it points to
+        unsafe { enter_64(load_address as u32 + ENTRY_64_OFFSET) }
+    } else {
+        // SAFETY: The kernel is loaded, and everything it points to is below 4 GiB
+        unsafe { enter_32(load_address as u32) }
+    }
+}
+
+/// Jumps to the 32 bit entry point with the segments the boot protocol asks for, the boot
+/// params in `esi` and `ebp`, `edi` and `ebx` zeroed
+unsafe fn enter_32(entry: u32) -> ! {
+    LINUX_GDT_PROTECTED.load();
+    unsafe {
+        asm!(
+            // Reload cs from the new GDT
+            "ljmp $0x10, $2f",
+            "2:",
+            options(att_syntax)
+        );
+        // esi can't be an operand, LLVM might be using it. The rest are pinned to registers
+        // that aren't cleared before the jump.
+        asm!(
+            "mov ds, eax",
+            "mov es, eax",
+            "mov fs, eax",
+            "mov gs, eax",
+            "mov ss, eax",
+            "mov esi, ecx",
+            "xor ebp, ebp",
+            "xor edi, edi",
+            "xor ebx, ebx",
+            "jmp edx",
+            in("eax") LinuxGdt::DATA_SELECTOR as u32,
+            in("ecx") LINUX_BOOT_PARAMS_ADDRESS as u32,
+            in("edx") entry,
+            options(noreturn),
+        )
+    }
+}
+
+/// Switches to long mode and jumps to the 64 bit entry point with the boot params in `rsi`
+unsafe fn enter_64(entry: u32) -> ! {
+    unsafe {
+        enable_long_mode();
+        LINUX_GDT_LONG.load();
+        asm!(
+            // Pushed as 64 bit values for the 64 bit code to pop
+            "push 0",
+            "push ecx",
+            "push 0",
+            "push edx",
+            in("ecx") LINUX_BOOT_PARAMS_ADDRESS as u32,
+            in("edx") entry,
+        );
+        asm!(
+            // Reload cs from the new GDT, which finishes the switch to 64 bit mode
+            "ljmp $0x10, $2f",
+            "2:",
+            options(att_syntax)
+        );
+        asm!(
+            ".code64",
+            "mov ds, {data:e}",
+            "mov es, {data:e}",
+            "mov ss, {data:e}",
+            "pop rax",
+            "pop rsi",
+            "jmp rax",
+            data = in(reg) LinuxGdt::DATA_SELECTOR as u32,
+            options(noreturn),
+        )
+    }
+}
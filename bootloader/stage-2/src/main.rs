@@ -1,41 +1,60 @@
 #![no_std]
 #![no_main]
 
+use common::elf::Elf;
 use common::gdt::*;
+use common::linux::SetupHeader;
+use common::multiboot2;
 use common::protected_mode::hlt;
 use common::protected_mode::io::clear_screen;
+use common::splash::ProgressBar;
 use common::*;
 use core::arch::asm;
 
 use common::{print, println};
 
+mod linux;
+mod multiboot;
+
 static GDT_LONG: Gdt = Gdt::long_mode();
 
 use core::panic::PanicInfo;
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    {
+        use core::fmt::Write;
+        let _ = writeln!(serial::SerialWriter, "PANIC: {info}\r");
+    }
     println!("{info}");
     loop {
         unsafe { asm!("hlt") }
     }
 }
 
-#[allow(dead_code)]
-#[repr(packed)]
-#[derive(Debug)]
-struct MemoryMapEntry {
-    base_address: u64,
-    length: u64,
-    region_type: u32,
-    attributes: u32,
-}
-
 #[link_section = ".start"]
 #[no_mangle]
-pub extern "C" fn _start(_count: u16) -> ! {
+pub extern "C" fn _start(kernel_size: u32, initrd_size: u32) -> ! {
+    serial::milestone(serial::STAGE_2_PROTECTED);
+    // SAFETY: Stage 1 wrote the info, and without paging the framebuffer is where it says
+    unsafe { ProgressBar::advance(&(*BIOS_INFO).framebuffer, serial::STAGE_2_PROTECTED) };
     clear_screen();
     println!("Started protected mode");
 
+    // SAFETY: Stage 1 loaded kernel_size bytes here, nothing else uses this memory
+    let kernel = unsafe { core::slice::from_raw_parts(KERNEL_FILE_START, kernel_size as usize) };
+    if SetupHeader::is_linux(kernel) {
+        // SAFETY: Same for the initrd, right after the kernel
+        let initrd = unsafe {
+            core::slice::from_raw_parts(initrd_file_start(kernel_size), initrd_size as usize)
+        };
+        linux::boot(kernel, initrd);
+    }
+    match multiboot2::Header::find(kernel) {
+        Some(Ok(header)) => multiboot::boot(kernel, &header),
+        Some(Err(e)) => panic!("Bad Multiboot2 header: {e:?}"),
+        None => {}
+    }
+
     //let mut mmap_reader: *const MemoryMapEntry = MEMORY_MAP_START as *const MemoryMapEntry;
     //for ii in 0..count {
     //    unsafe {
@@ -50,54 +69,27 @@ pub extern "C" fn _start(_count: u16) -> ! {
         hlt();
     }
 
-    unsafe {
-        println!("Setting up paging");
-        load_page_tables();
-    }
-    // Enter enable paging and enter 32 bit compatability submode of long mode
-    {
-        // TODO: Something is probably broken here
-        unsafe {
-            asm!(
-                // Eanable PAE paging:
-                "mov eax, cr4",
-                "or eax, 1 << 5", // PAE-bit is the 6th bit
-                "mov cr4, eax",
-                // Set long mode bit:
-                // Set the C-register to the EFER Model Specific Register (MSR)
-                "mov ecx, 0xc0000080",
-                // Read from MSR
-                "rdmsr",
-                // Set the LM-bit
-                "or eax, 1 << 8",
-                // Write to MSR
-                "wrmsr",
-                // Enable paging
-                "mov eax, cr0",
-                "or eax, 1 << 31", // PG-bit is the 31st bit
-                "mov cr0, eax",
-                // We are now in the 32 bit compatability submode of long mode
-            );
-        }
-    }
+    let entry_point = load_kernel(kernel);
+    println!("Kernel entry point at {entry_point:#x}");
+
+    unsafe { enable_long_mode() };
+    serial::milestone(serial::STAGE_2_LONG);
 
     // TODO: Load gdt and enter perform long jump to enter long mode
     unsafe {
-        let entry_point =
-            STAGE_0_START + (STAGE_0_SECTIONS + STAGE_1_SECTIONS + STAGE_2_SECTIONS) * 512;
-
         //println!("In protected mode, about to enter long mode");
         //clear_screen();
 
         GDT_LONG.load();
         asm!(
-            // Push value
+            // Push the kernel argument, a pointer to the BiosInfo from stage 1
             "push 0",
-            "push '3'",
+            "push {info:e}",
             // Push entry point
             "push 0",
             "push {entry_point:e}",
-            entry_point = in(reg) entry_point as u32,
+            info = in(reg) BIOS_INFO as u32,
+            entry_point = in(reg) entry_point,
         );
 
         // Perform a "long jump" to one line down.
@@ -118,7 +110,7 @@ pub extern "C" fn _start(_count: u16) -> ! {
             "mov es, {0}",
             "mov ss, {0}",
 
-            // jump to stage-3
+            // jump to the kernel
             "pop rax",
             "pop rdi",
             "call rax",
@@ -132,6 +124,104 @@ pub extern "C" fn _start(_count: u16) -> ! {
     hlt();
 }
 
+/// Everything below this is identity mapped once paging is on
+const IDENTITY_MAPPED_END: u64 = PDT_SPAN;
+/// Bytes a page directory maps with 2 MiB pages
+const PDT_SPAN: u64 = 1 << 30;
+
+/// Identity maps the first GiB and the framebuffer and turns on paging, entering the 32 bit
+/// compatability submode of long mode. A far jump to a 64 bit code segment finishes the switch.
+unsafe fn enable_long_mode() {
+    unsafe {
+        println!("Setting up paging");
+        load_page_tables();
+    }
+    unsafe {
+        asm!(
+            // Eanable PAE paging:
+            "mov eax, cr4",
+            "or eax, 1 << 5", // PAE-bit is the 6th bit
+            "mov cr4, eax",
+            // Set long mode bit:
+            // Set the C-register to the EFER Model Specific Register (MSR)
+            "mov ecx, 0xc0000080",
+            // Read from MSR
+            "rdmsr",
+            // Set the LM-bit
+            "or eax, 1 << 8",
+            // Write to MSR
+            "wrmsr",
+            // Enable paging
+            "mov eax, cr0",
+            "or eax, 1 << 31", // PG-bit is the 31st bit
+            "mov cr0, eax",
+            // We are now in the 32 bit compatability submode of long mode
+            out("eax") _,
+            out("ecx") _,
+            out("edx") _,
+        );
+    }
+}
+
+/// Copies the loadable segments of the kernel ELF that stage 1 left at `KERNEL_FILE_START` to
+/// the addresses they are linked at, zeroing their `.bss`. Returns the entry point.
+///
+/// Segments are copied to their physical address, and the kernel is jumped to with the first GiB
+/// identity mapped. So the kernel has to be linked with matching virtual and physical addresses
+/// somewhere between `KERNEL_ADDRESS` and 1 GiB.
+fn load_kernel(file: &[u8]) -> u32 {
+    let elf = match Elf::parse(file) {
+        Ok(elf) => elf,
+        Err(e) => panic!("Bad kernel ELF: {e:?}"),
+    };
+    if !elf.is_64_bit() {
+        panic!("Kernel is a 32 bit ELF without a Multiboot2 header");
+    }
+    load_elf(file, &elf, IDENTITY_MAPPED_END);
+
+    let entry = elf.entry();
+    if entry < KERNEL_ADDRESS as u64 || entry >= IDENTITY_MAPPED_END {
+        panic!("Kernel entry point {entry:#x} is outside of the kernel");
+    }
+    entry as u32
+}
+
+/// Copies every loadable segment of `elf`, which was parsed from `file`, to its physical address
+fn load_elf(file: &[u8], elf: &Elf, limit: u64) {
+    for segment in elf.load_segments() {
+        load_segment(
+            file,
+            elf.data(&segment),
+            segment.physical_address,
+            segment.memory_size,
+            limit,
+        );
+    }
+}
+
+/// Copies `data` from the kernel `file` to `start`, zeroing the rest of the `memory_size` bytes
+/// after it. Panics unless that memory is between `KERNEL_ADDRESS` and `limit`, and isn't where
+/// the kernel file is.
+fn load_segment(file: &[u8], data: &[u8], start: u64, memory_size: u64, limit: u64) {
+    let end = start + memory_size;
+    if start < KERNEL_ADDRESS as u64 || end > limit {
+        panic!("Kernel segment {start:#x}-{end:#x} is outside of usable memory");
+    }
+    let file_start = file.as_ptr() as u64;
+    let file_end = file_start + file.len() as u64;
+    if start < file_end && file_start < end {
+        panic!("Kernel segment {start:#x}-{end:#x} overlaps the kernel file");
+    }
+
+    let bss = memory_size as usize - data.len();
+    // SAFETY: Checked the segment is in free memory above the bootloader
+    unsafe {
+        let destination = start as *mut u8;
+        core::ptr::copy_nonoverlapping(data.as_ptr(), destination, data.len());
+        core::ptr::write_bytes(destination.add(data.len()), 0, bss);
+    }
+}
+
 #[allow(dead_code)]
 fn print_memory_addresses(start: *const u8) {
     let size = 16;
@@ -163,6 +253,8 @@ const CACHE_DISABLE: u64 = 1 << 4;
 #[allow(dead_code)]
 /// If the page is being accessed or not
 const ACCESSED: u64 = 1 << 5;
+/// In a page directory entry, maps a 2 MiB page instead of pointing to a page table
+const HUGE_PAGE: u64 = 1 << 7;
 
 /// Sets up paging at the configured addresses
 unsafe fn load_page_tables() {
@@ -170,7 +262,7 @@ unsafe fn load_page_tables() {
     // https://wiki.osdev.org/Setting_Up_Paging
 
     // Zero out addresses used for paging
-    let starts = [PML4T_START, PDPT_START, PDT_START, PT_START];
+    let starts = [PML4T_START, PDPT_START, PDT_START];
     //for val in unsafe { PML4T_START.as_uninit_ref()}
     for start in starts {
         // There are 512 entries in 64 bit page
@@ -182,23 +274,56 @@ unsafe fn load_page_tables() {
         }
     }
 
-    // Point first entry in PML4T, PDPT, and PDT to point to the only existing table
+    // Point first entry in PML4T and PDPT to point to the only existing table
     unsafe {
         // This or operation works because the page tables must be 0x1000 aligned, so the last 12
         // bits of the address must be 0.
         PML4T_START.as_mut().unwrap()[0] = PDPT_START as u64 | READ_WRITE | PRESENT;
         PDPT_START.as_mut().unwrap()[0] = PDT_START as u64 | READ_WRITE | PRESENT;
-        PDT_START.as_mut().unwrap()[0] = PT_START as u64 | READ_WRITE | PRESENT;
     }
 
-    // Identity map the PT to the first 2 MB
-    // SAFETY: PT_START is expected to be uninitalized, we initialize all the values here
-    let pt: &mut [u64; 0x200] = unsafe { PT_START.as_mut().unwrap() };
-    for ii in 0..0x200 {
-        let addr: u64 = 0x1000 * (ii as u64);
-        let flags: u64 = PRESENT | READ_WRITE;
-        let entry = addr | flags;
-        pt[ii] = entry;
+    // Identity map the first GiB with 2 MiB pages
+    // SAFETY: PDT_START was initialized above
+    let pdt: &mut [u64; 0x200] = unsafe { PDT_START.as_mut().unwrap() };
+    for (ii, entry) in pdt.iter_mut().enumerate() {
+        let addr: u64 = 0x200000 * (ii as u64);
+        *entry = addr | PRESENT | READ_WRITE | HUGE_PAGE;
+    }
+
+    // SAFETY: Stage 1 wrote the info
+    unsafe { map_framebuffer(&(*BIOS_INFO).framebuffer) };
+}
+
+/// Identity maps the GiB the framebuffer is in with 2 MiB pages, VBE framebuffers are usually
+/// near the top of the first 4 GiB. Panics if the framebuffer crosses into the next GiB, which
+/// PCI memory being aligned to its size rules out.
+///
+/// # Safety
+///
+/// The PDPT has to be set up already
+unsafe fn map_framebuffer(framebuffer: &FrameBufferInfo) {
+    if framebuffer.address == 0 {
+        return;
+    }
+    let start = framebuffer.address;
+    let end = start + framebuffer.pitch as u64 * framebuffer.height as u64;
+    let gib = start / PDT_SPAN;
+    if (end - 1) / PDT_SPAN != gib || gib >= 0x200 {
+        panic!("Can't map the framebuffer at {start:#x}-{end:#x}");
+    }
+    if gib == 0 {
+        return;
+    }
+
+    // SAFETY: Nothing else uses this memory
+    unsafe {
+        PDPT_START.as_mut().unwrap()[gib as usize] =
+            FRAMEBUFFER_PDT_START as u64 | READ_WRITE | PRESENT;
+        let pdt = FRAMEBUFFER_PDT_START.as_mut().unwrap();
+        for (ii, entry) in pdt.iter_mut().enumerate() {
+            let addr = gib * PDT_SPAN + 0x200000 * (ii as u64);
+            *entry = addr | PRESENT | READ_WRITE | HUGE_PAGE;
+        }
     }
 }
 
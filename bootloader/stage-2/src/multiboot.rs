@@ -0,0 +1,88 @@
+//! Boots Multiboot2 kernels. They are entered in 32 bit protected mode with paging off, which is
+//! what stage 2 is already running in, so there is no switch to long mode.
+
+use core::arch::asm;
+
+use common::elf::Elf;
+use common::multiboot2::{self, Header, InfoBuilder, BOOTLOADER_MAGIC, BOOTLOADER_NAME};
+use common::{print, println};
+use common::{BIOS_INFO, MULTIBOOT_INFO_ADDRESS, MULTIBOOT_INFO_SIZE};
+
+use crate::{load_elf, load_segment};
+
+/// Without paging the kernel can be anywhere in the first 4 GiB
+const ADDRESS_LIMIT: u64 = 1 << 32;
+
+/// Loads the kernel in `file` as asked for by its `header` and jumps to it
+pub fn boot(file: &[u8], header: &Header) -> ! {
+    let entry = match header.load_plan(file.len()) {
+        Some(Ok(plan)) => {
+            let data = &file[plan.file_offset..plan.file_offset + plan.file_size];
+            load_segment(
+                file,
+                data,
+                plan.load_address as u64,
+                plan.memory_size as u64,
+                ADDRESS_LIMIT,
+            );
+            // Parsing checks the entry tag comes with the address tag
+            header.entry.unwrap() as u64
+        }
+        Some(Err(e)) => panic!("Bad Multiboot2 address tag: {e:?}"),
+        // Without an address tag the kernel has to be an ELF file
+        None => {
+            let elf = match Elf::parse(file) {
+                Ok(elf) => elf,
+                Err(e) => panic!("Bad Multiboot2 kernel ELF: {e:?}"),
+            };
+            load_elf(file, &elf, ADDRESS_LIMIT);
+            header.entry.map_or(elf.entry(), |entry| entry as u64)
+        }
+    };
+    if entry >= ADDRESS_LIMIT {
+        panic!("Multiboot2 entry point {entry:#x} isn't reachable from protected mode");
+    }
+
+    let info_size = write_boot_info();
+    println!("Multiboot2 kernel entry point at {entry:#x}, {info_size} bytes of boot info");
+
+    // SAFETY: The kernel was loaded, and the machine is in the state Multiboot2 asks for: flat
+    // protected mode segments, A20 on, paging and interrupts off
+    unsafe {
+        asm!(
+            "jmp {entry:e}",
+            entry = in(reg) entry as u32,
+            in("eax") BOOTLOADER_MAGIC,
+            in("ebx") MULTIBOOT_INFO_ADDRESS as u32,
+            options(noreturn),
+        )
+    }
+}
+
+/// Builds the Multiboot2 boot information from what stage 1 found, returning its size
+fn write_boot_info() -> usize {
+    // SAFETY: Stage 1 filled these in, and nothing else uses the boot info memory
+    let (info, memory_map, cmdline, buffer) = unsafe {
+        let info = &*BIOS_INFO;
+        let buffer =
+            core::slice::from_raw_parts_mut(MULTIBOOT_INFO_ADDRESS as *mut u8, MULTIBOOT_INFO_SIZE);
+        (info, info.memory_map(), info.cmdline(), buffer)
+    };
+
+    let (lower, upper) = multiboot2::basic_memory(memory_map);
+    let mut builder = InfoBuilder::new(buffer);
+    builder
+        .cmdline(cmdline)
+        .bootloader_name(BOOTLOADER_NAME)
+        .basic_memory(lower, upper)
+        .boot_device(info.boot_drive)
+        .memory_map(memory_map);
+    // Left in text mode when the kernel didn't ask for a framebuffer
+    if info.framebuffer.address != 0 {
+        builder.framebuffer(&info.framebuffer);
+    }
+    match builder.finish() {
+        Ok(size) => size,
+        Err(e) => panic!("Building Multiboot2 boot info: {e:?}"),
+    }
+}
@@ -2,23 +2,60 @@
 #![no_main]
 
 use core::arch::asm;
+use core::fmt::Write;
 
-use common::{print, println};
+use common::qemu::{exit_qemu, QemuExitCode};
+use common::serial::{self, SerialWriter};
+use common::{print, println, BiosInfo, KernelEntry};
 
 use core::panic::PanicInfo;
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("PANIC: {info}");
+    let _ = writeln!(SerialWriter, "PANIC: {info}\r");
+    exit_qemu(QemuExitCode::Failed);
     loop {
         unsafe { asm!("hlt") }
     }
 }
 
-#[link_section = ".start"]
+// Make sure the entry point matches what stage 2 calls
+const _: KernelEntry = kernel_main;
+
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn kernel_main(info: &'static BiosInfo) -> ! {
+    serial::milestone(serial::STAGE_3_STARTED);
+    if !info.is_valid() {
+        panic!("Bad boot info at {:p}", info);
+    }
+
+    // SAFETY: Stage 2 identity maps the first GiB, which has the memory map
+    let memory_map = unsafe { info.memory_map() };
+    let usable: u64 = memory_map
+        .iter()
+        .filter(|entry| entry.region_type == 1)
+        .map(|entry| entry.length)
+        .sum();
+    let fb = &info.framebuffer;
+    // SAFETY: The command line is in stage 1's memory, which is identity mapped
+    let cmdline = unsafe { info.cmdline() };
+    let _ = writeln!(
+        SerialWriter,
+        "Booted from drive {:#x}, {} KiB usable memory in {} regions, {}x{}x{} framebuffer at {:#x}, cmdline {:?}\r",
+        info.boot_drive,
+        usable / 1024,
+        memory_map.len(),
+        fb.width,
+        fb.height,
+        fb.bits_per_pixel,
+        fb.address,
+        cmdline,
+    );
+
     unsafe {
         asm!("mov ah, 0xf0", "mov al, 'L'", "mov [0xb8000], ax",);
     }
+    // Made it all the way, lets `spenceros test` pass
+    exit_qemu(QemuExitCode::Success);
     loop {}
 }
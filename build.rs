@@ -91,15 +91,25 @@ fn build_elf(local_path: &Path, out_dir: &Path, bits: &NBits) -> PathBuf {
         .join(local_path.file_name().unwrap())
 }
 
+/// Builds a stage, returning the path of the raw binary. The kernel (stage 3) is loaded as an
+/// ELF by stage 2, so it stays one.
 fn build_stage(out_dir: &Path, stage_number: usize) -> PathBuf {
-    let stage_string = format!("stage-{stage_number}");
     let nbits = NBits::from_stage_number(stage_number);
     // Build ./bootloader/stage-{stage_number}/
+    let elf = build_package(out_dir, &format!("stage-{stage_number}"), &nbits);
+    match nbits {
+        NBits::Bits64 => elf,
+        _ => elf_to_bin(&elf, &nbits),
+    }
+}
+
+/// Builds the package in ./bootloader/{name}/, returning the path of the ELF
+fn build_package(out_dir: &Path, name: &str, nbits: &NBits) -> PathBuf {
     let local_path = Path::new(env!("CARGO_MANIFEST_DIR"))
         .join("bootloader")
-        .join(stage_string);
+        .join(name);
     println!("cargo:rerun-if-changed={}", local_path.display());
-    elf_to_bin(&build_elf(&local_path, out_dir, &nbits), &nbits)
+    build_elf(&local_path, out_dir, nbits)
 }
 
 fn main() {
@@ -108,6 +118,9 @@ fn main() {
         .join("bootloader")
         .join("common");
     println!("cargo:rerun-if-changed={}", common_path.display());
+    // Stage sizes and addresses, also used by the stage build scripts
+    let layout_path = common_path.join("..").join("layout.rs");
+    println!("cargo:rerun-if-changed={}", layout_path.display());
 
     let out = PathBuf::from(std::env::var("OUT_DIR").unwrap());
 
@@ -123,6 +136,14 @@ fn main() {
         handles.push(h);
     }
 
+    // The chainloading MBR, only used for images with the bootloader in a partition
+    let out_dir = out.clone();
+    handles.push(std::thread::spawn(move || {
+        let elf = build_package(&out_dir, "mbr", &NBits::Bits16);
+        let file = elf_to_bin(&elf, &NBits::Bits16);
+        println!("cargo:rustc-env=BIOS_MBR={}", file.display());
+    }));
+
     for h in handles.into_iter() {
         h.join().unwrap();
     }
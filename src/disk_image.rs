@@ -0,0 +1,630 @@
+//! Assembles bootable disk images out of the bootloader stages and any extra payloads
+
+mod fat;
+mod gpt;
+mod iso;
+mod mbr;
+
+pub use gpt::Guid;
+pub use mbr::MbrPartition;
+
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use common::config::{BootConfig, BootEntry, CONFIG_FILE, DEFAULT_INITRD, DEFAULT_KERNEL};
+use common::disk::StageLocation;
+use common::{
+    SECTORS_TO_READ, STAGES_START_LBA, STAGE_0_SECTIONS, STAGE_1_SECTIONS, STAGE_1_START,
+    STAGE_2_SECTIONS, STAGE_LOCATION_OFFSET,
+};
+
+use crate::{BOOT_0, BOOT_1, BOOT_2, BOOT_3, BOOT_MBR};
+
+/// Size of a disk sector in bytes
+pub const SECTOR_SIZE: u64 = 512;
+/// Payload partitions start on a multiple of this many sectors (1 MiB)
+pub const PARTITION_ALIGNMENT: u64 = 2048;
+
+/// Number of sectors each stage takes up, indexed by stage number
+const STAGE_SECTIONS: [usize; 3] = [STAGE_0_SECTIONS, STAGE_1_SECTIONS, STAGE_2_SECTIONS];
+
+#[derive(Debug)]
+pub enum ImageError {
+    /// A stage binary is not the size the bootloader expects, both in bytes
+    BadStageSize {
+        stage: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// The stages don't add up to the number of sectors stage 0 loads
+    BadSectorCount {
+        expected: usize,
+        found: usize,
+    },
+    /// Stage 0 doesn't end with the 0xaa55 boot signature
+    MissingBootSignature,
+    /// Stage 0 has code or data where the partition table goes
+    PartitionTableNotEmpty,
+    /// The image is larger than an MBR partition table can describe
+    TooLarge,
+    /// A file name doesn't fit the 8.3 uppercase names the boot partition uses
+    BadFileName(String),
+    /// Two files ended up with the same name in the boot partition
+    DuplicateFile(String),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::BadStageSize {
+                stage,
+                expected,
+                found,
+            } => write!(
+                f,
+                "stage {stage} was not correct size (expected 0x{expected:x} bytes, found 0x{found:x} bytes)"
+            ),
+            ImageError::BadSectorCount { expected, found } => write!(
+                f,
+                "stages take up 0x{found:x} sectors but stage 0 loads 0x{expected:x}"
+            ),
+            ImageError::MissingBootSignature => write!(f, "stage 0 is missing the boot signature"),
+            ImageError::PartitionTableNotEmpty => {
+                write!(f, "stage 0 overlaps the partition table")
+            }
+            ImageError::TooLarge => write!(f, "image too large for an MBR partition table"),
+            ImageError::BadFileName(name) => write!(f, "{name} is not an uppercase 8.3 name"),
+            ImageError::DuplicateFile(name) => write!(f, "{name} added more than once"),
+            ImageError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<std::io::Error> for ImageError {
+    fn from(e: std::io::Error) -> Self {
+        ImageError::Io(e)
+    }
+}
+
+/// Checks the stages are the sizes the bootloader expects them to be
+pub fn check_stage_sizes(stages: &[Vec<u8>; 3]) -> Result<(), ImageError> {
+    for (stage, (bytes, sections)) in stages.iter().zip(STAGE_SECTIONS).enumerate() {
+        if bytes.len() != sections * SECTOR_SIZE as usize {
+            return Err(ImageError::BadStageSize {
+                stage,
+                expected: sections * SECTOR_SIZE as usize,
+                found: bytes.len(),
+            });
+        }
+    }
+
+    // If this fails, need to read more sectors in stage 0 or 1
+    let total_sectors: usize = STAGE_SECTIONS.iter().sum();
+    if total_sectors != SECTORS_TO_READ + 1 {
+        return Err(ImageError::BadSectorCount {
+            expected: SECTORS_TO_READ,
+            found: total_sectors - 1,
+        });
+    }
+
+    Ok(())
+}
+
+/// Fills in the table in stage 0 saying where the following stages start on the disk
+fn locate_stages(stage_0: &[u8], lba: u64) -> Result<Vec<u8>, ImageError> {
+    let location = StageLocation {
+        lba: lba.try_into().map_err(|_| ImageError::TooLarge)?,
+        sectors: SECTORS_TO_READ as u16,
+        segment: (STAGE_1_START >> 4) as u16,
+    };
+    let mut stage_0 = stage_0.to_vec();
+    stage_0[STAGE_LOCATION_OFFSET..STAGE_LOCATION_OFFSET + StageLocation::SIZE]
+        .copy_from_slice(&location.to_bytes());
+    Ok(stage_0)
+}
+
+/// Which kind of partition table the image gets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PartitionScheme {
+    /// Classic MBR partition table in stage 0
+    #[default]
+    Mbr,
+    /// GUID partition table, with a protective MBR in stage 0
+    Gpt,
+    /// Classic MBR partition table behind a standard MBR that boots whatever partition is
+    /// active. Stage 0 goes in the first sector of a 1 MiB aligned bootloader partition, in
+    /// front of the other stages, so the image can carry other bootable partitions.
+    MbrChainload,
+}
+
+/// What a partition holds, mapped to an MBR type byte or GPT type GUID by each scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PartitionKind {
+    /// The stages following stage 0
+    BiosBoot,
+    /// The FAT32 boot partition with the kernel, configuration and data files
+    Fat32,
+}
+
+/// A partition independent of which partition table it goes in
+#[derive(Debug, Clone)]
+struct Partition {
+    name: &'static str,
+    kind: PartitionKind,
+    start_lba: u64,
+    sectors: u64,
+}
+
+/// Where something ended up on the disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    /// Name of the stage or partition table, or the name of a file in the boot partition
+    pub name: String,
+    /// First sector
+    pub lba: u64,
+    /// Number of sectors, including padding at the end of the last sector
+    pub sectors: u64,
+}
+
+/// Builds a disk image with the bootloader stages, the kernel and extra data files.
+///
+/// Stage 0 goes in the first sector and the other stages follow back to back from
+/// `STAGES_START_LBA`. With [`PartitionScheme::MbrChainload`] the first sector gets a standard
+/// MBR instead, and the stages go in their own partition with stage 0 as its boot sector. Either
+/// way the builder tells stage 0 where the stages are, see `StageLocation`. The kernel, the
+/// boot configuration and any data files go in the root directory of a 1 MiB aligned FAT32
+/// partition, where stage 1 looks them up by name.
+pub struct DiskImageBuilder {
+    mbr: Vec<u8>,
+    stages: [Vec<u8>; 3],
+    kernel: Vec<u8>,
+    cmdline: String,
+    initrd: Option<Vec<u8>>,
+    config: Option<String>,
+    files: Vec<(String, Vec<u8>)>,
+    scheme: PartitionScheme,
+    disk_size: u64,
+    disk_guid: Option<Guid>,
+}
+
+impl Default for DiskImageBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DiskImageBuilder {
+    /// Creates a builder using the stages and kernel built along with this crate
+    pub fn new() -> Self {
+        DiskImageBuilder {
+            mbr: BOOT_MBR.to_vec(),
+            stages: [BOOT_0.to_vec(), BOOT_1.to_vec(), BOOT_2.to_vec()],
+            kernel: BOOT_3.to_vec(),
+            cmdline: String::new(),
+            initrd: None,
+            config: None,
+            files: Vec::new(),
+            scheme: PartitionScheme::default(),
+            disk_size: 0,
+            disk_guid: None,
+        }
+    }
+
+    /// Replaces the binary used for a stage
+    pub fn stage(&mut self, stage: usize, bytes: impl Into<Vec<u8>>) -> &mut Self {
+        assert!(stage < self.stages.len(), "no stage {stage}");
+        self.stages[stage] = bytes.into();
+        self
+    }
+
+    /// Replaces the kernel, stored as `KERNEL.ELF` in the boot partition. It can be an ELF
+    /// kernel, a Multiboot2 kernel or a Linux bzImage.
+    pub fn kernel(&mut self, bytes: impl Into<Vec<u8>>) -> &mut Self {
+        self.kernel = bytes.into();
+        self
+    }
+
+    /// Sets the command line written to the generated boot configuration
+    pub fn cmdline(&mut self, cmdline: impl Into<String>) -> &mut Self {
+        self.cmdline = cmdline.into();
+        self
+    }
+
+    /// Adds an initrd for a Linux kernel, stored as `INITRD.IMG` and named in the generated boot
+    /// configuration
+    pub fn initrd(&mut self, bytes: impl Into<Vec<u8>>) -> &mut Self {
+        self.initrd = Some(bytes.into());
+        self
+    }
+
+    /// Uses the given text as the boot configuration instead of generating one
+    pub fn config(&mut self, text: impl Into<String>) -> &mut Self {
+        self.config = Some(text.into());
+        self
+    }
+
+    /// Adds a file to the root directory of the boot partition. The name has to be an uppercase
+    /// 8.3 name like `INITRD.IMG`.
+    pub fn file(&mut self, name: impl Into<String>, bytes: impl Into<Vec<u8>>) -> &mut Self {
+        self.files.push((name.into(), bytes.into()));
+        self
+    }
+
+    /// Selects the partition table written to the image, MBR by default
+    pub fn partition_scheme(&mut self, scheme: PartitionScheme) -> &mut Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Makes the image at least this many bytes, rounded up to a whole sector. The extra space
+    /// is left as a hole when the image is written to a file.
+    pub fn disk_size(&mut self, bytes: u64) -> &mut Self {
+        self.disk_size = bytes;
+        self
+    }
+
+    /// Sets the disk GUID of GPT images instead of generating a random one
+    pub fn disk_guid(&mut self, guid: Guid) -> &mut Self {
+        self.disk_guid = Some(guid);
+        self
+    }
+
+    /// The kernel, the boot configuration and every other file that goes next to them
+    fn boot_files(&self) -> Vec<(String, Vec<u8>)> {
+        let config = self.config.clone().unwrap_or_else(|| {
+            BootConfig::new(BootEntry {
+                cmdline: &self.cmdline,
+                initrd: self.initrd.as_ref().map(|_| DEFAULT_INITRD),
+                ..BootEntry::default()
+            })
+            .to_string()
+        });
+        let mut files = vec![
+            (DEFAULT_KERNEL.to_string(), self.kernel.clone()),
+            (CONFIG_FILE.to_string(), config.into_bytes()),
+        ];
+        if let Some(initrd) = &self.initrd {
+            files.push((DEFAULT_INITRD.to_string(), initrd.clone()));
+        }
+        files.extend(self.files.iter().cloned());
+        files
+    }
+
+    /// Lays out a bootable ISO 9660 CD image instead of a disk image. Stage 0 is the El Torito
+    /// boot image, and the files go in the root directory. The partition scheme and disk size
+    /// don't apply.
+    pub fn build_iso(&self) -> Result<DiskImage, ImageError> {
+        check_stage_sizes(&self.stages)?;
+
+        let mut image = DiskImage::default();
+        iso::write_iso(&mut image, &self.stages, &self.boot_files())?;
+        Ok(image)
+    }
+
+    /// Lays out everything and fills in the partition table
+    pub fn build(&self) -> Result<DiskImage, ImageError> {
+        check_stage_sizes(&self.stages)?;
+
+        let mut image = DiskImage::default();
+        // Stage 0 is the boot sector of the bootloader partition when chainloaded
+        let chainload = self.scheme == PartitionScheme::MbrChainload;
+        let (stage_0_lba, stages_lba) = if chainload {
+            image.place("mbr".into(), self.mbr.clone(), 0);
+            (PARTITION_ALIGNMENT, PARTITION_ALIGNMENT + 1)
+        } else {
+            (0, STAGES_START_LBA as u64)
+        };
+        let stage_0 = locate_stages(&self.stages[0], stages_lba)?;
+        image.place("stage-0".into(), stage_0, stage_0_lba);
+        let mut lba = stages_lba;
+        for (stage, bytes) in self.stages.iter().enumerate().skip(1) {
+            lba = image
+                .place(format!("stage-{stage}"), bytes.clone(), lba)
+                .end();
+        }
+
+        let bios_boot_start = if chainload { stage_0_lba } else { stages_lba };
+        let mut partitions = vec![Partition {
+            name: "BIOS boot",
+            kind: PartitionKind::BiosBoot,
+            start_lba: bios_boot_start,
+            sectors: lba - bios_boot_start,
+        }];
+
+        let files = self.boot_files();
+        let start = image.sectors.next_multiple_of(PARTITION_ALIGNMENT);
+        let volume = fat::format(&files, start)?;
+        for (lba, chunk) in volume.chunks {
+            image.add_chunk(start + lba, chunk);
+        }
+        for (name, lba, sectors) in volume.files {
+            image.layout.push(Placement {
+                name,
+                lba: start + lba,
+                sectors,
+            });
+        }
+        image.sectors = image.sectors.max(start + volume.sectors);
+        partitions.push(Partition {
+            name: "spenceros boot",
+            kind: PartitionKind::Fat32,
+            start_lba: start,
+            sectors: volume.sectors,
+        });
+
+        let min_sectors = self.disk_size.div_ceil(SECTOR_SIZE);
+        match self.scheme {
+            PartitionScheme::Mbr | PartitionScheme::MbrChainload => {
+                image.sectors = image.sectors.max(min_sectors);
+                let partitions = partitions
+                    .iter()
+                    .map(MbrPartition::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                mbr::write_partition_table(&mut image.chunks[0].1, &partitions)?;
+            }
+            PartitionScheme::Gpt => {
+                image.sectors = (image.sectors + gpt::BACKUP_SECTORS).max(min_sectors);
+                let disk_guid = self.disk_guid.unwrap_or_else(Guid::random);
+                gpt::write_gpt(&mut image, &partitions, disk_guid)?;
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+impl Placement {
+    /// First sector after this placement
+    pub fn end(&self) -> u64 {
+        self.lba + self.sectors
+    }
+}
+
+/// A built disk image.
+///
+/// Only the sectors that have something in them are kept in memory, so writing the image out
+/// leaves the gaps as holes in the file.
+#[derive(Debug, Default)]
+pub struct DiskImage {
+    /// Total size in sectors
+    sectors: u64,
+    /// Sector aligned data and the LBA it starts at
+    chunks: Vec<(u64, Vec<u8>)>,
+    layout: Vec<Placement>,
+}
+
+impl DiskImage {
+    /// Adds bytes starting at the given sector, padding them to a whole number of sectors
+    fn place(&mut self, name: String, mut bytes: Vec<u8>, lba: u64) -> Placement {
+        let sectors = (bytes.len() as u64).div_ceil(SECTOR_SIZE);
+        bytes.resize((sectors * SECTOR_SIZE) as usize, 0);
+        let placement = Placement { name, lba, sectors };
+
+        self.sectors = self.sectors.max(placement.end());
+        self.chunks.push((lba, bytes));
+        self.layout.push(placement.clone());
+        placement
+    }
+
+    /// Adds sector aligned bytes without recording them in the layout
+    fn add_chunk(&mut self, lba: u64, bytes: Vec<u8>) {
+        debug_assert!((bytes.len() as u64).is_multiple_of(SECTOR_SIZE));
+        self.sectors = self.sectors.max(lba + bytes.len() as u64 / SECTOR_SIZE);
+        self.chunks.push((lba, bytes));
+    }
+
+    /// Total size of the image in sectors
+    pub fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    /// Where each stage, the kernel and every file ended up, in the order they were placed
+    pub fn layout(&self) -> &[Placement] {
+        &self.layout
+    }
+
+    /// Looks up where something was placed by name
+    pub fn find(&self, name: &str) -> Option<&Placement> {
+        self.layout.iter().find(|p| p.name == name)
+    }
+
+    /// Returns the whole image as one contiguous buffer
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; (self.sectors * SECTOR_SIZE) as usize];
+        for (lba, chunk) in self.chunks.iter() {
+            let start = (lba * SECTOR_SIZE) as usize;
+            bytes[start..start + chunk.len()].copy_from_slice(chunk);
+        }
+        bytes
+    }
+
+    /// Writes the image to a file, replacing it if it exists
+    pub fn write(&self, path: &Path) -> Result<(), ImageError> {
+        let mut file = File::create(path)?;
+        file.set_len(self.sectors * SECTOR_SIZE)?;
+        for (lba, chunk) in self.chunks.iter() {
+            file.seek(SeekFrom::Start(lba * SECTOR_SIZE))?;
+            file.write_all(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_images_correct_size() {
+    let stages = [BOOT_0.to_vec(), BOOT_1.to_vec(), BOOT_2.to_vec()];
+    check_stage_sizes(&stages).unwrap();
+}
+
+/// Reads an in memory image like stage 1 reads the boot disk
+#[cfg(test)]
+struct ImageReader<'a>(&'a [u8]);
+
+#[cfg(test)]
+impl common::disk::SectorReader for ImageReader<'_> {
+    fn read_sectors(&mut self, lba: u64, buffer: &mut [u8]) -> Result<(), common::disk::DiskError> {
+        let start = (lba * SECTOR_SIZE) as usize;
+        buffer.copy_from_slice(&self.0[start..start + buffer.len()]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn read_boot_file(image: &DiskImage, name: &str) -> Vec<u8> {
+    use common::disk::SECTOR_SIZE;
+    use common::fat::{short_name, Fat32};
+
+    let bytes = image.to_bytes();
+    let mut disk = ImageReader(&bytes);
+    let (mut sector, mut fat_sector) = ([0; SECTOR_SIZE], [0; SECTOR_SIZE]);
+    let partition = common::partition::find_boot_partition(&mut disk, &mut sector).unwrap();
+    let mut volume = Fat32::open(&mut disk, &mut sector, &mut fat_sector, partition).unwrap();
+    let file = volume.find(&short_name(name).unwrap()).unwrap();
+    let mut contents = Vec::new();
+    volume
+        .read(&file, |piece| contents.extend_from_slice(piece))
+        .unwrap();
+    contents
+}
+
+#[test]
+fn test_boot_partition_readable() {
+    let kernel: Vec<u8> = (0..5000u32).map(|x| x as u8).collect();
+    for scheme in [
+        PartitionScheme::Mbr,
+        PartitionScheme::Gpt,
+        PartitionScheme::MbrChainload,
+    ] {
+        let image = DiskImageBuilder::new()
+            .partition_scheme(scheme)
+            .kernel(kernel.clone())
+            .cmdline("console=ttyS0")
+            .initrd(vec![2; 700])
+            .file("DATA.BIN", vec![1; 10])
+            .build()
+            .unwrap();
+
+        assert_eq!(read_boot_file(&image, DEFAULT_KERNEL), kernel);
+        assert_eq!(read_boot_file(&image, DEFAULT_INITRD), vec![2; 700]);
+        assert_eq!(read_boot_file(&image, "DATA.BIN"), vec![1; 10]);
+        let config = read_boot_file(&image, CONFIG_FILE);
+        let config = BootConfig::parse(std::str::from_utf8(&config).unwrap());
+        assert_eq!(config.entries()[0].cmdline, "console=ttyS0");
+        assert_eq!(config.entries()[0].initrd, Some(DEFAULT_INITRD));
+
+        // Files are contiguous, so their placement is where the data is on disk
+        let placement = image.find(DEFAULT_KERNEL).unwrap();
+        let start = (placement.lba * SECTOR_SIZE) as usize;
+        assert_eq!(image.to_bytes()[start..start + kernel.len()], kernel);
+    }
+}
+
+#[test]
+fn test_bad_file_names() {
+    let result = DiskImageBuilder::new().file("lower.txt", vec![]).build();
+    assert!(matches!(result, Err(ImageError::BadFileName(_))));
+    let result = DiskImageBuilder::new().file(CONFIG_FILE, vec![]).build();
+    assert!(matches!(result, Err(ImageError::DuplicateFile(_))));
+}
+
+#[test]
+fn test_gpt_past_2_tib() {
+    let size = 3 << 40;
+    let image = DiskImageBuilder::new()
+        .partition_scheme(PartitionScheme::Gpt)
+        .disk_size(size)
+        .build()
+        .unwrap();
+    assert_eq!(image.sectors(), size / SECTOR_SIZE);
+
+    // Backup header sits in the last sector and points back at the primary one
+    let backup = image.find("gpt-backup").unwrap();
+    assert_eq!(backup.end(), image.sectors());
+    let (_, chunk) = image
+        .chunks
+        .iter()
+        .find(|(lba, _)| *lba == backup.lba)
+        .unwrap();
+    let header = &chunk[chunk.len() - SECTOR_SIZE as usize..];
+    assert_eq!(&header[0..8], b"EFI PART");
+    assert_eq!(header[32..40], 1u64.to_le_bytes());
+
+    // Protective entry is capped at 32 bits
+    let stage_0 = &image.chunks[0].1;
+    assert_eq!(stage_0[mbr::PARTITION_TABLE_OFFSET + 4], 0xee);
+    assert_eq!(
+        stage_0[mbr::PARTITION_TABLE_OFFSET + 12..mbr::PARTITION_TABLE_OFFSET + 16],
+        u32::MAX.to_le_bytes()
+    );
+}
+
+#[test]
+fn test_chainload_layout() {
+    let image = DiskImageBuilder::new()
+        .partition_scheme(PartitionScheme::MbrChainload)
+        .build()
+        .unwrap();
+    let bytes = image.to_bytes();
+    let sector = |lba: u64| &bytes[(lba * SECTOR_SIZE) as usize..][..SECTOR_SIZE as usize];
+
+    assert_eq!(
+        sector(0)[..mbr::PARTITION_TABLE_OFFSET],
+        BOOT_MBR[..mbr::PARTITION_TABLE_OFFSET]
+    );
+    let stage_0 = image.find("stage-0").unwrap();
+    assert_eq!(stage_0.lba, PARTITION_ALIGNMENT);
+    let table = &sector(stage_0.lba)[STAGE_LOCATION_OFFSET..][..StageLocation::SIZE];
+    let location = StageLocation::from_bytes(table.try_into().unwrap());
+    assert_eq!(location.lba as u64, image.find("stage-1").unwrap().lba);
+    assert_eq!(location.lba as u64, stage_0.end());
+
+    // The MBR boots the active partition, which starts with stage 0
+    let entry = &sector(0)[mbr::PARTITION_TABLE_OFFSET..][..16];
+    assert_eq!(entry[0], 0x80);
+    assert_eq!(entry[8..12], (stage_0.lba as u32).to_le_bytes());
+    assert_eq!(entry[12..16], ((SECTORS_TO_READ + 1) as u32).to_le_bytes());
+}
+
+#[test]
+fn test_iso_readable() {
+    use common::iso9660::Iso9660;
+
+    let kernel: Vec<u8> = (0..5000u32).map(|x| x as u8).collect();
+    let image = DiskImageBuilder::new()
+        .kernel(kernel.clone())
+        .file("README", vec![1; 10])
+        .build_iso()
+        .unwrap();
+    let bytes = image.to_bytes();
+    assert!(bytes.len().is_multiple_of(common::CD_SECTOR_BYTES));
+
+    // Stage 0 reads the stages from the same byte offset as on a disk
+    let stage_1 = image.find("stage-1").unwrap();
+    assert_eq!(stage_1.lba, STAGES_START_LBA as u64);
+
+    let mut disk = ImageReader(&bytes);
+    let mut sector = [0; common::CD_SECTOR_BYTES];
+    let mut volume = Iso9660::open(&mut disk, &mut sector).unwrap();
+    let mut read = |name| {
+        let file = volume.find(name).unwrap();
+        let mut contents = Vec::new();
+        volume
+            .read(&file, |piece| contents.extend_from_slice(piece))
+            .unwrap();
+        contents
+    };
+    assert_eq!(read(DEFAULT_KERNEL), kernel);
+    assert_eq!(read("README"), vec![1; 10]);
+    assert!(volume.find("MISSING").is_err());
+
+    // The boot catalog's entry points at stage 0
+    let catalog = &bytes[19 * common::CD_SECTOR_BYTES..];
+    let boot_image = u32::from_le_bytes(catalog[40..44].try_into().unwrap()) as u64;
+    assert_eq!(image.find("stage-0").unwrap().lba, boot_image * 4);
+}
@@ -0,0 +1,189 @@
+//! Formats the FAT32 boot partition holding the kernel, the boot configuration and data files
+
+use common::fat::{short_name, ATTR_ARCHIVE, ATTR_VOLUME_ID, DIR_ENTRY_SIZE};
+
+use super::{ImageError, SECTOR_SIZE};
+
+/// Sectors before the first FAT, holding the boot sector, FS info sector and their backups
+const RESERVED_SECTORS: u64 = 32;
+/// Number of copies of the FAT
+const FAT_COUNT: u64 = 2;
+/// Sector of the FS info structure, relative to the start of the volume
+const FS_INFO_SECTOR: u64 = 1;
+/// Sector of the backup boot sector, relative to the start of the volume
+const BACKUP_BOOT_SECTOR: u64 = 6;
+const SECTORS_PER_CLUSTER: u64 = 1;
+const CLUSTER_SIZE: u64 = SECTORS_PER_CLUSTER * SECTOR_SIZE;
+/// Fewest clusters the volume is given. Anything below 65525 clusters is FAT16 by definition,
+/// and some slack is left since not every driver agrees on the exact boundary.
+const MIN_CLUSTERS: u64 = 0x10000;
+/// Marks the last cluster of a chain
+const END_OF_CHAIN: u32 = 0x0fff_ffff;
+
+const VOLUME_LABEL: &[u8; 11] = b"SPENCEROS  ";
+const VOLUME_ID: u32 = 0x5350_4f53;
+
+/// A formatted FAT32 volume
+pub struct Fat32Volume {
+    /// Total size in sectors
+    pub sectors: u64,
+    /// Sector aligned data and the sector it starts at, relative to the start of the volume.
+    /// The free clusters aren't included.
+    pub chunks: Vec<(u64, Vec<u8>)>,
+    /// Name, first sector relative to the start of the volume and number of sectors of every
+    /// file. Files are stored in contiguous clusters.
+    pub files: Vec<(String, u64, u64)>,
+}
+
+/// A file and the clusters it got
+struct Allocation<'a> {
+    file_name: &'a str,
+    name: [u8; 11],
+    bytes: &'a [u8],
+    first_cluster: u32,
+    clusters: u64,
+}
+
+/// Creates a FAT32 volume with the given files in its root directory.
+///
+/// `partition_lba` is stored in the boot sector as the number of hidden sectors before the
+/// volume.
+pub fn format(files: &[(String, Vec<u8>)], partition_lba: u64) -> Result<Fat32Volume, ImageError> {
+    // Root directory has the volume label and an entry for every file
+    let root_clusters = ((files.len() as u64 + 1) * DIR_ENTRY_SIZE as u64).div_ceil(CLUSTER_SIZE);
+
+    // Clusters 0 and 1 are reserved, the root directory starts at 2 followed by the files
+    let mut next_cluster = 2 + root_clusters;
+    let mut allocations: Vec<Allocation> = Vec::new();
+    for (name, bytes) in files.iter() {
+        let short = short_name(name).ok_or_else(|| ImageError::BadFileName(name.clone()))?;
+        if allocations.iter().any(|a| a.name == short) {
+            return Err(ImageError::DuplicateFile(name.clone()));
+        }
+        let clusters = (bytes.len() as u64).div_ceil(CLUSTER_SIZE);
+        allocations.push(Allocation {
+            file_name: name,
+            name: short,
+            bytes,
+            first_cluster: if clusters == 0 {
+                0
+            } else {
+                next_cluster as u32
+            },
+            clusters,
+        });
+        next_cluster += clusters;
+    }
+
+    let cluster_count = (next_cluster - 2).max(MIN_CLUSTERS);
+    let fat_sectors = ((cluster_count + 2) * 4).div_ceil(SECTOR_SIZE);
+    let data_start = RESERVED_SECTORS + FAT_COUNT * fat_sectors;
+    let sectors = data_start + cluster_count * SECTORS_PER_CLUSTER;
+    let total_sectors: u32 = sectors.try_into().map_err(|_| ImageError::TooLarge)?;
+    let hidden_sectors: u32 = partition_lba.try_into().map_err(|_| ImageError::TooLarge)?;
+
+    // Reserved sectors
+    let mut reserved = vec![0; (RESERVED_SECTORS * SECTOR_SIZE) as usize];
+    let boot = boot_sector(total_sectors, fat_sectors as u32, hidden_sectors);
+    let info = fs_info(cluster_count - (next_cluster - 2), next_cluster as u32);
+    for start in [0, BACKUP_BOOT_SECTOR] {
+        let at = (start * SECTOR_SIZE) as usize;
+        reserved[at..at + SECTOR_SIZE as usize].copy_from_slice(&boot);
+        let at = ((start + FS_INFO_SECTOR) * SECTOR_SIZE) as usize;
+        reserved[at..at + SECTOR_SIZE as usize].copy_from_slice(&info);
+    }
+
+    // FAT, with the media byte in entry 0 and entry 1 reserved
+    let mut fat = vec![0u32; (fat_sectors * SECTOR_SIZE / 4) as usize];
+    fat[0] = 0x0fff_fff8;
+    fat[1] = END_OF_CHAIN;
+    let mut chain = |first: u64, clusters: u64| {
+        for cluster in first..first + clusters {
+            let next = if cluster == first + clusters - 1 {
+                END_OF_CHAIN
+            } else {
+                cluster as u32 + 1
+            };
+            fat[cluster as usize] = next;
+        }
+    };
+    chain(2, root_clusters);
+    for allocation in allocations.iter().filter(|a| a.clusters != 0) {
+        chain(allocation.first_cluster as u64, allocation.clusters);
+    }
+    let fat: Vec<u8> = fat.iter().flat_map(|entry| entry.to_le_bytes()).collect();
+    let mut fats = Vec::with_capacity(fat.len() * FAT_COUNT as usize);
+    for _ in 0..FAT_COUNT {
+        fats.extend_from_slice(&fat);
+    }
+
+    // Root directory followed by the file contents
+    let mut data = vec![0; (root_clusters * CLUSTER_SIZE) as usize];
+    data[0..11].copy_from_slice(VOLUME_LABEL);
+    data[11] = ATTR_VOLUME_ID;
+    let mut placed = Vec::new();
+    for (ii, allocation) in allocations.iter().enumerate() {
+        let entry = &mut data[(ii + 1) * DIR_ENTRY_SIZE..(ii + 2) * DIR_ENTRY_SIZE];
+        entry[0..11].copy_from_slice(&allocation.name);
+        entry[11] = ATTR_ARCHIVE;
+        entry[20..22].copy_from_slice(&((allocation.first_cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(allocation.first_cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&(allocation.bytes.len() as u32).to_le_bytes());
+
+        let start = data_start + (allocation.first_cluster.max(2) as u64 - 2) * SECTORS_PER_CLUSTER;
+        let sectors = allocation.clusters * SECTORS_PER_CLUSTER;
+        placed.push((allocation.file_name.to_string(), start, sectors));
+    }
+    for allocation in allocations.iter() {
+        data.extend_from_slice(allocation.bytes);
+        data.resize(data.len().next_multiple_of(CLUSTER_SIZE as usize), 0);
+    }
+
+    Ok(Fat32Volume {
+        sectors,
+        chunks: vec![(0, reserved), (RESERVED_SECTORS, fats), (data_start, data)],
+        files: placed,
+    })
+}
+
+/// Encodes the boot sector with the BIOS parameter block
+fn boot_sector(total_sectors: u32, fat_sectors: u32, hidden_sectors: u32) -> Vec<u8> {
+    let mut boot = vec![0; SECTOR_SIZE as usize];
+    // Jump over the BPB, nothing boots from here
+    boot[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"SPENCER ");
+    boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    boot[13] = SECTORS_PER_CLUSTER as u8;
+    boot[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+    boot[16] = FAT_COUNT as u8;
+    // Root entries and 16 bit sector counts stay zero on FAT32, media is a fixed disk
+    boot[21] = 0xf8;
+    boot[24..26].copy_from_slice(&63u16.to_le_bytes());
+    boot[26..28].copy_from_slice(&255u16.to_le_bytes());
+    boot[28..32].copy_from_slice(&hidden_sectors.to_le_bytes());
+    boot[32..36].copy_from_slice(&total_sectors.to_le_bytes());
+    boot[36..40].copy_from_slice(&fat_sectors.to_le_bytes());
+    // Root directory cluster, FS info and backup boot sector locations
+    boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+    boot[48..50].copy_from_slice(&(FS_INFO_SECTOR as u16).to_le_bytes());
+    boot[50..52].copy_from_slice(&(BACKUP_BOOT_SECTOR as u16).to_le_bytes());
+    boot[64] = 0x80;
+    // Extended boot signature, followed by the volume id, label and file system type
+    boot[66] = 0x29;
+    boot[67..71].copy_from_slice(&VOLUME_ID.to_le_bytes());
+    boot[71..82].copy_from_slice(VOLUME_LABEL);
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+    boot
+}
+
+/// Encodes the FS info sector
+fn fs_info(free_clusters: u64, next_free: u32) -> Vec<u8> {
+    let mut info = vec![0; SECTOR_SIZE as usize];
+    info[0..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+    info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+    info[488..492].copy_from_slice(&(free_clusters as u32).to_le_bytes());
+    info[492..496].copy_from_slice(&next_free.to_le_bytes());
+    info[508..512].copy_from_slice(&0xaa55_0000u32.to_le_bytes());
+    info
+}
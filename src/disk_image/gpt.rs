@@ -0,0 +1,195 @@
+//! GUID partition table, with a protective MBR in stage 0 so BIOSes still boot the disk
+
+use std::hash::{BuildHasher, RandomState};
+
+use common::partition::{GPT_TYPE_BASIC_DATA, MBR_TYPE_GPT_PROTECTIVE};
+
+use super::{mbr, DiskImage, ImageError, Partition, PartitionKind, SECTOR_SIZE};
+
+/// Number of entries in the partition entry array, the minimum the spec allows
+const ENTRY_COUNT: usize = 128;
+/// Size of a partition entry in bytes
+const ENTRY_SIZE: usize = 128;
+/// Size of the header in bytes, the rest of its sector is zero
+const HEADER_SIZE: usize = 92;
+/// Sectors taken up by the partition entry array
+const ENTRY_ARRAY_SECTORS: u64 = (ENTRY_COUNT * ENTRY_SIZE) as u64 / SECTOR_SIZE;
+/// First sector that partitions can use, after the protective MBR, header and entry array
+pub const FIRST_USABLE_LBA: u64 = 2 + ENTRY_ARRAY_SECTORS;
+/// Sectors at the end of the disk taken up by the backup entry array and header
+pub const BACKUP_SECTORS: u64 = ENTRY_ARRAY_SECTORS + 1;
+
+// The BIOS boot partition starts where stage 0 loads from, so that has to be usable space
+const _: () = assert!(common::STAGES_START_LBA as u64 >= FIRST_USABLE_LBA);
+
+/// Partition type holding the stages that follow stage 0
+const TYPE_BIOS_BOOT: Guid = Guid::new(
+    0x21686148,
+    0x6449,
+    0x6e6f,
+    [0x74, 0x4e, 0x65, 0x65, 0x64, 0x45, 0x46, 0x49],
+);
+/// A GUID, stored in the mixed endian byte order used on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid([u8; 16]);
+
+impl Guid {
+    /// Creates a GUID from the fields of its usual `d1-d2-d3-d4[0..2]-d4[2..8]` text form
+    pub const fn new(d1: u32, d2: u16, d3: u16, d4: [u8; 8]) -> Self {
+        let d1 = d1.to_le_bytes();
+        let d2 = d2.to_le_bytes();
+        let d3 = d3.to_le_bytes();
+        Guid([
+            d1[0], d1[1], d1[2], d1[3], d2[0], d2[1], d3[0], d3[1], d4[0], d4[1], d4[2], d4[3],
+            d4[4], d4[5], d4[6], d4[7],
+        ])
+    }
+
+    /// Creates a random (version 4) GUID
+    pub fn random() -> Self {
+        let state = RandomState::new();
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&state.hash_one(0u8).to_le_bytes());
+        bytes[8..].copy_from_slice(&state.hash_one(1u8).to_le_bytes());
+        // Version 4 in the high nibble of d3, variant 0b10 in the top bits of d4
+        bytes[7] = (bytes[7] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Guid(bytes)
+    }
+
+    pub fn to_bytes(self) -> [u8; 16] {
+        self.0
+    }
+}
+
+impl PartitionKind {
+    fn gpt_type(&self) -> Guid {
+        match self {
+            PartitionKind::BiosBoot => TYPE_BIOS_BOOT,
+            PartitionKind::Fat32 => Guid(GPT_TYPE_BASIC_DATA),
+        }
+    }
+}
+
+/// Encodes the partition entry array
+fn entry_array(partitions: &[Partition]) -> Vec<u8> {
+    assert!(partitions.len() <= ENTRY_COUNT, "too many partitions");
+
+    let mut entries = vec![0; ENTRY_COUNT * ENTRY_SIZE];
+    for (entry, partition) in entries.chunks_exact_mut(ENTRY_SIZE).zip(partitions) {
+        entry[0..16].copy_from_slice(&partition.kind.gpt_type().to_bytes());
+        entry[16..32].copy_from_slice(&Guid::random().to_bytes());
+        entry[32..40].copy_from_slice(&partition.start_lba.to_le_bytes());
+        // Last LBA is inclusive
+        let last_lba = partition.start_lba + partition.sectors - 1;
+        entry[40..48].copy_from_slice(&last_lba.to_le_bytes());
+        // Attributes stay zero, name is UTF-16LE
+        for (ii, c) in partition.name.encode_utf16().take(36).enumerate() {
+            entry[56 + 2 * ii..58 + 2 * ii].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+    entries
+}
+
+/// Encodes a header sector
+fn header(
+    my_lba: u64,
+    alternate_lba: u64,
+    last_usable_lba: u64,
+    entries_lba: u64,
+    disk_guid: Guid,
+    entries_crc: u32,
+) -> Vec<u8> {
+    let mut header = vec![0; SECTOR_SIZE as usize];
+    header[0..8].copy_from_slice(b"EFI PART");
+    // Revision 1.0
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+    // Header CRC at 16..20 is computed with the field zeroed, 20..24 is reserved
+    header[24..32].copy_from_slice(&my_lba.to_le_bytes());
+    header[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+    header[40..48].copy_from_slice(&FIRST_USABLE_LBA.to_le_bytes());
+    header[48..56].copy_from_slice(&last_usable_lba.to_le_bytes());
+    header[56..72].copy_from_slice(&disk_guid.to_bytes());
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&(ENTRY_COUNT as u32).to_le_bytes());
+    header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+    header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+
+    let crc = crc32(&header[..HEADER_SIZE]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+/// Writes the protective MBR, the primary and backup headers and entry arrays.
+///
+/// The image has to already be sized to include the `BACKUP_SECTORS` at its end.
+pub fn write_gpt(
+    image: &mut DiskImage,
+    partitions: &[Partition],
+    disk_guid: Guid,
+) -> Result<(), ImageError> {
+    let last_lba = image.sectors - 1;
+    let backup_entries_lba = last_lba - ENTRY_ARRAY_SECTORS;
+    let last_usable_lba = backup_entries_lba - 1;
+    assert!(
+        partitions
+            .iter()
+            .all(|p| p.start_lba >= FIRST_USABLE_LBA
+                && p.start_lba + p.sectors - 1 <= last_usable_lba),
+        "partition outside of usable GPT space"
+    );
+
+    // Protective MBR covering everything but itself, capped at what 32 bits can describe
+    let protective = mbr::MbrPartition {
+        bootable: false,
+        kind: MBR_TYPE_GPT_PROTECTIVE,
+        start_lba: 1,
+        sectors: last_lba.min(u32::MAX as u64) as u32,
+    };
+    mbr::write_partition_table(&mut image.chunks[0].1, &[protective])?;
+
+    let entries = entry_array(partitions);
+    let entries_crc = crc32(&entries);
+
+    let mut primary = header(1, last_lba, last_usable_lba, 2, disk_guid, entries_crc);
+    primary.extend_from_slice(&entries);
+    image.place("gpt-primary".into(), primary, 1);
+
+    let mut backup = entries;
+    backup.extend(header(
+        last_lba,
+        1,
+        last_usable_lba,
+        backup_entries_lba,
+        disk_guid,
+        entries_crc,
+    ));
+    image.place("gpt-backup".into(), backup, backup_entries_lba);
+
+    Ok(())
+}
+
+/// CRC-32 (IEEE 802.3) as used by the GPT headers
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b"123456789"), 0xcbf43926);
+}
+
+#[test]
+fn test_guid_byte_order() {
+    // BIOS boot partition, 21686148-6449-6E6F-744E-656564454649, spells "Hah!IdontNeedEFI"
+    assert_eq!(&TYPE_BIOS_BOOT.to_bytes(), b"Hah!IdontNeedEFI");
+}
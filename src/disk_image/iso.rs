@@ -0,0 +1,246 @@
+//! ISO 9660 CD images with an El Torito boot catalog, booted without emulation
+
+use common::fat::short_name;
+use common::iso9660::{
+    FLAG_DIRECTORY, ROOT_RECORD_OFFSET, STANDARD_ID, TYPE_BOOT_RECORD, TYPE_PRIMARY,
+    TYPE_TERMINATOR, VOLUME_DESCRIPTORS_LBA,
+};
+use common::{CD_SECTOR_BYTES, SECTOR_BYTES, STAGES_START_LBA};
+
+use super::{locate_stages, DiskImage, ImageError, SECTOR_SIZE};
+
+/// Size of a CD sector in bytes
+const CD_SECTOR_SIZE: u64 = CD_SECTOR_BYTES as u64;
+/// Disk sectors in a CD sector, `DiskImage` counts in disk sectors
+const DISK_SECTORS: u64 = CD_SECTOR_SIZE / SECTOR_SIZE;
+/// CD sector of the boot catalog, after the primary, boot record and terminator descriptors
+const BOOT_CATALOG_LBA: u64 = VOLUME_DESCRIPTORS_LBA as u64 + 3;
+/// CD sector the other stages go in, the same byte offset as on disks
+pub const STAGES_LBA: u64 = (STAGES_START_LBA * SECTOR_BYTES / CD_SECTOR_BYTES) as u64;
+/// Virtual 512 byte sectors the BIOS loads from the boot image, one CD sector holding stage 0
+const BOOT_LOAD_COUNT: u16 = DISK_SECTORS as u16;
+
+const _: () = assert!(
+    STAGES_LBA > BOOT_CATALOG_LBA,
+    "stages overlap the boot catalog"
+);
+
+const VOLUME_ID: &str = "SPENCEROS";
+/// Size of the directory records of `.` and `..`
+const DOT_RECORD_SIZE: usize = 34;
+/// Size of a path table with only the root directory in it
+const PATH_TABLE_SIZE: usize = 10;
+
+/// Writes `value` in both byte orders, like most numbers in ISO 9660 are stored
+fn both_u32(bytes: &mut [u8], value: u32) {
+    bytes[0..4].copy_from_slice(&value.to_le_bytes());
+    bytes[4..8].copy_from_slice(&value.to_be_bytes());
+}
+
+fn both_u16(bytes: &mut [u8], value: u16) {
+    bytes[0..2].copy_from_slice(&value.to_le_bytes());
+    bytes[2..4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Encodes a directory record
+fn directory_record(identifier: &[u8], lba: u64, size: u64, flags: u8) -> Vec<u8> {
+    // Padded to an even length
+    let len = (33 + identifier.len()).next_multiple_of(2);
+    let mut record = vec![0; len];
+    record[0] = len as u8;
+    both_u32(&mut record[2..10], lba as u32);
+    both_u32(&mut record[10..18], size as u32);
+    // Recorded at 1970-01-01 00:00 UTC, years count from 1900
+    record[18..25].copy_from_slice(&[70, 1, 1, 0, 0, 0, 0]);
+    record[25] = flags;
+    both_u16(&mut record[28..32], 1);
+    record[32] = identifier.len() as u8;
+    record[33..33 + identifier.len()].copy_from_slice(identifier);
+    record
+}
+
+/// Starts a volume descriptor sector of the given type
+fn volume_descriptor(kind: u8) -> Vec<u8> {
+    let mut descriptor = vec![0; CD_SECTOR_BYTES];
+    descriptor[0] = kind;
+    descriptor[1..6].copy_from_slice(STANDARD_ID);
+    descriptor[6] = 1;
+    descriptor
+}
+
+/// Encodes the primary volume descriptor
+fn primary_descriptor(
+    sectors: u64,
+    path_table_lba: u64,
+    root: &[u8],
+) -> Result<Vec<u8>, ImageError> {
+    let mut descriptor = volume_descriptor(TYPE_PRIMARY);
+    // Text fields are space padded
+    descriptor[8..72].fill(b' ');
+    descriptor[40..40 + VOLUME_ID.len()].copy_from_slice(VOLUME_ID.as_bytes());
+    let sectors = sectors.try_into().map_err(|_| ImageError::TooLarge)?;
+    both_u32(&mut descriptor[80..88], sectors);
+    // One volume in the set, and this is it
+    both_u16(&mut descriptor[120..124], 1);
+    both_u16(&mut descriptor[124..128], 1);
+    both_u16(&mut descriptor[128..132], CD_SECTOR_BYTES as u16);
+    both_u32(&mut descriptor[132..140], PATH_TABLE_SIZE as u32);
+    descriptor[140..144].copy_from_slice(&(path_table_lba as u32).to_le_bytes());
+    descriptor[148..152].copy_from_slice(&(path_table_lba as u32 + 1).to_be_bytes());
+    descriptor[ROOT_RECORD_OFFSET..ROOT_RECORD_OFFSET + DOT_RECORD_SIZE].copy_from_slice(root);
+    descriptor[190..813].fill(b' ');
+    // Creation, modification, expiration and effective dates are "not specified", 16 zero
+    // digits and a zero time zone
+    for date in descriptor[813..881].chunks_exact_mut(17) {
+        date[..16].fill(b'0');
+    }
+    // File structure version
+    descriptor[881] = 1;
+    Ok(descriptor)
+}
+
+/// Encodes the El Torito boot record pointing at the boot catalog
+fn boot_record() -> Vec<u8> {
+    let mut descriptor = volume_descriptor(TYPE_BOOT_RECORD);
+    let system_id = b"EL TORITO SPECIFICATION";
+    descriptor[7..7 + system_id.len()].copy_from_slice(system_id);
+    descriptor[71..75].copy_from_slice(&(BOOT_CATALOG_LBA as u32).to_le_bytes());
+    descriptor
+}
+
+/// Encodes the boot catalog with a single no emulation entry for the boot image
+fn boot_catalog(boot_image_lba: u64) -> Vec<u8> {
+    let mut catalog = vec![0; CD_SECTOR_BYTES];
+
+    // Validation entry for x86, the words of which have to add up to zero
+    let validation = &mut catalog[0..32];
+    validation[0] = 1;
+    validation[30] = 0x55;
+    validation[31] = 0xaa;
+    let sum = validation.chunks_exact(2).fold(0u16, |sum, word| {
+        sum.wrapping_add(u16::from_le_bytes([word[0], word[1]]))
+    });
+    validation[28..30].copy_from_slice(&sum.wrapping_neg().to_le_bytes());
+
+    // Default entry, bootable without emulation and loaded to the default 0x07c0:0000
+    let entry = &mut catalog[32..64];
+    entry[0] = 0x88;
+    entry[6..8].copy_from_slice(&BOOT_LOAD_COUNT.to_le_bytes());
+    entry[8..12].copy_from_slice(&(boot_image_lba as u32).to_le_bytes());
+    catalog
+}
+
+/// Encodes the little and big endian path tables, each with only the root directory
+fn path_tables(root_lba: u64) -> Vec<u8> {
+    let mut tables = vec![0; 2 * CD_SECTOR_BYTES];
+    for (table, big_endian) in [(0, false), (CD_SECTOR_BYTES, true)] {
+        let entry = &mut tables[table..table + PATH_TABLE_SIZE];
+        entry[0] = 1;
+        let (lba, parent) = if big_endian {
+            ((root_lba as u32).to_be_bytes(), 1u16.to_be_bytes())
+        } else {
+            ((root_lba as u32).to_le_bytes(), 1u16.to_le_bytes())
+        };
+        entry[2..6].copy_from_slice(&lba);
+        entry[6..8].copy_from_slice(&parent);
+    }
+    tables
+}
+
+/// Lays out a CD with the stages, and `files` in the root directory.
+///
+/// Stage 0 is the boot image, and the other stages sit at `STAGES_LBA`.
+pub fn write_iso(
+    image: &mut DiskImage,
+    stages: &[Vec<u8>; 3],
+    files: &[(String, Vec<u8>)],
+) -> Result<(), ImageError> {
+    let mut lba = STAGES_LBA * DISK_SECTORS;
+    for (stage, bytes) in stages.iter().enumerate().skip(1) {
+        lba = image
+            .place(format!("stage-{stage}"), bytes.clone(), lba)
+            .end();
+    }
+    let boot_image_lba = lba.div_ceil(DISK_SECTORS);
+    let path_table_lba = boot_image_lba + 1;
+    let root_lba = path_table_lba + 2;
+
+    // Identifiers are sorted, and records can't cross a sector
+    let mut identifiers = Vec::new();
+    for (name, _) in files {
+        if short_name(name).is_none() {
+            return Err(ImageError::BadFileName(name.clone()));
+        }
+        let identifier = if name.contains('.') {
+            format!("{name};1")
+        } else {
+            format!("{name}.;1")
+        };
+        if identifiers.iter().any(|(other, _)| *other == identifier) {
+            return Err(ImageError::DuplicateFile(name.clone()));
+        }
+        identifiers.push((identifier, name));
+    }
+    identifiers.sort();
+    let mut root_size = 2 * DOT_RECORD_SIZE as u64;
+    for (identifier, _) in identifiers.iter() {
+        let len = (33 + identifier.len()).next_multiple_of(2) as u64;
+        if root_size % CD_SECTOR_SIZE + len > CD_SECTOR_SIZE {
+            root_size = root_size.next_multiple_of(CD_SECTOR_SIZE);
+        }
+        root_size += len;
+    }
+    let root_size = root_size.next_multiple_of(CD_SECTOR_SIZE);
+
+    let root_record =
+        |identifier: &[u8]| directory_record(identifier, root_lba, root_size, FLAG_DIRECTORY);
+    let mut root = root_record(&[0]);
+    root.extend(root_record(&[1]));
+    let mut lba = root_lba + root_size / CD_SECTOR_SIZE;
+    for (identifier, name) in identifiers.iter() {
+        let (_, bytes) = files.iter().find(|(file, _)| file == *name).unwrap();
+        let record = directory_record(identifier.as_bytes(), lba, bytes.len() as u64, 0);
+        if root.len() % CD_SECTOR_BYTES + record.len() > CD_SECTOR_BYTES {
+            root.resize(root.len().next_multiple_of(CD_SECTOR_BYTES), 0);
+        }
+        root.extend(record);
+
+        let sectors = image
+            .place(name.to_string(), bytes.clone(), lba * DISK_SECTORS)
+            .sectors;
+        lba += sectors.div_ceil(DISK_SECTORS);
+    }
+    let sectors = lba;
+    root.resize(root_size as usize, 0);
+
+    let mut descriptors = primary_descriptor(sectors, path_table_lba, &root[..DOT_RECORD_SIZE])?;
+    descriptors.extend(boot_record());
+    descriptors.extend(volume_descriptor(TYPE_TERMINATOR));
+    descriptors.extend(boot_catalog(boot_image_lba));
+    let descriptors_lba = VOLUME_DESCRIPTORS_LBA as u64 * DISK_SECTORS;
+    image.place("iso-descriptors".into(), descriptors, descriptors_lba);
+
+    let stage_0 = locate_stages(&stages[0], STAGES_LBA * DISK_SECTORS)?;
+    image.place("stage-0".into(), stage_0, boot_image_lba * DISK_SECTORS);
+    image.place(
+        "path-tables".into(),
+        path_tables(root_lba),
+        path_table_lba * DISK_SECTORS,
+    );
+    image.place("root-directory".into(), root, root_lba * DISK_SECTORS);
+    // Round up to a whole CD sector
+    image.sectors = sectors * DISK_SECTORS;
+
+    Ok(())
+}
+
+#[test]
+fn test_boot_catalog_checksum() {
+    let catalog = boot_catalog(0x24);
+    let sum = catalog[0..32].chunks_exact(2).fold(0u16, |sum, word| {
+        sum.wrapping_add(u16::from_le_bytes([word[0], word[1]]))
+    });
+    assert_eq!(sum, 0);
+    assert_eq!(catalog[32], 0x88);
+    assert_eq!(catalog[40..44], 0x24u32.to_le_bytes());
+}
@@ -0,0 +1,137 @@
+//! The classic MBR partition table, written into the space stage 0 reserves at `_partition_table`
+
+use common::partition::MBR_TYPE_FAT32_LBA;
+
+use super::{ImageError, Partition, PartitionKind};
+
+/// Offset of the partition table in the boot sector
+pub const PARTITION_TABLE_OFFSET: usize = 446;
+/// Number of entries in the partition table
+pub const PARTITION_COUNT: usize = 4;
+/// Size of a single partition table entry in bytes
+const ENTRY_SIZE: usize = 16;
+/// Offset of the 0xaa55 boot signature in the boot sector
+const SIGNATURE_OFFSET: usize = 510;
+
+/// Partition type for regions without a filesystem, used for the stages
+pub const TYPE_NON_FS_DATA: u8 = 0xda;
+
+impl TryFrom<&Partition> for MbrPartition {
+    type Error = ImageError;
+
+    fn try_from(partition: &Partition) -> Result<Self, ImageError> {
+        Ok(MbrPartition {
+            bootable: partition.kind == PartitionKind::BiosBoot,
+            kind: match partition.kind {
+                PartitionKind::BiosBoot => TYPE_NON_FS_DATA,
+                PartitionKind::Fat32 => MBR_TYPE_FAT32_LBA,
+            },
+            start_lba: partition
+                .start_lba
+                .try_into()
+                .map_err(|_| ImageError::TooLarge)?,
+            sectors: partition
+                .sectors
+                .try_into()
+                .map_err(|_| ImageError::TooLarge)?,
+        })
+    }
+}
+
+/// Heads per cylinder used when translating LBAs to CHS addresses
+const HEADS: u64 = 255;
+/// Sectors per track used when translating LBAs to CHS addresses
+const SECTORS_PER_TRACK: u64 = 63;
+
+/// A single entry of the MBR partition table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MbrPartition {
+    /// If the partition is marked active (bootable)
+    pub bootable: bool,
+    /// Partition type byte
+    pub kind: u8,
+    /// First sector of the partition
+    pub start_lba: u32,
+    /// Number of sectors in the partition
+    pub sectors: u32,
+}
+
+impl MbrPartition {
+    /// Encodes the entry as it is laid out on disk
+    pub fn to_bytes(&self) -> [u8; ENTRY_SIZE] {
+        let mut entry = [0; ENTRY_SIZE];
+        entry[0] = if self.bootable { 0x80 } else { 0 };
+        entry[1..4].copy_from_slice(&chs(self.start_lba as u64));
+        entry[4] = self.kind;
+        let last_lba = (self.start_lba as u64 + self.sectors as u64).saturating_sub(1);
+        entry[5..8].copy_from_slice(&chs(last_lba));
+        entry[8..12].copy_from_slice(&self.start_lba.to_le_bytes());
+        entry[12..16].copy_from_slice(&self.sectors.to_le_bytes());
+        entry
+    }
+}
+
+/// Converts an LBA to the packed (head, sector/cylinder high, cylinder low) triple used in
+/// partition entries, saturating to the conventional 0xfe 0xff 0xff past 1024 cylinders
+fn chs(lba: u64) -> [u8; 3] {
+    let cylinder = lba / (HEADS * SECTORS_PER_TRACK);
+    if cylinder > 1023 {
+        return [0xfe, 0xff, 0xff];
+    }
+    let head = (lba / SECTORS_PER_TRACK) % HEADS;
+    let sector = lba % SECTORS_PER_TRACK + 1;
+    [
+        head as u8,
+        sector as u8 | ((cylinder >> 2) & 0xc0) as u8,
+        cylinder as u8,
+    ]
+}
+
+/// Fills in the partition table of a boot sector.
+///
+/// Fails if the boot sector isn't signed or if something other than zeros already lives where
+/// the partition table goes, which would mean stage 0 grew into it.
+pub fn write_partition_table(
+    boot_sector: &mut [u8],
+    partitions: &[MbrPartition],
+) -> Result<(), ImageError> {
+    assert!(partitions.len() <= PARTITION_COUNT, "too many partitions");
+
+    if boot_sector[SIGNATURE_OFFSET..SIGNATURE_OFFSET + 2] != [0x55, 0xaa] {
+        return Err(ImageError::MissingBootSignature);
+    }
+
+    let table = &mut boot_sector[PARTITION_TABLE_OFFSET..SIGNATURE_OFFSET];
+    if table.iter().any(|b| *b != 0) {
+        return Err(ImageError::PartitionTableNotEmpty);
+    }
+
+    for (entry, partition) in table.chunks_exact_mut(ENTRY_SIZE).zip(partitions) {
+        entry.copy_from_slice(&partition.to_bytes());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_chs_translation() {
+    assert_eq!(chs(0), [0, 1, 0]);
+    assert_eq!(chs(1), [0, 2, 0]);
+    assert_eq!(chs(2048), [32, 33, 0]);
+    assert_eq!(chs(u32::MAX as u64), [0xfe, 0xff, 0xff]);
+}
+
+#[test]
+fn test_partition_entry_layout() {
+    let partition = MbrPartition {
+        bootable: true,
+        kind: TYPE_NON_FS_DATA,
+        start_lba: 1,
+        sectors: 0x60,
+    };
+    let bytes = partition.to_bytes();
+    assert_eq!(bytes[0], 0x80);
+    assert_eq!(bytes[4], TYPE_NON_FS_DATA);
+    assert_eq!(bytes[8..12], [1, 0, 0, 0]);
+    assert_eq!(bytes[12..16], [0x60, 0, 0, 0]);
+}
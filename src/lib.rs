@@ -0,0 +1,20 @@
+//! Host side tooling for spenceros, used to assemble bootable disk images from the bootloader
+//! stages built by build.rs and run them in QEMU.
+
+pub mod disk_image;
+pub mod qemu;
+
+pub use disk_image::{DiskImage, DiskImageBuilder, ImageError, PartitionScheme};
+
+/// Raw binary of the MBR that chainloads stage 0 from the active partition
+pub const BOOT_MBR: &[u8] = include_bytes!(env!("BIOS_MBR"));
+/// Raw binary of stage 0, the boot sector
+pub const BOOT_0: &[u8] = include_bytes!(env!("BIOS_STAGE0"));
+/// Raw binary of stage 1
+pub const BOOT_1: &[u8] = include_bytes!(env!("BIOS_STAGE1"));
+/// Raw binary of stage 2
+pub const BOOT_2: &[u8] = include_bytes!(env!("BIOS_STAGE2"));
+/// ELF of stage 3, the default kernel
+pub const BOOT_3: &[u8] = include_bytes!(env!("BIOS_STAGE3"));
+/// Path of the stage 3 ELF, for loading its symbols into a debugger
+pub const BOOT_3_PATH: &str = env!("BIOS_STAGE3");
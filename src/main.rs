@@ -1,87 +1,184 @@
-use std::path::Path;
-
-use common::{
-    SECTORS_TO_READ, STAGE_0_SECTIONS, STAGE_1_SECTIONS, STAGE_2_SECTIONS, STAGE_3_SECTIONS,
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-const BOOT_0: &[u8] = include_bytes!(env!("BIOS_STAGE0"));
-const BOOT_1: &[u8] = include_bytes!(env!("BIOS_STAGE1"));
-const BOOT_2: &[u8] = include_bytes!(env!("BIOS_STAGE2"));
-const BOOT_3: &[u8] = include_bytes!(env!("BIOS_STAGE3"));
-const EXTRA_BYTES: [u8; 512] = [0; 512];
+use spenceros::qemu::{Mode, Qemu, TestOutcome, GDB_PORT};
+use spenceros::{DiskImageBuilder, PartitionScheme, BOOT_3_PATH};
+
+const USAGE: &str = "\
+Usage: spenceros [COMMAND] [OPTIONS]
 
-fn assert_sizes() {
-    // First section needs to always be 512 bytes
-    assert_eq!(BOOT_0.len(), 512, "boot entry point was not correct size");
-    // Check section 1 is the correct size
-    assert_eq!(
-        BOOT_1.len(),
-        512 * STAGE_1_SECTIONS,
-        "boot stage 1 was not correct size (0x{:x} sections)",
-        BOOT_1.len() / 0x200
-    );
-    // Check section 2 is the correct size
-    assert_eq!(
-        BOOT_2.len(),
-        512 * STAGE_2_SECTIONS,
-        "boot stage 2 was not correct size (0x{:x} sections)",
-        BOOT_2.len() / 0x200
-    );
-    // Check section 2 is the correct size
-    assert_eq!(
-        BOOT_3.len(),
-        512 * STAGE_3_SECTIONS,
-        "boot stage 3 was not correct size (0x{:x} sections)",
-        BOOT_3.len() / 0x200
-    );
+Commands:
+  build    Only write the disk image
+  run      Boot the image in QEMU (default)
+  debug    Boot the image and wait for gdb to attach
+  test     Boot headless and exit with the result the guest reports
 
-    // If this fails, need to read more sectors in stage 0 or 1
-    let total_sectors = STAGE_0_SECTIONS + STAGE_1_SECTIONS + STAGE_2_SECTIONS + STAGE_3_SECTIONS;
-    assert_eq!(
-        total_sectors,
-        SECTORS_TO_READ + 1,
-        "Total sectors did not match expected"
-    );
-    assert!(
-        total_sectors <= u8::MAX as usize,
-        "too many sectors to read with one u8"
-    );
+Options:
+  --image <PATH>    Where to write the image [default: target/disk.img]
+  --gpt             Use a GPT partition table instead of MBR
+  --chainload       Boot the active partition from a standard MBR, with the stages in it
+  --iso             Write a bootable ISO 9660 CD image instead and boot it as a CD
+  --kernel <PATH>   Boot this kernel instead of stage 3, an ELF, Multiboot2 or bzImage file
+  --initrd <PATH>   Initrd for a Linux kernel
+  --cmdline <TEXT>  Command line passed to the kernel
+  --memory <SIZE>   Guest memory [default: 128M]
+  --cpus <N>        Number of CPUs [default: 1]
+  --vga <TYPE>      Emulated graphics card, like std or cirrus
+  --nographic       Run without a window, serial and monitor on the terminal
+  -h, --help        Print this message
+";
+
+/// What to do once the image is written
+enum Command {
+    Build,
+    Qemu(Mode),
 }
 
-fn main() {
-    assert_sizes();
-    // Put all sections together
-    let disk_bytes: Vec<u8> = BOOT_0
-        .iter()
-        .chain(BOOT_1.iter())
-        .chain(BOOT_2.iter())
-        .chain(BOOT_3.iter())
-        .chain(EXTRA_BYTES.iter())
-        .cloned()
-        .collect();
+struct Args {
+    command: Command,
+    image: PathBuf,
+    scheme: PartitionScheme,
+    iso: bool,
+    kernel: Option<PathBuf>,
+    initrd: Option<PathBuf>,
+    cmdline: String,
+    qemu: Qemu,
+}
 
-    // Write to file
-    let disk_image_file = Path::new(env!("CARGO_MANIFEST_DIR"))
+/// Parses the command line, returning an error message on bad input
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut command = None;
+    let mut image = Path::new(env!("CARGO_MANIFEST_DIR"))
         .join("target")
         .join("disk.img");
-    std::fs::write(&disk_image_file, &disk_bytes).unwrap();
+    let mut scheme = PartitionScheme::Mbr;
+    let mut iso = false;
+    let (mut kernel, mut initrd, mut cmdline) = (None, None, String::new());
+    let mut qemu = Qemu::default();
 
-    // Launch qemu
-    let mut cmd = std::process::Command::new("qemu-system-x86_64");
-    let extra_args = format!(
-        "file={},format=raw,index=0,media=disk",
-        disk_image_file.display()
-    );
-    cmd.arg("-drive").arg(extra_args);
-    let out = cmd.output().unwrap();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "build" | "run" | "debug" | "test" if command.is_none() => {
+                command = Some(match arg.as_str() {
+                    "build" => Command::Build,
+                    "run" => Command::Qemu(Mode::Run),
+                    "debug" => Command::Qemu(Mode::Debug),
+                    _ => Command::Qemu(Mode::Test),
+                });
+            }
+            "--image" => image = value()?.into(),
+            "--gpt" => scheme = PartitionScheme::Gpt,
+            "--chainload" => scheme = PartitionScheme::MbrChainload,
+            "--iso" => {
+                iso = true;
+                qemu.cdrom(true);
+            }
+            "--kernel" => kernel = Some(value()?.into()),
+            "--initrd" => initrd = Some(value()?.into()),
+            "--cmdline" => cmdline = value()?,
+            "--memory" => {
+                qemu.memory(value()?);
+            }
+            "--cpus" => {
+                let cpus = value()?;
+                let cpus = cpus.parse().map_err(|_| format!("bad cpu count {cpus}"))?;
+                qemu.cpus(cpus);
+            }
+            "--vga" => {
+                qemu.vga(value()?);
+            }
+            "--nographic" => {
+                qemu.nographic(true);
+            }
+            "-h" | "--help" => return Err(String::new()),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
 
-    // Print Results
-    let stdout = String::from_utf8(out.stdout).unwrap();
-    let stderr = String::from_utf8(out.stderr).unwrap();
-    println!("{}\n{}", stdout, stderr);
+    let command = command.unwrap_or(Command::Qemu(Mode::Run));
+    if let Command::Qemu(mode) = command {
+        qemu.mode(mode);
+    }
+    Ok(Args {
+        command,
+        image,
+        scheme,
+        iso,
+        kernel,
+        initrd,
+        cmdline,
+        qemu,
+    })
 }
 
-#[test]
-fn test_images_correct_size() {
-    assert_sizes();
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("error: {message}\n");
+            }
+            eprint!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut builder = DiskImageBuilder::new();
+    builder
+        .partition_scheme(args.scheme)
+        .cmdline(args.cmdline.as_str());
+    let read = |path: &Path| {
+        std::fs::read(path).unwrap_or_else(|e| panic!("Reading {}: {e}", path.display()))
+    };
+    if let Some(kernel) = &args.kernel {
+        builder.kernel(read(kernel));
+    }
+    if let Some(initrd) = &args.initrd {
+        builder.initrd(read(initrd));
+    }
+    let image = if args.iso {
+        builder.build_iso().unwrap()
+    } else {
+        builder.build().unwrap()
+    };
+    if let Some(parent) = args.image.parent() {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+    image.write(&args.image).unwrap();
+    println!("Wrote {}", args.image.display());
+
+    let Command::Qemu(mode) = args.command else {
+        return ExitCode::SUCCESS;
+    };
+    if mode == Mode::Debug {
+        println!("Waiting for gdb, connect with:");
+        let symbols = args.kernel.as_deref().unwrap_or(Path::new(BOOT_3_PATH));
+        println!(
+            "  gdb {} -ex 'target remote localhost:{GDB_PORT}'",
+            symbols.display()
+        );
+    }
+
+    let status = args
+        .qemu
+        .run(&args.image)
+        .expect("Failed to start qemu-system-x86_64");
+    match mode {
+        Mode::Test => match TestOutcome::from(status) {
+            TestOutcome::Passed => ExitCode::SUCCESS,
+            TestOutcome::Failed => {
+                eprintln!("Guest reported failure");
+                ExitCode::FAILURE
+            }
+            TestOutcome::Crashed(code) => {
+                eprintln!("QEMU exited without a result ({code:?})");
+                ExitCode::from(2)
+            }
+        },
+        _ if status.success() => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
 }
@@ -0,0 +1,394 @@
+//! Runs disk images in QEMU
+
+use std::{
+    ffi::OsString,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use common::qemu::{QemuExitCode, DEBUG_EXIT_PORT};
+
+pub use common::serial::MILESTONES;
+
+/// Port gdb connects to when QEMU is started with `-s`
+pub const GDB_PORT: u16 = 1234;
+
+/// How the image is run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Boot the image and wait for QEMU to be closed
+    #[default]
+    Run,
+    /// Like run, but halt on the first instruction and wait for gdb
+    Debug,
+    /// Boot headless and exit with the code the guest writes to the `isa-debug-exit` device
+    Test,
+}
+
+/// Options for a QEMU run, see [`Qemu::command`] for how they map to arguments
+#[derive(Debug, Clone)]
+pub struct Qemu {
+    mode: Mode,
+    memory: String,
+    cpus: u32,
+    vga: Option<String>,
+    nographic: bool,
+    cdrom: bool,
+}
+
+impl Default for Qemu {
+    fn default() -> Self {
+        Qemu {
+            mode: Mode::default(),
+            memory: "128M".into(),
+            cpus: 1,
+            vga: None,
+            nographic: false,
+            cdrom: false,
+        }
+    }
+}
+
+/// Result of booting an image with [`Mode::Test`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestOutcome {
+    /// The guest exited with [`QemuExitCode::Success`]
+    Passed,
+    /// The guest exited with [`QemuExitCode::Failed`]
+    Failed,
+    /// QEMU exited some other way, with the given status code if there was one
+    Crashed(Option<i32>),
+}
+
+impl From<ExitStatus> for TestOutcome {
+    fn from(status: ExitStatus) -> Self {
+        match status.code() {
+            Some(code) if code == QemuExitCode::Success.process_code() => TestOutcome::Passed,
+            Some(code) if code == QemuExitCode::Failed.process_code() => TestOutcome::Failed,
+            code => TestOutcome::Crashed(code),
+        }
+    }
+}
+
+/// Why a boot didn't reach every milestone
+#[derive(Debug)]
+pub enum BootError {
+    /// QEMU couldn't be started
+    Io(io::Error),
+    /// The milestone didn't show up in time
+    Timeout {
+        waiting_for: &'static str,
+        last_reached: Option<&'static str>,
+        timeout: Duration,
+        output: String,
+    },
+    /// QEMU exited before printing the milestone
+    Exited {
+        waiting_for: &'static str,
+        last_reached: Option<&'static str>,
+        status: ExitStatus,
+        output: String,
+    },
+}
+
+impl std::fmt::Display for BootError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (last_reached, output) = match self {
+            BootError::Io(e) => return write!(f, "failed to start qemu: {e}"),
+            BootError::Timeout {
+                waiting_for,
+                last_reached,
+                timeout,
+                output,
+            } => {
+                write!(
+                    f,
+                    "timed out after {timeout:?} waiting for \"{waiting_for}\""
+                )?;
+                (last_reached, output)
+            }
+            BootError::Exited {
+                waiting_for,
+                last_reached,
+                status,
+                output,
+            } => {
+                write!(f, "qemu exited ({status}) before \"{waiting_for}\"")?;
+                (last_reached, output)
+            }
+        };
+        match last_reached {
+            Some(milestone) => write!(f, ", last milestone reached was \"{milestone}\"")?,
+            None => write!(f, ", no milestone was reached")?,
+        }
+        write!(f, "\nserial output:\n{output}")
+    }
+}
+
+impl std::error::Error for BootError {}
+
+impl From<io::Error> for BootError {
+    fn from(e: io::Error) -> Self {
+        BootError::Io(e)
+    }
+}
+
+impl Qemu {
+    pub fn new(mode: Mode) -> Self {
+        Qemu {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    /// Sets how the image is run
+    pub fn mode(&mut self, mode: Mode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the guest memory size, in any form `-m` accepts like `256M` or `1G`
+    pub fn memory(&mut self, memory: impl Into<String>) -> &mut Self {
+        self.memory = memory.into();
+        self
+    }
+
+    /// Sets the number of CPUs
+    pub fn cpus(&mut self, cpus: u32) -> &mut Self {
+        self.cpus = cpus;
+        self
+    }
+
+    /// Sets the emulated graphics card, like `std` or `cirrus`
+    pub fn vga(&mut self, vga: impl Into<String>) -> &mut Self {
+        self.vga = Some(vga.into());
+        self
+    }
+
+    /// Runs without a window, with the serial port and QEMU monitor on the terminal
+    pub fn nographic(&mut self, nographic: bool) -> &mut Self {
+        self.nographic = nographic;
+        self
+    }
+
+    /// Boots the image as a CD instead of a hard disk, for images from
+    /// [`DiskImageBuilder::build_iso`](crate::DiskImageBuilder::build_iso)
+    pub fn cdrom(&mut self, cdrom: bool) -> &mut Self {
+        self.cdrom = cdrom;
+        self
+    }
+
+    /// Arguments passed to QEMU to boot `image`
+    pub fn args(&self, image: &Path) -> Vec<OsString> {
+        let mut drive = OsString::from("file=");
+        drive.push(image);
+        if self.cdrom {
+            drive.push(",format=raw,index=0,media=cdrom");
+        } else {
+            drive.push(",format=raw,index=0,media=disk");
+        }
+
+        let mut args: Vec<OsString> = vec!["-drive".into(), drive];
+        if self.cdrom {
+            args.extend(["-boot".into(), "d".into()]);
+        }
+        args.extend(["-m".into(), self.memory.clone().into()]);
+        args.extend(["-smp".into(), self.cpus.to_string().into()]);
+        if let Some(vga) = &self.vga {
+            args.extend(["-vga".into(), vga.into()]);
+        }
+
+        match self.mode {
+            Mode::Test => {
+                let device = format!("isa-debug-exit,iobase=0x{DEBUG_EXIT_PORT:x},iosize=0x04");
+                args.extend(["-device".into(), device.into()]);
+                args.extend(["-display".into(), "none".into()]);
+                args.extend(["-serial".into(), "stdio".into()]);
+                // A triple fault should fail the test instead of booting again forever
+                args.push("-no-reboot".into());
+            }
+            // -nographic already puts the serial port on stdio, asking for it again is an error
+            _ if self.nographic => args.push("-nographic".into()),
+            _ => args.extend(["-serial".into(), "stdio".into()]),
+        }
+
+        if self.mode == Mode::Debug {
+            args.extend(["-s".into(), "-S".into()]);
+        }
+        args
+    }
+
+    /// Command that boots `image`. Serial output goes straight to our stdout so it shows up
+    /// while the guest is running.
+    pub fn command(&self, image: &Path) -> Command {
+        let mut cmd = Command::new("qemu-system-x86_64");
+        cmd.args(self.args(image));
+        cmd
+    }
+
+    /// Boots `image` and waits for QEMU to exit
+    pub fn run(&self, image: &Path) -> io::Result<ExitStatus> {
+        self.command(image).status()
+    }
+
+    /// Boots `image` and waits for each of `milestones` to be printed on the serial port, in
+    /// order. QEMU is killed once the last one shows up or after `timeout`.
+    pub fn wait_for_milestones(
+        &self,
+        image: &Path,
+        milestones: &[&'static str],
+        timeout: Duration,
+    ) -> Result<(), BootError> {
+        let mut child = self
+            .command(image)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        // Read on another thread so waiting on a line can time out
+        let (sender, receiver) = mpsc::channel();
+        let stdout = child.stdout.take().unwrap();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).split(b'\n') {
+                let Ok(line) = line else { break };
+                if sender
+                    .send(String::from_utf8_lossy(&line).into_owned())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let deadline = Instant::now() + timeout;
+        let mut output = String::new();
+        let mut last_reached = None;
+        let mut result = Ok(());
+        'milestones: for &milestone in milestones {
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match receiver.recv_timeout(remaining) {
+                    Ok(line) => {
+                        output.push_str(line.trim_end_matches('\r'));
+                        output.push('\n');
+                        // The BIOS may leave escape codes or other junk on the same line
+                        if line.contains(milestone) {
+                            last_reached = Some(milestone);
+                            continue 'milestones;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        result = Err(BootError::Timeout {
+                            waiting_for: milestone,
+                            last_reached,
+                            timeout,
+                            output,
+                        });
+                        break 'milestones;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        result = Err(BootError::Exited {
+                            waiting_for: milestone,
+                            last_reached,
+                            status: child.wait()?,
+                            output,
+                        });
+                        break 'milestones;
+                    }
+                }
+            }
+        }
+
+        // Already exited if the guest wrote to isa-debug-exit, which is fine
+        let _ = child.kill();
+        child.wait()?;
+        result
+    }
+}
+
+#[test]
+fn test_args() {
+    let image = Path::new("disk.img");
+    let args = Qemu::new(Mode::Run).nographic(true).args(image);
+    assert!(args.contains(&"-nographic".into()));
+    assert!(!args.contains(&"-serial".into()));
+
+    let args = Qemu::new(Mode::Test).nographic(true).args(image);
+    assert!(args.contains(&"isa-debug-exit,iobase=0xf4,iosize=0x04".into()));
+    assert!(!args.contains(&"-nographic".into()));
+
+    let args = Qemu::new(Mode::Debug)
+        .memory("1G")
+        .cpus(2)
+        .vga("std")
+        .args(image);
+    let windows: Vec<_> = args.windows(2).collect();
+    for pair in [["-m", "1G"], ["-smp", "2"], ["-vga", "std"], ["-s", "-S"]] {
+        assert!(windows.contains(&&[pair[0].into(), pair[1].into()][..]));
+    }
+
+    let args = Qemu::new(Mode::Run).cdrom(true).args(image);
+    assert!(args.contains(&"file=disk.img,format=raw,index=0,media=cdrom".into()));
+    assert!(args.contains(&"d".into()));
+}
+
+/// Boots an image the same way `spenceros test` does
+#[cfg(test)]
+fn boot_test(scheme: crate::PartitionScheme) {
+    let image = crate::DiskImageBuilder::new()
+        .partition_scheme(scheme)
+        .build()
+        .unwrap();
+    boot_image(&format!("{scheme:?}"), &image, Qemu::new(Mode::Test));
+}
+
+/// Boots `image` in QEMU until every milestone shows up on the serial port.
+///
+/// The test is skipped with a message when `qemu-system-x86_64` can't be run, unless
+/// `SPENCEROS_REQUIRE_QEMU` is set, which makes a missing QEMU a failure instead.
+#[cfg(test)]
+fn boot_image(name: &str, image: &crate::DiskImage, qemu: Qemu) {
+    if let Err(e) = Command::new("qemu-system-x86_64").arg("--version").output() {
+        if std::env::var_os("SPENCEROS_REQUIRE_QEMU").is_some() {
+            panic!("Boot tests need qemu-system-x86_64: {e}");
+        }
+        eprintln!("Skipping {name} boot test, qemu-system-x86_64 can't be run: {e}");
+        return;
+    }
+
+    let path =
+        std::env::temp_dir().join(format!("spenceros-boot-{name}-{}.img", std::process::id()));
+    image.write(&path).unwrap();
+
+    let result = qemu.wait_for_milestones(&path, &MILESTONES, Duration::from_secs(60));
+    std::fs::remove_file(&path).unwrap();
+    if let Err(e) = result {
+        panic!("{e}");
+    }
+}
+
+#[test]
+fn test_boot_mbr() {
+    boot_test(crate::PartitionScheme::Mbr);
+}
+
+#[test]
+fn test_boot_gpt() {
+    boot_test(crate::PartitionScheme::Gpt);
+}
+
+#[test]
+fn test_boot_chainload() {
+    boot_test(crate::PartitionScheme::MbrChainload);
+}
+
+#[test]
+fn test_boot_iso() {
+    let image = crate::DiskImageBuilder::new().build_iso().unwrap();
+    let mut qemu = Qemu::new(Mode::Test);
+    qemu.cdrom(true);
+    boot_image("iso", &image, qemu);
+}